@@ -10,6 +10,16 @@ import { type IPage, PAGE_TYPE } from "./shared_types";
 import state from "./state";
 import UnixTime from "./UnixTime";
 
+function formatBytes(bytes: number): string {
+    const units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let i = 0;
+    while (bytes >= 1024 && i + 1 < units.length) {
+        bytes /= 1024;
+        i += 1;
+    }
+    return `${bytes.toFixed(i === 0 ? 0 : 1)} ${units[i]}`;
+}
+
 export const DockerImages = observer(function DockerImages() {
     const dockerImages = state.dockerImages;
     if (!dockerImages) return <DisplayError>Missing state.dockerImages</DisplayError>;
@@ -94,11 +104,22 @@ export const DockerImages = observer(function DockerImages() {
             );
         }
         if (!rows.length) continue;
+        const usage =
+            dockerImages.usage.state === "data" ? dockerImages.usage.data.get(project) : undefined;
+        let usageText = "";
+        if (usage) {
+            usageText = ` (${formatBytes(usage.uniqueBytes)} unique, ${formatBytes(usage.sharedBytes)} shared`;
+            if (usage.quotaBytes !== null) usageText += ` of ${formatBytes(usage.quotaBytes)} quota`;
+            usageText += ")";
+        }
         lst.push(
             <React.Fragment key={project}>
                 <thead>
                     <tr>
-                        <InfoTableHeader colSpan={10}>{project}</InfoTableHeader>
+                        <InfoTableHeader colSpan={10}>
+                            {project}
+                            {usageText}
+                        </InfoTableHeader>
                     </tr>
                     <tr>
                         <th>Tag</th>
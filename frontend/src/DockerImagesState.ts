@@ -7,6 +7,8 @@ import type {
     IDockerListImageTagHistoryRes,
     IDockerListImageTagsCharged,
     IDockerListImageTagsRes,
+    IDockerProjectUsage,
+    IDockerStorageUsageRes,
 } from "./shared_types";
 import state from "./state";
 
@@ -27,6 +29,9 @@ export default class DockerImagesState {
     @observable
     imageTagPin = new Set<string>(); // Key is image + ":" + tag
 
+    @observable
+    usage: Remote<Map<string, IDockerProjectUsage>> = { state: "initial" };
+
     load() {
         if (this.projects.state !== "initial") return;
         state.sendMessage({
@@ -34,6 +39,11 @@ export default class DockerImagesState {
             ref: 0,
         });
         this.projects = { state: "loading" };
+        state.sendMessage({
+            type: "DockerStorageUsage",
+            ref: 0,
+        });
+        this.usage = { state: "loading" };
     }
 
     @action
@@ -70,6 +80,13 @@ export default class DockerImagesState {
         if (pit != null) nullCheck(state.dockerImages).setPinnedImageTags(pit);
     }
 
+    @action
+    handleUsage(act: IDockerStorageUsageRes) {
+        const m = new Map<string, IDockerProjectUsage>();
+        for (const p of act.projects) m.set(p.project, p);
+        this.usage = { state: "data", data: m };
+    }
+
     @action
     handleLoadHistory(act: IDockerListImageTagHistoryRes) {
         const h1 = this.imageHistory.get(act.image);
@@ -161,6 +161,15 @@ export const ModifiedFiles = observer(function ModifiedFiles() {
                 <td>{type ? type.name : f.type}</td>
                 <td>{f.path}</td>
                 <td>{digest ? digest.name : f.object}</td>
+                <td>
+                    {f.drift
+                        .map((d) =>
+                            d.attribute === "content"
+                                ? "content"
+                                : `${d.attribute} ${d.deployed} → ${d.actual}`,
+                        )
+                        .join(", ")}
+                </td>
                 <td>
                     <Button
                         onClick={(e) => {
@@ -243,6 +252,7 @@ export const ModifiedFiles = observer(function ModifiedFiles() {
                         <th>Type</th>
                         <th>Path</th>
                         <th>Name</th>
+                        <th>Changed</th>
                         <th>Actions</th>
                     </tr>
                 </thead>
@@ -205,6 +205,9 @@ export const setupSocket = () => {
             case "DockerListImageTagHistoryRes":
                 nullCheck(state.dockerImages).handleLoadHistory(d);
                 break;
+            case "DockerStorageUsageRes":
+                nullCheck(state.dockerImages).handleUsage(d);
+                break;
             case "DockerListDeploymentsRes":
                 nullCheck(state.dockerContainers).handleLoad(d);
                 break;
@@ -154,6 +154,8 @@ export type KindType =
     | "docker"
     | "monitor";
 
+export type DriftPolicy = "alert" | "redeploy" | "adopt";
+
 export type IType = {
     plural?: string;
     kind?: KindType;
@@ -169,6 +171,7 @@ export type IType = {
     containsName?: string;
     content?: Array<ITypeProp>;
     nameVariable?: string;
+    driftPolicy?: DriftPolicy;
 };
 
 export type IVariable = { key: string; value: string };
@@ -325,6 +328,70 @@ export type IDeployObject = { id: number | null; redeploy: boolean; cancel: bool
 
 export type IMarkDeployed = Record<string, unknown>;
 
+export type IDeploymentPlan = { ref: Ref; id: number | null; redeploy: boolean };
+
+export type IDeploymentPlanDiff = { field: string; diff: string };
+
+export type IDeploymentPlanObject = {
+    host: number;
+    hostName: string;
+    title: string;
+    name: string;
+    typeName: string;
+    action: DEPLOYMENT_OBJECT_ACTION;
+    diffs: Array<IDeploymentPlanDiff>;
+};
+
+export type IDeploymentPlanRes = {
+    ref: Ref;
+    objects: Array<IDeploymentPlanObject>;
+    error: string | null;
+};
+
+export type IListDeploymentHistory = { ref: Ref; host: number | null; limit: number | null };
+
+export type IDeploymentHistory = {
+    id: number;
+    user: string | null;
+    startTime: number;
+    endTime: number | null;
+    markOnly: boolean;
+    message: string;
+};
+
+export type IListDeploymentHistoryRes = { ref: Ref; deployments: Array<IDeploymentHistory> };
+
+export type AuditOutcome = "started" | "success" | "failure" | "denied";
+
+export type IAuditEntry = {
+    id: number;
+    time: number;
+    user: string | null;
+    remote: string;
+    action: string;
+    host: string | null;
+    outcome: AuditOutcome;
+    details: string | null;
+};
+
+export type IListAuditLog = {
+    ref: Ref;
+    user: string | null;
+    since: number | null;
+    limit: number | null;
+};
+
+export type IListAuditLogRes = { ref: Ref; entries: Array<IAuditEntry> };
+
+export type IGetDeploymentHistory = { ref: Ref; id: number };
+
+export type IGetDeploymentHistoryRes = {
+    ref: Ref;
+    deployment: IDeploymentHistory | null;
+    objects: Array<IDeploymentObject>;
+    log: string;
+};
+
 export type IDeleteObject = { id: number };
 
 export type ISetDeploymentStatus = { status: DEPLOYMENT_STATUS };
@@ -370,6 +437,15 @@ export type IAuthStatus = {
     authDays: number | null;
     /** Seconds the client must wait before the server will accept the next login attempt. */
     rateLimitDelay: number | null;
+    /**
+     * Image projects the user may pull in addition to those granted by
+     * docker_pull. A trailing * matches any suffix
+     */
+    dockerPullProjects: Array<string>;
+    /** Image projects the user may push to in addition to those granted by docker_push */
+    dockerPushProjects: Array<string>;
+    /** Image projects the user may deploy in addition to those granted by docker_push */
+    dockerDeployProjects: Array<string>;
 };
 
 export type ILogin = { user: string; pwd: string; otp: string | null };
@@ -491,6 +567,35 @@ export type IDockerListImageByHash = { hash: Array<string>; ref: Ref };
 
 export type IDockerListImageByHashRes = { ref: Ref; tags: { [key in string]?: DockerImageTag } };
 
+export type IDockerPrunePreview = { ref: Ref; image?: string };
+
+export type IDockerPruneImage = {
+    id: number;
+    image: string;
+    tag: string;
+    hash: string;
+    missing: boolean;
+};
+
+export type IDockerPrunePreviewRes = {
+    ref: Ref;
+    images: Array<IDockerPruneImage>;
+    blobs: number;
+    bytes: number;
+};
+
+export type IDockerStorageUsage = { ref: Ref };
+
+export type IDockerProjectUsage = {
+    project: string;
+    images: number;
+    uniqueBytes: number;
+    sharedBytes: number;
+    quotaBytes: number | null;
+};
+
+export type IDockerStorageUsageRes = { ref: Ref; projects: Array<IDockerProjectUsage> };
+
 export type IDockerImageSetPin = { id: number; pin: boolean };
 
 export type IDockerImageTagSetPin = { image: string; tag: string; pin: boolean };
@@ -513,6 +618,14 @@ export type IDockerListImageTagHistoryRes = {
     images: Array<DockerImageTag>;
 };
 
+export type ModifiedFileAttribute = "content" | "mode" | "owner" | "group";
+
+export type IModifiedFileDrift = {
+    attribute: ModifiedFileAttribute;
+    deployed: string;
+    actual: string;
+};
+
 export type ModifiedFile = {
     id: number;
     type: number;
@@ -522,9 +635,10 @@ export type ModifiedFile = {
     actual: string;
     current: string | null;
     path: string;
+    drift: Array<IModifiedFileDrift>;
 };
 
-export type IModifiedFilesScan = Record<string, unknown>;
+export type IModifiedFilesScan = { host?: number };
 
 export type IModifiedFilesList = Record<string, unknown>;
 
@@ -567,9 +681,15 @@ export type IServerAction =
     | ({ type: "DockerDeployEnd" } & IDockerDeployEnd)
     | ({ type: "DockerDeployLog" } & IDockerDeployLog)
     | ({ type: "DockerDeploymentsChanged" } & IDockerDeploymentsChanged)
+    | ({ type: "DeploymentPlanRes" } & IDeploymentPlanRes)
+    | ({ type: "GetDeploymentHistoryRes" } & IGetDeploymentHistoryRes)
+    | ({ type: "ListDeploymentHistoryRes" } & IListDeploymentHistoryRes)
+    | ({ type: "ListAuditLogRes" } & IListAuditLogRes)
     | ({ type: "DockerListDeploymentHistoryRes" } & IDockerListDeploymentHistoryRes)
     | ({ type: "DockerListDeploymentsRes" } & IDockerListDeploymentsRes)
     | ({ type: "DockerListImageByHashRes" } & IDockerListImageByHashRes)
+    | ({ type: "DockerPrunePreviewRes" } & IDockerPrunePreviewRes)
+    | ({ type: "DockerStorageUsageRes" } & IDockerStorageUsageRes)
     | ({ type: "DockerListImageTagHistoryRes" } & IDockerListImageTagHistoryRes)
     | ({ type: "DockerListImageTagsChanged" } & IDockerListImageTagsCharged)
     | ({ type: "DockerListImageTagsRes" } & IDockerListImageTagsRes)
@@ -604,6 +724,10 @@ export type IClientAction =
     | ({ type: "Debug" } & IDebug)
     | ({ type: "DeleteObject" } & IDeleteObject)
     | ({ type: "DeployObject" } & IDeployObject)
+    | ({ type: "DeploymentPlan" } & IDeploymentPlan)
+    | ({ type: "GetDeploymentHistory" } & IGetDeploymentHistory)
+    | ({ type: "ListDeploymentHistory" } & IListDeploymentHistory)
+    | ({ type: "ListAuditLog" } & IListAuditLog)
     | ({ type: "MarkDeployed" } & IMarkDeployed)
     | ({ type: "DockerContainerForget" } & IDockerContainerForget)
     | ({ type: "DockerImageSetPin" } & IDockerImageSetPin)
@@ -611,6 +735,8 @@ export type IClientAction =
     | ({ type: "DockerListDeploymentHistory" } & IDockerListDeploymentHistory)
     | ({ type: "DockerListDeployments" } & IDockerListDeployments)
     | ({ type: "DockerListImageByHash" } & IDockerListImageByHash)
+    | ({ type: "DockerPrunePreview" } & IDockerPrunePreview)
+    | ({ type: "DockerStorageUsage" } & IDockerStorageUsage)
     | ({ type: "DockerListImageTagHistory" } & IDockerListImageTagHistory)
     | ({ type: "DockerListImageTags" } & IDockerListImageTags)
     | ({ type: "FetchObject" } & IFetchObject)
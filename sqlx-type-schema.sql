@@ -42,6 +42,32 @@ CREATE TABLE IF NOT EXISTS `deployments` (
 ) STRICT;
 CREATE UNIQUE INDEX IF NOT EXISTS `deployments_host_name` ON `deployments` (host, name);
 
+CREATE TABLE IF NOT EXISTS `deployment_history` (
+    `id` INTEGER NOT NULL PRIMARY KEY,
+    `user` TEXT,
+    `startTime` REAL NOT NULL,
+    `endTime` REAL,
+    `markOnly` BOOLEAN NOT NULL,
+    `message` TEXT NOT NULL,
+    `objects` TEXT NOT NULL,
+    `log` TEXT NOT NULL) STRICT;
+
+CREATE TABLE IF NOT EXISTS `deployment_history_hosts` (
+    `deployment` INTEGER NOT NULL,
+    `host` INTEGER NOT NULL) STRICT;
+CREATE INDEX IF NOT EXISTS `deployment_history_hosts_host` ON `deployment_history_hosts` (`host`, `deployment`);
+
+CREATE TABLE IF NOT EXISTS `audit_log` (
+    `id` INTEGER NOT NULL PRIMARY KEY,
+    `time` REAL NOT NULL,
+    `user` TEXT,
+    `remote` TEXT NOT NULL,
+    `action` TEXT NOT NULL,
+    `host` TEXT,
+    `outcome` TEXT NOT NULL,
+    `details` TEXT) STRICT;
+CREATE INDEX IF NOT EXISTS `audit_log_time` ON `audit_log` (`time`);
+
 CREATE TABLE IF NOT EXISTS `docker_images` (
     `id` INTEGER NOT NULL PRIMARY KEY,
     `project` TEXT NOT NULL,
@@ -54,7 +80,9 @@ CREATE TABLE IF NOT EXISTS `docker_images` (
     `labels` TEXT,
     `removed` REAL,
     `used` REAL,
-    `content_type` TEXT
+    `content_type` TEXT,
+    `subject` TEXT,
+    `artifact_type` TEXT
 ) STRICT;
 
       
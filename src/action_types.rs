@@ -518,6 +518,153 @@ pub struct IDeployObject {
 #[derive(Serialize, Deserialize, Clone, Debug, TS)]
 pub struct IMarkDeployed {}
 
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IDeploymentPlan {
+    pub r#ref: Ref,
+    pub id: Option<i64>,
+    pub redeploy: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IDeploymentPlanDiff {
+    pub field: String,
+    // Unified diff between the deployed and the new value of the field
+    pub diff: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IDeploymentPlanObject {
+    pub host: i64,
+    pub host_name: String,
+    pub title: String,
+    pub name: String,
+    pub type_name: String,
+    pub action: DeploymentObjectAction,
+    pub diffs: Vec<IDeploymentPlanDiff>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IDeploymentPlanRes {
+    pub r#ref: Ref,
+    pub objects: Vec<IDeploymentPlanObject>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IListDeploymentHistory {
+    pub r#ref: Ref,
+    // Only list deployments that touched this host
+    pub host: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IDeploymentHistory {
+    pub id: i64,
+    pub user: Option<String>,
+    pub start_time: FiniteF64,
+    pub end_time: Option<FiniteF64>,
+    pub mark_only: bool,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IListDeploymentHistoryRes {
+    pub r#ref: Ref,
+    pub deployments: Vec<IDeploymentHistory>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum AuditOutcome {
+    /// The action has started but not yet completed
+    Started,
+    Success,
+    Failure,
+    Denied,
+}
+
+impl AuditOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditOutcome::Started => "started",
+            AuditOutcome::Success => "success",
+            AuditOutcome::Failure => "failure",
+            AuditOutcome::Denied => "denied",
+        }
+    }
+}
+
+impl std::str::FromStr for AuditOutcome {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "started" => Ok(AuditOutcome::Started),
+            "success" => Ok(AuditOutcome::Success),
+            "failure" => Ok(AuditOutcome::Failure),
+            "denied" => Ok(AuditOutcome::Denied),
+            _ => bail!("Unknown audit outcome {s}"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IAuditEntry {
+    pub id: i64,
+    pub time: FiniteF64,
+    pub user: Option<String>,
+    // The ip address the action was performed from
+    pub remote: String,
+    pub action: String,
+    // The name of the host targeted by the action if any
+    pub host: Option<String>,
+    pub outcome: AuditOutcome,
+    pub details: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IListAuditLog {
+    pub r#ref: Ref,
+    // Only list actions performed by this user
+    pub user: Option<String>,
+    // Only list actions performed after this unix time
+    pub since: Option<f64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IListAuditLogRes {
+    pub r#ref: Ref,
+    pub entries: Vec<IAuditEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IGetDeploymentHistory {
+    pub r#ref: Ref,
+    pub id: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IGetDeploymentHistoryRes {
+    pub r#ref: Ref,
+    pub deployment: Option<IDeploymentHistory>,
+    pub objects: Vec<IDeploymentObject>,
+    pub log: String,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, TS)]
 #[serde(rename_all = "camelCase")]
 pub struct IDeleteObject {
@@ -644,6 +791,51 @@ pub struct IAuthStatus {
     /// accepted (set when IP-based backoff is active, otherwise None/0).
     #[serde(default)]
     pub rate_limit_delay: Option<u32>,
+    /// Image projects the user may pull in addition to those granted by
+    /// docker_pull. A trailing * matches any suffix
+    #[serde(default)]
+    pub docker_pull_projects: Vec<String>,
+    /// Image projects the user may push to in addition to those granted by docker_push
+    #[serde(default)]
+    pub docker_push_projects: Vec<String>,
+    /// Image projects the user may deploy in addition to those granted by docker_push
+    #[serde(default)]
+    pub docker_deploy_projects: Vec<String>,
+}
+
+fn project_matches(patterns: &[String], project: &str) -> bool {
+    patterns.iter().any(|p| match p.strip_suffix('*') {
+        Some(prefix) => project.starts_with(prefix),
+        None => p == project,
+    })
+}
+
+impl IAuthStatus {
+    /// May the user pull images of the project
+    pub fn can_docker_pull(&self, project: &str) -> bool {
+        self.docker_pull
+            || project_matches(&self.docker_pull_projects, project)
+            || self.can_docker_push(project)
+            || self.can_docker_deploy(project)
+    }
+
+    /// May the user push images to the project
+    pub fn can_docker_push(&self, project: &str) -> bool {
+        self.docker_push || project_matches(&self.docker_push_projects, project)
+    }
+
+    /// May the user deploy services using images of the project
+    pub fn can_docker_deploy(&self, project: &str) -> bool {
+        self.docker_push || project_matches(&self.docker_deploy_projects, project)
+    }
+
+    /// May the user pull images of at least some project
+    pub fn can_docker_pull_any(&self) -> bool {
+        self.docker_pull
+            || !self.docker_pull_projects.is_empty()
+            || !self.docker_push_projects.is_empty()
+            || !self.docker_deploy_projects.is_empty()
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, TS)]
@@ -973,6 +1165,65 @@ pub struct IDockerListImageByHashRes {
     pub tags: HashMap<String, DockerImageTag>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IDockerPrunePreview {
+    pub r#ref: Ref,
+    // Only report images of this project
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub image: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IDockerPruneImage {
+    pub id: i64,
+    pub image: String,
+    pub tag: String,
+    pub hash: String,
+    // The image is removed because some of its blobs are missing
+    pub missing: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IDockerPrunePreviewRes {
+    pub r#ref: Ref,
+    // The images the next prune would remove
+    pub images: Vec<IDockerPruneImage>,
+    // The number of blobs the next prune would delete
+    pub blobs: i64,
+    // The number of bytes freed by deleting the blobs
+    pub bytes: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IDockerStorageUsage {
+    pub r#ref: Ref,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IDockerProjectUsage {
+    pub project: String,
+    // The number of live images of the project
+    pub images: i64,
+    // Bytes of blobs used only by images of this project
+    pub unique_bytes: i64,
+    // Bytes of blobs also used by images of other projects
+    pub shared_bytes: i64,
+    pub quota_bytes: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, TS)]
+#[serde(rename_all = "camelCase")]
+pub struct IDockerStorageUsageRes {
+    pub r#ref: Ref,
+    pub projects: Vec<IDockerProjectUsage>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, TS)]
 #[serde(rename_all = "camelCase")]
 pub struct IDockerImageSetPin {
@@ -1021,6 +1272,25 @@ pub struct IDockerListImageTagHistoryRes {
     pub images: Vec<DockerImageTag>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, TS, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ModifiedFileAttribute {
+    Content,
+    Mode,
+    Owner,
+    Group,
+}
+
+// An attribute of a file that differs from what was deployed. For the content
+// the sha256 hashes are given
+#[derive(Serialize, Deserialize, Clone, Debug, TS, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct IModifiedFileDrift {
+    pub attribute: ModifiedFileAttribute,
+    pub deployed: String,
+    pub actual: String,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, TS)]
 #[serde(rename_all = "camelCase")]
 pub struct ModifiedFile {
@@ -1032,10 +1302,17 @@ pub struct ModifiedFile {
     pub actual: String,
     pub current: Option<String>,
     pub path: String,
+    #[serde(default)]
+    pub drift: Vec<IModifiedFileDrift>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, TS)]
-pub struct IModifiedFilesScan {}
+pub struct IModifiedFilesScan {
+    // Only scan this host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub host: Option<i64>,
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug, TS)]
 pub struct IModifiedFilesList {}
@@ -1201,9 +1478,15 @@ pub enum IServerAction {
     DockerDeployEnd(IDockerDeployEnd),
     DockerDeployLog(IDockerDeployLog),
     DockerDeploymentsChanged(IDockerDeploymentsChanged),
+    DeploymentPlanRes(IDeploymentPlanRes),
+    GetDeploymentHistoryRes(IGetDeploymentHistoryRes),
+    ListDeploymentHistoryRes(IListDeploymentHistoryRes),
+    ListAuditLogRes(IListAuditLogRes),
     DockerListDeploymentHistoryRes(IDockerListDeploymentHistoryRes),
     DockerListDeploymentsRes(IDockerListDeploymentsRes),
     DockerListImageByHashRes(IDockerListImageByHashRes),
+    DockerPrunePreviewRes(IDockerPrunePreviewRes),
+    DockerStorageUsageRes(IDockerStorageUsageRes),
     DockerListImageTagHistoryRes(IDockerListImageTagHistoryRes),
     DockerListImageTagsChanged(IDockerListImageTagsCharged),
     DockerListImageTagsRes(IDockerListImageTagsRes),
@@ -1245,9 +1528,15 @@ impl IServerAction {
             IServerAction::DockerDeployEnd(_) => "DockerDeployEnd",
             IServerAction::DockerDeployLog(_) => "DockerDeployLog",
             IServerAction::DockerDeploymentsChanged(_) => "DockerDeploymentsChanged",
+            IServerAction::DeploymentPlanRes(_) => "DeploymentPlanRes",
+            IServerAction::GetDeploymentHistoryRes(_) => "GetDeploymentHistoryRes",
+            IServerAction::ListDeploymentHistoryRes(_) => "ListDeploymentHistoryRes",
+            IServerAction::ListAuditLogRes(_) => "ListAuditLogRes",
             IServerAction::DockerListDeploymentHistoryRes(_) => "DockerListDeploymentHistoryRes",
             IServerAction::DockerListDeploymentsRes(_) => "DockerListDeploymentsRes",
             IServerAction::DockerListImageByHashRes(_) => "DockerListImageByHashRes",
+            IServerAction::DockerPrunePreviewRes(_) => "DockerPrunePreviewRes",
+            IServerAction::DockerStorageUsageRes(_) => "DockerStorageUsageRes",
             IServerAction::DockerListImageTagHistoryRes(_) => "DockerListImageTagHistoryRes",
             IServerAction::DockerListImageTagsChanged(_) => "DockerListImageTagsChanged",
             IServerAction::DockerListImageTagsRes(_) => "DockerListImageTagsRes",
@@ -1287,6 +1576,10 @@ pub enum IClientAction {
     Debug(IDebug),
     DeleteObject(IDeleteObject),
     DeployObject(IDeployObject),
+    DeploymentPlan(IDeploymentPlan),
+    GetDeploymentHistory(IGetDeploymentHistory),
+    ListDeploymentHistory(IListDeploymentHistory),
+    ListAuditLog(IListAuditLog),
     MarkDeployed(IMarkDeployed),
     DockerContainerForget(IDockerContainerForget),
     DockerImageSetPin(IDockerImageSetPin),
@@ -1294,6 +1587,8 @@ pub enum IClientAction {
     DockerListDeploymentHistory(IDockerListDeploymentHistory),
     DockerListDeployments(IDockerListDeployments),
     DockerListImageByHash(IDockerListImageByHash),
+    DockerPrunePreview(IDockerPrunePreview),
+    DockerStorageUsage(IDockerStorageUsage),
     DockerListImageTagHistory(IDockerListImageTagHistory),
     DockerListImageTags(IDockerListImageTags),
     FetchObject(IFetchObject),
@@ -1335,12 +1630,18 @@ impl IClientAction {
             IClientAction::Debug(_) => "Debug",
             IClientAction::DeleteObject(_) => "DeleteObject",
             IClientAction::DeployObject(_) => "DeployObject",
+            IClientAction::DeploymentPlan(_) => "DeploymentPlan",
+            IClientAction::GetDeploymentHistory(_) => "GetDeploymentHistory",
+            IClientAction::ListDeploymentHistory(_) => "ListDeploymentHistory",
+            IClientAction::ListAuditLog(_) => "ListAuditLog",
             IClientAction::DockerContainerForget(_) => "DockerContainerForget",
             IClientAction::DockerImageSetPin(_) => "DockerImageSetPin",
             IClientAction::DockerImageTagSetPin(_) => "DockerImageTagSetPin",
             IClientAction::DockerListDeploymentHistory(_) => "DockerListDeploymentHistory",
             IClientAction::DockerListDeployments(_) => "DockerListDeployments",
             IClientAction::DockerListImageByHash(_) => "DockerListImageByHash",
+            IClientAction::DockerPrunePreview(_) => "DockerPrunePreview",
+            IClientAction::DockerStorageUsage(_) => "DockerStorageUsage",
             IClientAction::DockerListImageTagHistory(_) => "DockerListImageTagHistory",
             IClientAction::DockerListImageTags(_) => "DockerListImageTags",
             IClientAction::FetchObject(_) => "FetchObject",
@@ -1383,6 +1684,10 @@ impl IClientAction {
             IClientAction::Debug(_) => None,
             IClientAction::DeleteObject(_) => None,
             IClientAction::DeployObject(_) => None,
+            IClientAction::DeploymentPlan(_) => None,
+            IClientAction::GetDeploymentHistory(_) => None,
+            IClientAction::ListDeploymentHistory(_) => None,
+            IClientAction::ListAuditLog(_) => None,
             IClientAction::MarkDeployed(_) => None,
             IClientAction::DockerContainerForget(_) => None,
             IClientAction::DockerImageSetPin(_) => None,
@@ -1390,6 +1695,8 @@ impl IClientAction {
             IClientAction::DockerListDeploymentHistory(_) => None,
             IClientAction::DockerListDeployments(_) => None,
             IClientAction::DockerListImageByHash(_) => None,
+            IClientAction::DockerPrunePreview(_) => None,
+            IClientAction::DockerStorageUsage(_) => None,
             IClientAction::DockerListImageTagHistory(_) => None,
             IClientAction::DockerListImageTags(_) => None,
             IClientAction::FetchObject(_) => None,
@@ -1458,6 +1765,19 @@ pub fn export_ts(config: &Config) -> Vec<String> {
         IHostUp::export_to_string(config).unwrap(),
         IDeployObject::export_to_string(config).unwrap(),
         IMarkDeployed::export_to_string(config).unwrap(),
+        IDeploymentPlan::export_to_string(config).unwrap(),
+        IDeploymentPlanDiff::export_to_string(config).unwrap(),
+        IDeploymentPlanObject::export_to_string(config).unwrap(),
+        IDeploymentPlanRes::export_to_string(config).unwrap(),
+        IListDeploymentHistory::export_to_string(config).unwrap(),
+        IDeploymentHistory::export_to_string(config).unwrap(),
+        IListDeploymentHistoryRes::export_to_string(config).unwrap(),
+        AuditOutcome::export_to_string(config).unwrap(),
+        IAuditEntry::export_to_string(config).unwrap(),
+        IListAuditLog::export_to_string(config).unwrap(),
+        IListAuditLogRes::export_to_string(config).unwrap(),
+        IGetDeploymentHistory::export_to_string(config).unwrap(),
+        IGetDeploymentHistoryRes::export_to_string(config).unwrap(),
         IDeleteObject::export_to_string(config).unwrap(),
         ISetDeploymentStatus::export_to_string(config).unwrap(),
         IResetServerState::export_to_string(config).unwrap(),
@@ -1506,12 +1826,20 @@ pub fn export_ts(config: &Config) -> Vec<String> {
         IDockerContainerForget::export_to_string(config).unwrap(),
         IDockerListImageByHash::export_to_string(config).unwrap(),
         IDockerListImageByHashRes::export_to_string(config).unwrap(),
+        IDockerPrunePreview::export_to_string(config).unwrap(),
+        IDockerPruneImage::export_to_string(config).unwrap(),
+        IDockerPrunePreviewRes::export_to_string(config).unwrap(),
+        IDockerStorageUsage::export_to_string(config).unwrap(),
+        IDockerProjectUsage::export_to_string(config).unwrap(),
+        IDockerStorageUsageRes::export_to_string(config).unwrap(),
         IDockerImageSetPin::export_to_string(config).unwrap(),
         IDockerImageTagSetPin::export_to_string(config).unwrap(),
         IDockerListDeploymentHistory::export_to_string(config).unwrap(),
         IDockerListDeploymentHistoryRes::export_to_string(config).unwrap(),
         IDockerListImageTagHistory::export_to_string(config).unwrap(),
         IDockerListImageTagHistoryRes::export_to_string(config).unwrap(),
+        ModifiedFileAttribute::export_to_string(config).unwrap(),
+        IModifiedFileDrift::export_to_string(config).unwrap(),
         ModifiedFile::export_to_string(config).unwrap(),
         IModifiedFilesScan::export_to_string(config).unwrap(),
         IModifiedFilesList::export_to_string(config).unwrap(),
@@ -1569,4 +1897,24 @@ mod tests {
             vec![&ObjectType::Id(1)]
         );
     }
+
+    #[test]
+    fn test_docker_project_permissions() {
+        let auth = IAuthStatus {
+            docker_pull_projects: vec!["pull".to_string()],
+            docker_push_projects: vec!["push/*".to_string()],
+            docker_deploy_projects: vec!["deploy".to_string()],
+            ..Default::default()
+        };
+        for (project, pull, push, deploy) in [
+            ("pull", true, false, false),
+            ("push/a", true, true, false),
+            ("deploy", true, false, true),
+            ("other", false, false, false),
+        ] {
+            assert_eq!(auth.can_docker_pull(project), pull, "pull {project}");
+            assert_eq!(auth.can_docker_push(project), push, "push {project}");
+            assert_eq!(auth.can_docker_deploy(project), deploy, "deploy {project}");
+        }
+    }
 }
@@ -0,0 +1,124 @@
+use anyhow::{Context, Result, bail};
+use sadmin2::action_types::{
+    AuditOutcome, IClientAction, IListAuditLog, IListAuditLogRes, IServerAction, Ref,
+};
+
+use crate::{
+    connection::{Config, Connection},
+    dyn_format::RelTime,
+};
+
+/// List administrative actions recorded in the audit log
+#[derive(clap::Parser)]
+pub struct Audit {
+    /// Only show actions performed by this user
+    #[clap(long)]
+    user: Option<String>,
+
+    /// Only show actions newer than this, either a unix time or an age like 30m, 12h or 7d
+    #[clap(long)]
+    since: Option<String>,
+
+    /// Show at most this many entries
+    #[clap(long, default_value_t = 100)]
+    limit: i64,
+}
+
+/// Parse the --since argument into a unix time
+fn parse_since(since: &str) -> Result<f64> {
+    if let Ok(v) = since.parse::<f64>() {
+        return Ok(v);
+    }
+    let (num, unit) = since.split_at(since.trim_end_matches(char::is_alphabetic).len());
+    let num: f64 = num
+        .parse()
+        .with_context(|| format!("Invalid --since value {since}"))?;
+    let unit = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        "w" => 604800.0,
+        _ => bail!("Invalid --since unit {unit}, expected one of s, m, h, d or w"),
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("Bad unix time")?
+        .as_secs_f64();
+    Ok(now - num * unit)
+}
+
+pub async fn audit(config: Config, args: Audit) -> Result<()> {
+    let since = args.since.as_deref().map(parse_since).transpose()?;
+    let mut c = Connection::open(config, true).await?;
+    let msg_ref = Ref::random();
+    c.send(&IClientAction::ListAuditLog(IListAuditLog {
+        r#ref: msg_ref.clone(),
+        user: args.user,
+        since,
+        limit: Some(args.limit),
+    }))
+    .await?;
+    let entries = loop {
+        match c.recv().await? {
+            IServerAction::ListAuditLogRes(IListAuditLogRes { r#ref, entries })
+                if r#ref == msg_ref =>
+            {
+                break entries;
+            }
+            _ => continue,
+        }
+    };
+    for entry in entries.into_iter().rev() {
+        let outcome = match entry.outcome {
+            AuditOutcome::Started => "\x1b[33mstarted\x1b[0m",
+            AuditOutcome::Success => "\x1b[32msuccess\x1b[0m",
+            AuditOutcome::Failure => "\x1b[31mfailure\x1b[0m",
+            AuditOutcome::Denied => "\x1b[31mdenied\x1b[0m",
+        };
+        print!(
+            "{} {} from {} {}",
+            RelTime(entry.time),
+            entry.user.as_deref().unwrap_or("-"),
+            entry.remote,
+            entry.action
+        );
+        if let Some(host) = &entry.host {
+            print!(" on {host}");
+        }
+        print!(": {outcome}");
+        if let Some(details) = &entry.details {
+            print!(" ({details})");
+        }
+        println!();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_since() {
+        assert_eq!(parse_since("1700000000").unwrap(), 1700000000.0);
+        assert_eq!(parse_since("1700000000.5").unwrap(), 1700000000.5);
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64();
+        for (since, secs) in [
+            ("30s", 30.0),
+            ("5m", 300.0),
+            ("2h", 7200.0),
+            ("1.5d", 129600.0),
+            ("1w", 604800.0),
+        ] {
+            let v = parse_since(since).unwrap();
+            assert!((now - secs - v).abs() < 5.0, "{since}: {v}");
+        }
+        for since in ["", "h", "2y", "two days", "2hh"] {
+            assert!(parse_since(since).is_err(), "{since}");
+        }
+    }
+}
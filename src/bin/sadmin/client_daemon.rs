@@ -530,6 +530,7 @@ impl Client {
 
     async fn handle_deploy_service_inner(
         self: &Arc<Self>,
+        run_token: &RunToken,
         msg: DeployServiceMessage,
     ) -> Result<ClientHostMessage> {
         let d: ServiceDescription = serde_yaml::from_str(&msg.description)
@@ -557,6 +558,7 @@ impl Client {
         });
         service
             .deploy(
+                run_token,
                 image,
                 d,
                 msg.docker_auth,
@@ -578,11 +580,11 @@ impl Client {
 
     async fn handle_deploy_service(
         self: Arc<Self>,
-        _run_token: RunToken,
+        run_token: RunToken,
         msg: DeployServiceMessage,
     ) -> Result<()> {
         let id = msg.id;
-        let m = match self.handle_deploy_service_inner(msg).await {
+        let m = match self.handle_deploy_service_inner(&run_token, msg).await {
             Ok(m) => m,
             Err(e) => {
                 error!("Error in deploy service: {e:?}");
@@ -1633,6 +1635,7 @@ impl Client {
 
                 service
                     .deploy(
+                        run_token,
                         d.image,
                         *d.config,
                         None,
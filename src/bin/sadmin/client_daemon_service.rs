@@ -2,15 +2,21 @@ use std::{
     collections::HashMap,
     fmt::Display,
     io::Write,
-    os::unix::prelude::{AsFd, AsRawFd, OpenOptionsExt, OsStrExt, OwnedFd},
+    os::unix::prelude::{AsFd, AsRawFd, MetadataExt, OpenOptionsExt, OsStrExt, OwnedFd},
     path::Path,
     process::{ExitStatus, Stdio},
-    sync::Arc,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
     time::{Duration, Instant, SystemTime},
 };
 
 use sadmin2::client_message::{ClientHostMessage, DataMessage, DataSource};
-use sadmin2::service_description::{Bind, ServiceDescription, ServiceMetrics, ServiceType};
+use sadmin2::service_description::{
+    Bind, HealthProbe, RestartPolicy, ServiceDescription, ServiceMetrics, ServiceRestart,
+    ServiceType,
+};
 
 use crate::{
     client_daemon::{self, SERVICE_ORDER},
@@ -22,7 +28,11 @@ use crate::{
 use anyhow::{Context, Result, bail};
 use base64::{Engine, prelude::BASE64_STANDARD};
 use bytes::BytesMut;
-use cgroups_rs::fs::cgroup_builder::CgroupBuilder;
+use cgroups_rs::fs::{
+    MaxValue,
+    cgroup_builder::CgroupBuilder,
+    memory::{MemController, SetMemory},
+};
 use log::{debug, error, info, warn};
 use nix::{
     fcntl::AT_FDCWD,
@@ -226,6 +236,8 @@ pub struct StatusJsonV1 {
     pub pod_name: Option<String>,
     pub image: Option<String>,
     pub run_user: String,
+    #[serde(default)]
+    pub health: Option<ServiceHealth>,
 }
 
 #[derive(Serialize)]
@@ -292,6 +304,205 @@ fn podman_user_command(user: Option<&str>) -> Result<tokio::process::Command> {
     Ok(cmd)
 }
 
+/// The cpu.max period in microseconds
+const CPU_PERIOD: u64 = 100000;
+
+/// The cpu.max quota for a cpu quota in cores, -1 for no limit
+fn cpu_max_quota(cpu_quota: Option<f64>) -> Result<i64> {
+    match cpu_quota {
+        None => Ok(-1),
+        Some(v) if v.is_finite() && v > 0.0 => Ok(((v * CPU_PERIOD as f64) as i64).max(1)),
+        Some(v) => bail!("Invalid cpu_quota {v}, it must be a positive number of cores"),
+    }
+}
+
+/// Find the value of `key` in a cgroup file of key value pairs like cpu.stat and memory.events
+fn parse_cgroup_key(content: &str, key: &str) -> Result<Option<u64>> {
+    for line in content.lines() {
+        if let Some((k, v)) = line.split_once(' ')
+            && k == key
+        {
+            return Ok(Some(
+                v.trim()
+                    .parse()
+                    .with_context(|| format!("Invalid value for {key}: {v}"))?,
+            ));
+        }
+    }
+    Ok(None)
+}
+
+/// Sum the bytes read and written over all devices in io.stat, which has a line
+/// per device like "8:0 rbytes=1 wbytes=2 rios=3 wios=4 ..."
+fn parse_io_stat(content: &str) -> Result<(u64, u64)> {
+    let (mut rbytes, mut wbytes) = (0u64, 0u64);
+    for part in content.split_ascii_whitespace() {
+        if let Some(v) = part.strip_prefix("rbytes=") {
+            rbytes += v.parse::<u64>()?;
+        } else if let Some(v) = part.strip_prefix("wbytes=") {
+            wbytes += v.parse::<u64>()?;
+        }
+    }
+    Ok((rbytes, wbytes))
+}
+
+/// The devices limited in io.max, which has a line per device like
+/// "8:16 rbps=2097152 wbps=max riops=max wiops=max"
+fn io_max_devices(content: &str) -> Vec<&str> {
+    content
+        .lines()
+        .filter_map(|line| line.split_ascii_whitespace().next())
+        .collect()
+}
+
+/// Create the cgroup of a service, applying the resource limits of the description.
+/// Limits not in the description are reset to the kernel defaults, as the cgroup
+/// may remain from an earlier instance
+fn create_service_cgroup(cgroup_name: &str, desc: &ServiceDescription) -> Result<()> {
+    let cpu_quota = cpu_max_quota(desc.cpu_quota)?;
+    let memory =
+        CgroupBuilder::new(cgroup_name)
+            .memory()
+            .memory_hard_limit(match desc.max_memory {
+                Some(v) => u64::from(v).try_into()?,
+                None => -1,
+            });
+    let cpu = memory
+        .done()
+        .cpu()
+        .shares(desc.cpu_weight.unwrap_or(100))
+        .quota(cpu_quota)
+        .period(CPU_PERIOD);
+    let pid = cpu
+        .done()
+        .pid()
+        .maximum_number_of_processes(match desc.pids_max {
+            Some(v) => MaxValue::Value(v.try_into()?),
+            None => MaxValue::Max,
+        });
+    // Lift the io limits of an earlier instance, the builder only writes the devices
+    // currently listed
+    let io_max = format!("/sys/fs/cgroup/{cgroup_name}/io.max");
+    if let Ok(content) = std::fs::read_to_string(&io_max) {
+        for device in io_max_devices(&content) {
+            std::fs::write(
+                &io_max,
+                format!("{device} rbps=max wbps=max riops=max wiops=max"),
+            )
+            .with_context(|| format!("Failed to reset io.max of {device}"))?;
+        }
+    }
+    let mut blkio = pid.done().blkio();
+    for limit in &desc.io_limits {
+        let rdev = std::fs::metadata(&limit.device)
+            .with_context(|| format!("Unable to stat io limit device {}", limit.device))?
+            .rdev();
+        let (major, minor) = (nix::sys::stat::major(rdev), nix::sys::stat::minor(rdev));
+        blkio = blkio.throttle_bps();
+        if let Some(v) = limit.read_bps {
+            blkio = blkio.read(major, minor, v.into());
+        }
+        if let Some(v) = limit.write_bps {
+            blkio = blkio.write(major, minor, v.into());
+        }
+        blkio = blkio.throttle_iops();
+        if let Some(v) = limit.read_iops {
+            blkio = blkio.read(major, minor, v);
+        }
+        if let Some(v) = limit.write_iops {
+            blkio = blkio.write(major, minor, v);
+        }
+    }
+    let cgroup = blkio
+        .done()
+        .build(Box::new(cgroups_rs::fs::hierarchies::V2::new()))
+        .context("Failed to create service cgroup")?;
+
+    // The builder maps the soft limit to memory.low and the io weight to
+    // io.bfq.weight, so set memory.high and io.weight ourselves
+    cgroup
+        .controller_of::<MemController>()
+        .context("Missing memory controller")?
+        .set_mem(SetMemory {
+            high: Some(match desc.memory_soft_limit {
+                Some(v) => MaxValue::Value(u64::from(v).try_into()?),
+                None => MaxValue::Max,
+            }),
+            ..Default::default()
+        })
+        .context("Failed to set memory.high")?;
+    // io.weight only exists with the io controller and blk-iocost, so a cleared weight
+    // is only reset when the file exists
+    let io_weight = format!("/sys/fs/cgroup/{cgroup_name}/io.weight");
+    if let Some(v) = desc.io_weight {
+        std::fs::write(&io_weight, format!("default {v}")).context("Failed to set io.weight")?;
+    } else if std::fs::exists(&io_weight)? {
+        std::fs::write(&io_weight, "default 100").context("Failed to reset io.weight")?;
+    }
+    Ok(())
+}
+
+/// Run a single health probe against a service
+async fn run_health_probe(
+    probe: HealthProbe,
+    user: Option<String>,
+    pod_name: Option<String>,
+) -> Result<()> {
+    match probe {
+        HealthProbe::Exec { command } => {
+            let mut cmd = match &pod_name {
+                Some(pod_name) => {
+                    let mut cmd = podman_user_command(user.as_deref())?;
+                    cmd.arg("exec")
+                        .arg(pod_name)
+                        .args(&command)
+                        .kill_on_drop(true);
+                    cmd
+                }
+                None => {
+                    let (program, args) = command
+                        .split_first()
+                        .context("Empty health check command")?;
+                    let mut cmd = tokio::process::Command::new(program);
+                    cmd.args(args).kill_on_drop(true);
+                    if let Some(user) = &user {
+                        let user = User::from_name(user)?
+                            .with_context(|| format!("Unknown user {user}"))?;
+                        cmd.uid(user.uid.as_raw()).gid(user.gid.as_raw());
+                    }
+                    cmd
+                }
+            };
+            let status = cmd
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .await
+                .context("Failed running health check command")?;
+            if !status.success() {
+                bail!("Health check command failed: {status}");
+            }
+        }
+        HealthProbe::Http { port, path } => {
+            let response = reqwest::get(format!(
+                "http://127.0.0.1:{port}{}",
+                path.as_deref().unwrap_or("/")
+            ))
+            .await?;
+            if !response.status().is_success() {
+                bail!("Health check returned {}", response.status());
+            }
+        }
+        HealthProbe::Tcp { port } => {
+            tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .with_context(|| format!("Unable to connect to port {port}"))?;
+        }
+    }
+    Ok(())
+}
+
 /// Append message to systemd journal
 async fn send_journal_message(
     socket: &tokio::net::UnixDatagram,
@@ -619,6 +830,67 @@ pub enum ServiceState {
     Stopped,
     Running,
     New,
+    /// The service crashed too often and will not be restarted until started again
+    Failed,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum HealthState {
+    Starting,
+    Healthy,
+    Unhealthy,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ServiceHealth {
+    pub state: HealthState,
+    /// Number of consecutive failed probes
+    pub failures: u32,
+    /// The error of the last failed probe
+    pub message: Option<String>,
+}
+
+impl ServiceHealth {
+    fn starting() -> Self {
+        ServiceHealth {
+            state: HealthState::Starting,
+            failures: 0,
+            message: None,
+        }
+    }
+
+    fn new(desc: &ServiceDescription) -> Option<Self> {
+        desc.health_check.as_ref().map(|_| Self::starting())
+    }
+
+    /// Record the result of a probe. Returns the message to log, if any, and true
+    /// if `threshold` consecutive probes have failed
+    fn record(&mut self, threshold: u32, res: Result<()>) -> (Option<String>, bool) {
+        match res {
+            Ok(()) => {
+                let changed = self.state != HealthState::Healthy;
+                self.state = HealthState::Healthy;
+                self.failures = 0;
+                self.message = None;
+                (changed.then(|| "Health check passed".to_string()), false)
+            }
+            Err(e) => {
+                self.failures += 1;
+                self.message = Some(format!("{e:#}"));
+                let unhealthy = self.failures >= threshold;
+                if unhealthy {
+                    self.state = HealthState::Unhealthy;
+                }
+                (
+                    Some(format!(
+                        "Health check failed ({}/{threshold}): {e:#}",
+                        self.failures
+                    )),
+                    unhealthy,
+                )
+            }
+        }
+    }
 }
 
 struct ServiceInstance {
@@ -654,6 +926,8 @@ struct ServiceStatus {
     deploy_user: String,
     image: Option<String>,
     pod_name: Option<String>,
+    #[serde(default)]
+    health: Option<ServiceHealth>,
 }
 
 enum DeployAction {
@@ -674,11 +948,103 @@ type ServiceTask = Arc<Task<Option<ServiceInstance>, anyhow::Error>>;
 enum ProcessServiceInstanceRes {
     Finished,
     WatchdogTimeout,
+    Unhealthy,
     Ready,
     Canceled,
     Timeout,
 }
 
+#[derive(Debug, PartialEq)]
+enum SoakDecision {
+    Healthy,
+    Wait,
+    Failed(&'static str),
+}
+
+/// Decide how the soak of a new instance continues after supervising it until a deadline.
+/// `health` is the health of the instance if it has a health check, and `at_limit` is true
+/// when the soak may not be extended further
+fn soak_decision(
+    res: ProcessServiceInstanceRes,
+    health: Option<HealthState>,
+    at_limit: bool,
+) -> SoakDecision {
+    match res {
+        ProcessServiceInstanceRes::Timeout => match health {
+            None | Some(HealthState::Healthy) => SoakDecision::Healthy,
+            Some(HealthState::Starting | HealthState::Unhealthy) if at_limit => {
+                SoakDecision::Failed("The new instance did not become healthy in time")
+            }
+            Some(HealthState::Starting | HealthState::Unhealthy) => SoakDecision::Wait,
+        },
+        ProcessServiceInstanceRes::Finished => {
+            SoakDecision::Failed("The new instance exited during the soak period")
+        }
+        ProcessServiceInstanceRes::Unhealthy => {
+            SoakDecision::Failed("The new instance failed its health checks")
+        }
+        ProcessServiceInstanceRes::WatchdogTimeout => {
+            SoakDecision::Failed("The new instance timed out waiting for watchdog")
+        }
+        ProcessServiceInstanceRes::Canceled => {
+            SoakDecision::Failed("The deployment was canceled during the soak period")
+        }
+        ProcessServiceInstanceRes::Ready => SoakDecision::Failed("Logic error in soak"),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum RestartDecision {
+    Restart(Duration),
+    Stop,
+    CrashLoop,
+}
+
+/// Track the restarts of a service to compute the backoff and detect crash loops
+#[derive(Default)]
+struct RestartTracker {
+    restarts: std::collections::VecDeque<Instant>,
+    failures: u32,
+}
+
+impl RestartTracker {
+    /// Decide what to do after the service exited, or failed to start, at `now`. `uptime`
+    /// is how long the instance ran, a failure after running for longer than the maximal
+    /// backoff starts a new series of consecutive failures
+    fn next(
+        &mut self,
+        restart: &ServiceRestart,
+        failed: bool,
+        uptime: Duration,
+        now: Instant,
+    ) -> RestartDecision {
+        match restart.policy {
+            RestartPolicy::Never => return RestartDecision::Stop,
+            RestartPolicy::OnFailure if !failed => return RestartDecision::Stop,
+            RestartPolicy::Always | RestartPolicy::OnFailure => (),
+        }
+        if uptime >= restart.get_backoff_max().into() {
+            self.failures = 0;
+        }
+        self.failures = self.failures.saturating_add(1);
+        if let Some(max_restarts) = restart.max_restarts {
+            let window: Duration = restart.get_restart_window().into();
+            while self
+                .restarts
+                .front()
+                .is_some_and(|t| now.duration_since(*t) > window)
+            {
+                self.restarts.pop_front();
+            }
+            if self.restarts.len() >= max_restarts as usize {
+                return RestartDecision::CrashLoop;
+            }
+            self.restarts.push_back(now);
+        }
+        RestartDecision::Restart(restart.backoff(self.failures))
+    }
+}
+
 enum StopState {
     Sent15,
     Sent9,
@@ -812,7 +1178,9 @@ impl Stop {
                 )
                 .await?
             {
-                ProcessServiceInstanceRes::Timeout | ProcessServiceInstanceRes::WatchdogTimeout => {
+                ProcessServiceInstanceRes::Timeout
+                | ProcessServiceInstanceRes::WatchdogTimeout
+                | ProcessServiceInstanceRes::Unhealthy => {
                     if our_timeout {
                         return Ok(false);
                     }
@@ -891,6 +1259,8 @@ pub struct Service {
     client: Arc<client_daemon::Client>,
     run_task: std::sync::Mutex<Option<ServiceTask>>,
     status: std::sync::Mutex<ServiceStatus>,
+    /// Number of times the service has been restarted since the daemon started
+    restarts: AtomicU64,
 }
 
 impl Service {
@@ -991,6 +1361,7 @@ impl Service {
         mut instance: Option<ServiceInstance>,
         task_id: usize,
     ) -> Result<Option<ServiceInstance>> {
+        let mut restarts = RestartTracker::default();
         while !run_token.is_cancelled() {
             let ins = match &mut instance {
                 Some(v) => v,
@@ -1019,24 +1390,18 @@ impl Service {
                     {
                         Ok(v) => v,
                         Err(e) => {
-                            const SLEEP_TIME: u64 = 5;
-                            error!(
-                                "Failed starting service {}: {:?}. Will retry in {} secs",
-                                self.name, e, SLEEP_TIME
-                            );
-                            log.stdout(
-                                format!(
-                                    "Failed starting service: {e:?}. Will retry in {SLEEP_TIME} secs\n"
+                            error!("Failed starting service {}: {:?}", self.name, e);
+                            log.stdout(format!("Failed starting service: {e:?}\n").as_bytes())
+                                .await?;
+                            if !self
+                                .wait_for_restart(
+                                    &run_token,
+                                    &mut restarts,
+                                    true,
+                                    Duration::ZERO,
+                                    &mut log,
                                 )
-                                .as_bytes(),
-                            )
-                            .await?;
-                            if cancelable(
-                                &run_token,
-                                tokio::time::sleep(std::time::Duration::from_secs(SLEEP_TIME)),
-                            )
-                            .await
-                            .is_err()
+                                .await?
                             {
                                 break;
                             }
@@ -1068,74 +1433,69 @@ impl Service {
                     bail!("Logic error")
                 }
                 Ok(ProcessServiceInstanceRes::Finished) => {
-                    error!("Service {} stopped unexpectedly", self.name);
-                    let instance_id = self.status.lock().unwrap().instance_id;
+                    let code = ins.code;
+                    error!("Service {} stopped unexpectedly: {:?}", self.name, code);
+                    let (instance_id, uptime) = {
+                        let status = self.status.lock().unwrap();
+                        (
+                            status.instance_id,
+                            status.start_stop_time.elapsed().unwrap_or_default(),
+                        )
+                    };
                     let mut log = RemoteLogTarget::Unit {
                         name: self.name.clone(),
                         instance_id,
                         socket: &self.client.journal_socket,
                     };
                     instance = None;
-                    log.stdout(b"Serivce stop unexpectedly").await?;
+                    log.stdout(b"Serivce stop unexpectedly\n").await?;
                     self.cleanup_instance(instance_id).await?;
-                }
-                Ok(ProcessServiceInstanceRes::WatchdogTimeout) => {
-                    error!("Service {} timeout waiting for watchdog", self.name);
-                    let (instance_id, process_key, user, podname) = {
-                        let status = self.status.lock().unwrap();
-                        (
-                            status.instance_id,
-                            status.process_key.clone(),
-                            status.description.user.clone(),
-                            status.pod_name.clone(),
+                    if !self
+                        .wait_for_restart(
+                            &run_token,
+                            &mut restarts,
+                            code != Some(0),
+                            uptime,
+                            &mut log,
                         )
+                        .await?
+                    {
+                        break;
+                    }
+                }
+                Ok(
+                    res @ (ProcessServiceInstanceRes::WatchdogTimeout
+                    | ProcessServiceInstanceRes::Unhealthy),
+                ) => {
+                    let reason = match res {
+                        ProcessServiceInstanceRes::Unhealthy => "Service is unhealthy",
+                        _ => "Timeout waiting for watchdog",
                     };
+                    error!("Service {}: {}", self.name, reason);
+                    let instance_id = self.status.lock().unwrap().instance_id;
                     let mut log = RemoteLogTarget::Unit {
                         name: self.name.clone(),
                         instance_id,
                         socket: &self.client.journal_socket,
                     };
-                    log.stdout(b"Timeout waiting for watchdog").await?;
-                    if let Some(process_key) = process_key {
-                        if let Some(podname) = podname {
-                            let status = podman_user_command(user.as_deref())?
-                                .arg("kill")
-                                .arg(podname)
-                                .status()
-                                .await?;
-                            if !status.success() {
-                                error!("Failed running podman kill {status}");
-                                log.stdout(b"Failed running podman kill").await?;
-                            }
-                        } else {
-                            self.client.persist_signal_process(process_key, 9).await?;
-                        }
-                        match self
-                            .process_service_instance(
-                                &run_token,
-                                ins,
-                                &self.status,
-                                &mut RemoteLogTarget::Null,
-                                false,
-                                false,
-                                Some(Instant::now() + Duration::from_secs(10)),
-                                None,
-                            )
-                            .await?
-                        {
-                            ProcessServiceInstanceRes::Finished
-                            | ProcessServiceInstanceRes::Canceled => (),
-                            ProcessServiceInstanceRes::WatchdogTimeout
-                            | ProcessServiceInstanceRes::Ready => {
-                                bail!("Result should not happen")
-                            }
-                            ProcessServiceInstanceRes::Timeout => {
-                                error!("Gave up waiting for process to exit")
-                            }
-                        }
-                    }
+                    log.stdout(format!("{reason}\n").as_bytes()).await?;
+                    self.kill_instance(&run_token, ins, &self.status, &mut log)
+                        .await?;
                     self.cleanup_instance(instance_id).await?;
                     instance = None;
+                    let uptime = self
+                        .status
+                        .lock()
+                        .unwrap()
+                        .start_stop_time
+                        .elapsed()
+                        .unwrap_or_default();
+                    if !self
+                        .wait_for_restart(&run_token, &mut restarts, true, uptime, &mut log)
+                        .await?
+                    {
+                        break;
+                    }
                 }
                 Err(e) => {
                     let instance_id = self.status.lock().unwrap().instance_id;
@@ -1165,11 +1525,197 @@ impl Service {
         Ok(instance)
     }
 
+    /// Hard kill a service instance and wait for it to exit
+    async fn kill_instance(
+        self: &Arc<Self>,
+        run_token: &RunToken,
+        ins: &mut ServiceInstance,
+        status: &std::sync::Mutex<ServiceStatus>,
+        log: &mut RemoteLogTarget<'_>,
+    ) -> Result<()> {
+        let (process_key, user, podname) = {
+            let status = status.lock().unwrap();
+            (
+                status.process_key.clone(),
+                status.description.user.clone(),
+                status.pod_name.clone(),
+            )
+        };
+        let Some(process_key) = process_key else {
+            return Ok(());
+        };
+        if let Some(podname) = podname {
+            let status = podman_user_command(user.as_deref())?
+                .arg("kill")
+                .arg(podname)
+                .status()
+                .await?;
+            if !status.success() {
+                error!("Failed running podman kill {status}");
+                log.stdout(b"Failed running podman kill").await?;
+            }
+        } else {
+            self.client.persist_signal_process(process_key, 9).await?;
+        }
+        match self
+            .process_service_instance(
+                run_token,
+                ins,
+                status,
+                &mut RemoteLogTarget::Null,
+                false,
+                false,
+                Some(Instant::now() + Duration::from_secs(10)),
+                None,
+            )
+            .await?
+        {
+            ProcessServiceInstanceRes::Finished | ProcessServiceInstanceRes::Canceled => (),
+            ProcessServiceInstanceRes::WatchdogTimeout
+            | ProcessServiceInstanceRes::Unhealthy
+            | ProcessServiceInstanceRes::Ready => {
+                bail!("Result should not happen")
+            }
+            ProcessServiceInstanceRes::Timeout => {
+                error!("Gave up waiting for process to exit")
+            }
+        }
+        Ok(())
+    }
+
+    /// Supervise a newly started instance for the overlap soak period before the old
+    /// instance is stopped. Fails if the instance exits or does not pass its health checks.
+    /// While the instance is not yet healthy the soak is extended, by at most the time
+    /// it takes to fail the health check
+    async fn soak_instance(
+        self: &Arc<Self>,
+        run_token: &RunToken,
+        ins: &mut ServiceInstance,
+        status: &std::sync::Mutex<ServiceStatus>,
+        log: &mut RemoteLogTarget<'_>,
+    ) -> Result<()> {
+        let (soak, health_check) = {
+            let status = status.lock().unwrap();
+            (
+                status.description.overlap_soak,
+                status.description.health_check.clone(),
+            )
+        };
+        if soak.is_none() && health_check.is_none() {
+            return Ok(());
+        }
+        let soak: Duration = soak.map(Duration::from).unwrap_or_default();
+        log.stdout(
+            format!(
+                "Waiting {:.1} secs for the new instance to be healthy before stopping the old one\n",
+                soak.as_secs_f64()
+            )
+            .as_bytes(),
+        )
+        .await?;
+        // Leave room for a complete probe when extending the deadline
+        let (probe_time, extension) = match &health_check {
+            Some(c) => {
+                let interval = Duration::from(c.get_interval());
+                (
+                    interval + Duration::from(c.get_timeout()) + Duration::from_secs(1),
+                    interval * c.get_failure_threshold(),
+                )
+            }
+            None => (Duration::from_secs(1), Duration::ZERO),
+        };
+        let start = Instant::now();
+        let limit = start + soak + extension;
+        let mut deadline = start + soak;
+        loop {
+            let res = self
+                .process_service_instance(
+                    run_token,
+                    ins,
+                    status,
+                    log,
+                    false,
+                    true,
+                    Some(deadline),
+                    None,
+                )
+                .await?;
+            let health = status.lock().unwrap().health.as_ref().map(|h| h.state);
+            match soak_decision(res, health, Instant::now() >= limit) {
+                SoakDecision::Healthy => return Ok(()),
+                SoakDecision::Wait => deadline = (Instant::now() + probe_time).min(limit),
+                SoakDecision::Failed(message) => bail!("{message}"),
+            }
+        }
+    }
+
+    /// Wait before restarting the service after it exited or failed to start, as given
+    /// by its restart policy. Returns false if the service should stay down
+    async fn wait_for_restart(
+        self: &Arc<Self>,
+        run_token: &RunToken,
+        restarts: &mut RestartTracker,
+        failed: bool,
+        uptime: Duration,
+        log: &mut RemoteLogTarget<'_>,
+    ) -> Result<bool> {
+        let restart = self.status.lock().unwrap().description.get_restart();
+        let state = match restarts.next(&restart, failed, uptime, Instant::now()) {
+            RestartDecision::Restart(delay) => {
+                self.restarts.fetch_add(1, Ordering::Relaxed);
+                info!(
+                    "Restarting service {} in {:.1} secs",
+                    self.name,
+                    delay.as_secs_f64()
+                );
+                log.stdout(
+                    format!("Restarting service in {:.1} secs\n", delay.as_secs_f64()).as_bytes(),
+                )
+                .await?;
+                return Ok(cancelable(run_token, tokio::time::sleep(delay))
+                    .await
+                    .is_ok());
+            }
+            RestartDecision::Stop => {
+                info!("Service {} will not be restarted", self.name);
+                log.stdout(b"Service will not be restarted\n").await?;
+                ServiceState::Stopped
+            }
+            RestartDecision::CrashLoop => {
+                let message = format!(
+                    "Service {} was restarted {} times within {:?}, giving up",
+                    self.name,
+                    restart.max_restarts.unwrap_or_default(),
+                    Duration::from(restart.get_restart_window())
+                );
+                error!("{message}");
+                log.stdout(format!("{message}\n").as_bytes()).await?;
+                let client = self.client.clone();
+                let name = self.name.clone();
+                tokio::spawn(async move {
+                    client
+                        .send_message(ClientHostMessage::ServiceFailed { name, message })
+                        .await
+                });
+                ServiceState::Failed
+            }
+        };
+        // The service stays enabled, so it is started again when the daemon restarts
+        {
+            let mut status = self.status.lock().unwrap();
+            status.state = state;
+            status.start_stop_time = SystemTime::now();
+        }
+        self.persist_status()?;
+        Ok(false)
+    }
+
     pub fn new(client: Arc<client_daemon::Client>, name: String) -> Self {
         Self {
             name: name.clone(),
             client,
             run_task: Default::default(),
+            restarts: Default::default(),
             status: std::sync::Mutex::new(ServiceStatus {
                 status: Default::default(),
                 state: ServiceState::New,
@@ -1185,6 +1731,12 @@ impl Service {
                     pre_start: Default::default(),
                     post_start: Default::default(),
                     max_memory: Default::default(),
+                    memory_soft_limit: Default::default(),
+                    cpu_quota: Default::default(),
+                    cpu_weight: Default::default(),
+                    io_weight: Default::default(),
+                    io_limits: Default::default(),
+                    pids_max: Default::default(),
                     extract_files: Default::default(),
                     service_executable: Default::default(),
                     args: Default::default(),
@@ -1198,10 +1750,13 @@ impl Service {
                     env: Default::default(),
                     pod_env: Default::default(),
                     overlap_stop_signal: Default::default(),
+                    overlap_soak: Default::default(),
                     start_magic: Default::default(),
                     stop_signal: Default::default(),
                     metrics: Default::default(),
                     project: Default::default(),
+                    restart: Default::default(),
+                    health_check: Default::default(),
                 },
                 extra_env: Default::default(),
                 instance_id: 0,
@@ -1216,6 +1771,7 @@ impl Service {
                 deploy_user: "unset".to_string(),
                 image: None,
                 pod_name: None,
+                health: None,
             }),
         }
     }
@@ -1223,6 +1779,7 @@ impl Service {
     #[allow(clippy::too_many_arguments)]
     async fn deploy_inner(
         self: &Arc<Self>,
+        run_token: &RunToken,
         image: Option<String>,
         desc: ServiceDescription,
         docker_auth: Option<String>,
@@ -1308,7 +1865,7 @@ impl Service {
         if !desc.overlap {
             let state = self.status.lock().unwrap().state;
             match state {
-                ServiceState::Stopped | ServiceState::Stopping => (),
+                ServiceState::Stopped | ServiceState::Stopping | ServiceState::Failed => (),
                 ServiceState::Starting
                 | ServiceState::Ready
                 | ServiceState::Reloading
@@ -1448,9 +2005,24 @@ impl Service {
             .context("Failed running podman rm")?;
         }
 
-        let (instance, mut status) = self
+        let (mut instance, status) = self
             .start_instance(desc.clone(), extra_env, image, log, deploy_user)
             .await?;
+        let status = std::sync::Mutex::new(status);
+        if desc.overlap
+            && let Err(e) = self
+                .soak_instance(run_token, &mut instance, &status, log)
+                .await
+        {
+            log.stdout(format!("{e:#}, stopping the new instance\n").as_bytes())
+                .await?;
+            let instance_id = status.lock().unwrap().instance_id;
+            self.kill_instance(run_token, &mut instance, &status, log)
+                .await?;
+            self.cleanup_instance(instance_id).await?;
+            return Err(e.context("The new instance failed, the old instance was kept running"));
+        }
+        let mut status = status.into_inner().unwrap();
         std::mem::swap(&mut *self.status.lock().unwrap(), &mut status);
 
         let old_run_task = self.create_run_service_task(Some(instance));
@@ -1499,8 +2071,10 @@ It will be hard killed in {:?} if it does not stop before that. ",
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn deploy(
         self: &Arc<Self>,
+        run_token: &RunToken,
         image: Option<String>,
         desc: ServiceDescription,
         docker_auth: Option<String>,
@@ -1511,6 +2085,7 @@ It will be hard killed in {:?} if it does not stop before that. ",
         let mut actions = Vec::new();
         match self
             .deploy_inner(
+                run_token,
                 image,
                 desc,
                 docker_auth,
@@ -1577,6 +2152,39 @@ It will be hard killed in {:?} if it does not stop before that. ",
         Ok(())
     }
 
+    /// Record the result of a health probe. Returns true if the service has failed
+    /// `threshold` consecutive probes
+    async fn record_health(
+        self: &Arc<Self>,
+        status: &std::sync::Mutex<ServiceStatus>,
+        instance_id: u64,
+        threshold: u32,
+        res: Result<()>,
+    ) -> Result<bool> {
+        let (msg, unhealthy) = status
+            .lock()
+            .unwrap()
+            .health
+            .get_or_insert_with(ServiceHealth::starting)
+            .record(threshold, res);
+        if let Some(msg) = msg {
+            info!("Service {}: {}", self.name, msg);
+            send_journal_messages(
+                &self.client.journal_socket,
+                if unhealthy {
+                    Priority::Error
+                } else {
+                    Priority::Info
+                },
+                format!("{msg}\n").as_bytes(),
+                &self.name,
+                instance_id,
+            )
+            .await?;
+        }
+        Ok(unhealthy)
+    }
+
     #[allow(clippy::too_many_arguments)]
     async fn process_service_instance(
         self: &Arc<Self>,
@@ -1589,7 +2197,22 @@ It will be hard killed in {:?} if it does not stop before that. ",
         timeout: Option<Instant>,
         stop_start_magic: Option<&str>,
     ) -> Result<ProcessServiceInstanceRes> {
-        let timeout_duration = status.lock().unwrap().description.watchdog_timeout;
+        let (timeout_duration, health_check, user) = {
+            let status = status.lock().unwrap();
+            (
+                status.description.watchdog_timeout,
+                status.description.health_check.clone(),
+                status.description.user.clone(),
+            )
+        };
+        // Health checks are only run while supervising a started service
+        let health_check = health_check.filter(|_| with_watchdog_timeout);
+        let health_interval: Duration = health_check
+            .as_ref()
+            .map(|c| c.get_interval().into())
+            .unwrap_or_default();
+        let mut next_probe = Instant::now() + health_interval;
+        let mut probe: Option<futures::future::BoxFuture<'static, Result<()>>> = None;
         let mut stdout_tail = Vec::new();
         let mut stderr_tail = Vec::new();
         while i.go_stdout || i.go_stderr || i.code.is_none() {
@@ -1600,6 +2223,25 @@ It will be hard killed in {:?} if it does not stop before that. ",
                 _ = tokio::time::sleep_until(i.watchdog_timout.into()), if timeout_duration.is_some() && with_watchdog_timeout => {
                     return Ok(ProcessServiceInstanceRes::WatchdogTimeout)
                 }
+                _ = tokio::time::sleep_until(next_probe.into()), if health_check.is_some() && probe.is_none() => {
+                    if let Some(check) = &health_check {
+                        let timeout = Duration::from(check.get_timeout());
+                        let run = run_health_probe(check.probe.clone(), user.clone(), i.pod_name.clone());
+                        probe = Some(Box::pin(async move {
+                            tokio::time::timeout(timeout, run)
+                                .await
+                                .unwrap_or_else(|_| Err(anyhow::anyhow!("Timeout after {timeout:?}")))
+                        }));
+                    }
+                }
+                r = async { probe.as_mut().expect("Probe").await }, if probe.is_some() => {
+                    probe = None;
+                    next_probe = Instant::now() + health_interval;
+                    let threshold = health_check.as_ref().map(|c| c.get_failure_threshold()).unwrap_or_default();
+                    if self.record_health(status, i.instance_id, threshold, r).await? {
+                        return Ok(ProcessServiceInstanceRes::Unhealthy)
+                    }
+                }
                 _ = tokio::time::sleep_until(timeout.unwrap_or_else(Instant::now).into()), if timeout.is_some() => {
                     return Ok(ProcessServiceInstanceRes::Timeout)
                 }
@@ -1803,20 +2445,7 @@ It will be hard killed in {:?} if it does not stop before that. ",
             .build(Box::new(cgroups_rs::fs::hierarchies::V2::new()))
             .context("Failed to create sadmin cgroup")?;
         let cgroup_name = format!("sadmin/{}", desc.name);
-        if let Some(v) = desc.max_memory {
-            CgroupBuilder::new(&cgroup_name)
-                .memory()
-                .memory_hard_limit(u64::from(v).try_into()?)
-                .done()
-                .build(Box::new(cgroups_rs::fs::hierarchies::V2::new()))
-                .context("Failed to create service cgroup")?;
-        } else {
-            CgroupBuilder::new(&cgroup_name)
-                .memory()
-                .done()
-                .build(Box::new(cgroups_rs::fs::hierarchies::V2::new()))
-                .context("Failed to create service cgroup")?;
-        }
+        create_service_cgroup(&cgroup_name, &desc)?;
 
         let mut script_env = Vec::new();
         for (k, v) in extra_env {
@@ -2154,6 +2783,7 @@ It will be hard killed in {:?} if it does not stop before that. ",
             deploy_user,
             image,
             pod_name: pod_name.clone(),
+            health: ServiceHealth::new(&desc),
         });
 
         let watchdog_timout = match desc.watchdog_timeout {
@@ -2191,7 +2821,8 @@ It will be hard killed in {:?} if it does not stop before that. ",
                 .await?
             {
                 ProcessServiceInstanceRes::Canceled
-                | ProcessServiceInstanceRes::WatchdogTimeout => {
+                | ProcessServiceInstanceRes::WatchdogTimeout
+                | ProcessServiceInstanceRes::Unhealthy => {
                     bail!("Logic error in start service")
                 }
                 ProcessServiceInstanceRes::Ready => (),
@@ -2224,7 +2855,8 @@ It will be hard killed in {:?} if it does not stop before that. ",
                 .await?
             {
                 ProcessServiceInstanceRes::Canceled
-                | ProcessServiceInstanceRes::WatchdogTimeout => {
+                | ProcessServiceInstanceRes::WatchdogTimeout
+                | ProcessServiceInstanceRes::Unhealthy => {
                     bail!("Logic error in start service")
                 }
                 ProcessServiceInstanceRes::Ready => (),
@@ -2376,15 +3008,101 @@ It will be hard killed in {:?} if it does not stop before that. ",
                 status.metrics_path.clone(),
             )
         };
-        match state {
+        let running = match state {
             ServiceState::Starting
             | ServiceState::Stopping
             | ServiceState::Stopped
-            | ServiceState::New => return Ok(None),
-            ServiceState::Ready | ServiceState::Reloading | ServiceState::Running => (),
+            | ServiceState::Failed
+            | ServiceState::New => false,
+            ServiceState::Ready | ServiceState::Reloading | ServiceState::Running => true,
+        };
+        let (job, instance) = match &metrics {
+            Some(
+                ServiceMetrics::SimpleSocket { job, instance }
+                | ServiceMetrics::Http { job, instance, .. },
+            ) => (job.clone(), instance.clone()),
+            None => (
+                self.name.clone(),
+                self.client.config.hostname.clone().unwrap_or_default(),
+            ),
+        };
+        let mut res = String::new();
+        if running && let Some(metrics) = metrics {
+            match self
+                .service_metrics(metrics, metrics_path, start_time)
+                .await
+            {
+                Ok(Some(v)) => res.push_str(&v),
+                Ok(None) => (),
+                Err(e) => warn!("Failure getting metrics for {}: {}", self.name, e),
+            }
+        }
+        if let Err(e) = self.cgroup_metrics(&mut res, &job, &instance) {
+            warn!("Failure reading cgroup metrics for {}: {}", self.name, e);
         }
+        Ok((!res.is_empty()).then_some(res))
+    }
+
+    /// Read the resource usage of the service from its cgroup
+    fn cgroup_metrics(&self, res: &mut String, job: &str, instance: &str) -> Result<()> {
+        use std::fmt::Write;
+        let dir = format!("/sys/fs/cgroup/sadmin/{}", self.name);
+        let read = |file: &str| std::fs::read_to_string(format!("{dir}/{file}"));
+        let read_key =
+            |file: &str, key: &str| -> Result<Option<u64>> { parse_cgroup_key(&read(file)?, key) };
+
+        let mut values = vec![(
+            "sadmin_service_restarts_total",
+            self.restarts.load(Ordering::Relaxed).to_string(),
+        )];
+        if std::fs::exists(&dir)? {
+            if let Some(v) = read_key("cpu.stat", "usage_usec")? {
+                values.push((
+                    "sadmin_service_cpu_seconds_total",
+                    (v as f64 / 1e6).to_string(),
+                ));
+            }
+            values.push((
+                "sadmin_service_memory_bytes",
+                read("memory.current")?.trim().to_string(),
+            ));
+            // memory.peak is only available from linux 5.19
+            if let Ok(v) = read("memory.peak") {
+                values.push(("sadmin_service_memory_peak_bytes", v.trim().to_string()));
+            }
+            if let Some(v) = read_key("memory.events", "oom_kill")? {
+                values.push(("sadmin_service_oom_kills_total", v.to_string()));
+            }
+            let (rbytes, wbytes) = parse_io_stat(&read("io.stat")?)?;
+            values.push(("sadmin_service_io_read_bytes_total", rbytes.to_string()));
+            values.push(("sadmin_service_io_write_bytes_total", wbytes.to_string()));
+        }
+
+        let job = format!("\"{job}\"");
+        let instance = format!("\"{instance}\"");
+        for (name, tail) in &values {
+            writeln!(
+                res,
+                "{}",
+                MetricItem::Value {
+                    name,
+                    properties: vec![("job", &job), ("instance", &instance)],
+                    tail
+                }
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Fetch the metrics exported by the service itself
+    async fn service_metrics(
+        &self,
+        metrics: ServiceMetrics,
+        metrics_path: Option<String>,
+        start_time: SystemTime,
+    ) -> Result<Option<String>> {
         let (body, instance, job) = match metrics {
-            Some(ServiceMetrics::SimpleSocket { job, instance }) => {
+            ServiceMetrics::SimpleSocket { job, instance } => {
                 let mut socket =
                     tokio::net::UnixStream::connect(metrics_path.context("Missing metrics_path")?)
                         .await?;
@@ -2392,12 +3110,12 @@ It will be hard killed in {:?} if it does not stop before that. ",
                 socket.read_to_string(&mut body).await?;
                 (body, instance, job)
             }
-            Some(ServiceMetrics::Http {
+            ServiceMetrics::Http {
                 job,
                 instance,
                 port,
                 path,
-            }) => {
+            } => {
                 let response = reqwest::get(format!("http://127.0.0.1:{port}{path}")).await?;
                 if !response.status().is_success() {
                     error!(
@@ -2411,7 +3129,6 @@ It will be hard killed in {:?} if it does not stop before that. ",
                 let body = response.text().await?;
                 (body, instance, job)
             }
-            None => return Ok(None),
         };
 
         let body = parse_metrics(&body)?;
@@ -2525,6 +3242,13 @@ It will be hard killed in {:?} if it does not stop before that. ",
             if let Some(pod) = &status.pod_name {
                 writeln!(msg, "pod_name: {pod}")?;
             }
+            if let Some(health) = &status.health {
+                write!(msg, "health: {:?}", health.state)?;
+                if let Some(message) = &health.message {
+                    write!(msg, " ({} failures, last: {message})", health.failures)?;
+                }
+                writeln!(msg)?;
+            }
             writeln!(
                 msg,
                 "start_stop_time: {}",
@@ -2534,7 +3258,10 @@ It will be hard killed in {:?} if it does not stop before that. ",
             msg
         } else {
             let status = self.status.lock().unwrap();
-            format!("{}: {:?}\n", self.name, status.state)
+            match &status.health {
+                Some(health) => format!("{}: {:?} {:?}\n", self.name, status.state, health.state),
+                None => format!("{}: {:?}\n", self.name, status.state),
+            }
         };
 
         log.stdout(msg.as_bytes()).await?;
@@ -2559,6 +3286,7 @@ It will be hard killed in {:?} if it does not stop before that. ",
                 .as_deref()
                 .unwrap_or("root")
                 .to_string(),
+            health: status.health.clone(),
         })
     }
 }
@@ -2616,3 +3344,225 @@ impl client_daemon::Client {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sadmin2::service_description;
+
+    #[test]
+    fn test_soak_decision() {
+        use ProcessServiceInstanceRes as R;
+        // Without a health check surviving the soak is enough
+        assert_eq!(
+            soak_decision(R::Timeout, None, false),
+            SoakDecision::Healthy
+        );
+        for at_limit in [false, true] {
+            assert_eq!(
+                soak_decision(R::Timeout, Some(HealthState::Healthy), at_limit),
+                SoakDecision::Healthy
+            );
+        }
+        for state in [HealthState::Starting, HealthState::Unhealthy] {
+            assert_eq!(
+                soak_decision(R::Timeout, Some(state), false),
+                SoakDecision::Wait
+            );
+            assert!(matches!(
+                soak_decision(R::Timeout, Some(state), true),
+                SoakDecision::Failed(_)
+            ));
+        }
+        for res in [R::Finished, R::Unhealthy, R::WatchdogTimeout, R::Canceled] {
+            assert!(matches!(
+                soak_decision(res, Some(HealthState::Healthy), false),
+                SoakDecision::Failed(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_cgroup_stat_parsing() {
+        let cpu_stat = "usage_usec 2500000\nuser_usec 2000000\nsystem_usec 500000\n\
+            nr_periods 0\nnr_throttled 0\nthrottled_usec 0\n";
+        assert_eq!(
+            parse_cgroup_key(cpu_stat, "usage_usec").unwrap(),
+            Some(2500000)
+        );
+        assert_eq!(parse_cgroup_key(cpu_stat, "user").unwrap(), None);
+        assert_eq!(parse_cgroup_key("", "usage_usec").unwrap(), None);
+
+        let memory_events = "low 0\nhigh 12\nmax 3\noom 1\noom_kill 1\noom_group_kill 0\n";
+        assert_eq!(
+            parse_cgroup_key(memory_events, "oom_kill").unwrap(),
+            Some(1)
+        );
+        assert_eq!(parse_cgroup_key(memory_events, "oom").unwrap(), Some(1));
+        assert!(parse_cgroup_key("oom_kill many\n", "oom_kill").is_err());
+
+        let io_stat = "8:0 rbytes=1024 wbytes=4096 rios=2 wios=8 dbytes=0 dios=0\n\
+            259:0 rbytes=100 wbytes=0 rios=1 wios=0 dbytes=0 dios=0\n";
+        assert_eq!(parse_io_stat(io_stat).unwrap(), (1124, 4096));
+        assert_eq!(parse_io_stat("").unwrap(), (0, 0));
+        assert!(parse_io_stat("8:0 rbytes=x wbytes=1\n").is_err());
+    }
+
+    #[test]
+    fn test_io_max_devices() {
+        let io_max = "8:16 rbps=2097152 wbps=max riops=max wiops=max\n\
+            259:0 rbps=max wbps=max riops=100 wiops=max\n";
+        assert_eq!(io_max_devices(io_max), vec!["8:16", "259:0"]);
+        assert!(io_max_devices("").is_empty());
+    }
+
+    #[test]
+    fn test_cpu_max_quota() {
+        assert_eq!(cpu_max_quota(None).unwrap(), -1);
+        assert_eq!(cpu_max_quota(Some(1.5)).unwrap(), 150000);
+        assert_eq!(cpu_max_quota(Some(1e-9)).unwrap(), 1);
+        for v in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(cpu_max_quota(Some(v)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_health_threshold() {
+        let mut health = ServiceHealth::starting();
+        let (msg, unhealthy) = health.record(3, Err(anyhow::anyhow!("refused")));
+        assert_eq!(msg.as_deref(), Some("Health check failed (1/3): refused"));
+        assert!(!unhealthy);
+        assert_eq!(health.state, HealthState::Starting);
+        assert!(!health.record(3, Err(anyhow::anyhow!("refused"))).1);
+        // A passing probe resets the consecutive failures
+        let (msg, unhealthy) = health.record(3, Ok(()));
+        assert_eq!(msg.as_deref(), Some("Health check passed"));
+        assert!(!unhealthy);
+        assert_eq!(health.state, HealthState::Healthy);
+        assert_eq!(health.failures, 0);
+        assert_eq!(health.message, None);
+        // Only changes to healthy are logged
+        assert_eq!(health.record(3, Ok(())), (None, false));
+        for _ in 0..2 {
+            assert!(!health.record(3, Err(anyhow::anyhow!("timeout"))).1);
+            assert_eq!(health.state, HealthState::Healthy);
+        }
+        assert!(health.record(3, Err(anyhow::anyhow!("timeout"))).1);
+        assert_eq!(health.state, HealthState::Unhealthy);
+        assert_eq!(health.message.as_deref(), Some("timeout"));
+        assert!(health.record(3, Err(anyhow::anyhow!("timeout"))).1);
+    }
+
+    fn restart(policy: RestartPolicy, max_restarts: Option<u32>) -> ServiceRestart {
+        ServiceRestart {
+            policy,
+            backoff_base: Some(service_description::Duration::S(1.0)),
+            backoff_max: Some(service_description::Duration::S(60.0)),
+            max_restarts,
+            restart_window: Some(service_description::Duration::S(100.0)),
+        }
+    }
+
+    fn secs(v: u64) -> Duration {
+        Duration::from_secs(v)
+    }
+
+    #[test]
+    fn test_restart_policy() {
+        let now = Instant::now();
+        for (policy, failed, restarted) in [
+            (RestartPolicy::Always, true, true),
+            (RestartPolicy::Always, false, true),
+            (RestartPolicy::OnFailure, true, true),
+            (RestartPolicy::OnFailure, false, false),
+            (RestartPolicy::Never, true, false),
+            (RestartPolicy::Never, false, false),
+        ] {
+            let decision = RestartTracker::default().next(
+                &restart(policy, Some(3)),
+                failed,
+                Duration::ZERO,
+                now,
+            );
+            let expected = if restarted {
+                RestartDecision::Restart(secs(1))
+            } else {
+                RestartDecision::Stop
+            };
+            assert_eq!(decision, expected, "{policy:?} failed={failed}");
+        }
+    }
+
+    #[test]
+    fn test_restart_forever_without_max_restarts() {
+        let restart = restart(RestartPolicy::Always, None);
+        let mut tracker = RestartTracker::default();
+        let now = Instant::now();
+        let delays: Vec<_> = (0..10)
+            .map(|_| tracker.next(&restart, true, Duration::ZERO, now))
+            .collect();
+        let expected: Vec<_> = [1, 2, 4, 8, 16, 32, 60, 60, 60, 60]
+            .into_iter()
+            .map(|v| RestartDecision::Restart(secs(v)))
+            .collect();
+        assert_eq!(delays, expected);
+        assert!(tracker.restarts.is_empty());
+    }
+
+    #[test]
+    fn test_restart_uptime_resets_backoff() {
+        let restart = restart(RestartPolicy::Always, None);
+        let mut tracker = RestartTracker::default();
+        let now = Instant::now();
+        for _ in 0..4 {
+            tracker.next(&restart, true, secs(59), now);
+        }
+        assert_eq!(
+            tracker.next(&restart, true, secs(59), now),
+            RestartDecision::Restart(secs(16))
+        );
+        // Running for longer than the maximal backoff starts over
+        assert_eq!(
+            tracker.next(&restart, true, secs(60), now),
+            RestartDecision::Restart(secs(1))
+        );
+        assert_eq!(
+            tracker.next(&restart, true, Duration::ZERO, now),
+            RestartDecision::Restart(secs(2))
+        );
+    }
+
+    #[test]
+    fn test_restart_crash_loop_window() {
+        let restart = restart(RestartPolicy::Always, Some(3));
+        let mut tracker = RestartTracker::default();
+        let start = Instant::now();
+        for t in [0, 10, 20] {
+            assert!(matches!(
+                tracker.next(&restart, true, Duration::ZERO, start + secs(t)),
+                RestartDecision::Restart(_)
+            ));
+        }
+        assert_eq!(
+            tracker.next(&restart, true, Duration::ZERO, start + secs(30)),
+            RestartDecision::CrashLoop
+        );
+        // The restart at 0 falls out of the window, making room for one more
+        assert!(matches!(
+            tracker.next(&restart, true, Duration::ZERO, start + secs(101)),
+            RestartDecision::Restart(_)
+        ));
+        assert_eq!(tracker.restarts.len(), 3);
+        assert_eq!(
+            tracker.next(&restart, true, Duration::ZERO, start + secs(105)),
+            RestartDecision::CrashLoop
+        );
+        // After a quiet window all restarts are forgotten
+        for t in [300, 301, 302] {
+            assert!(matches!(
+                tracker.next(&restart, true, Duration::ZERO, start + secs(t)),
+                RestartDecision::Restart(_)
+            ));
+        }
+    }
+}
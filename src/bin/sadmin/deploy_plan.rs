@@ -0,0 +1,108 @@
+use anyhow::{Result, bail};
+use sadmin2::action_types::{
+    DeploymentObjectAction, IClientAction, IDeploymentPlan, IDeploymentPlanRes, IServerAction, Ref,
+};
+use std::io::Write;
+
+use crate::connection::{Config, Connection};
+
+/// Show what a deployment would change on each host, without deploying anything
+#[derive(clap::Parser)]
+pub struct DeployPlan {
+    /// Only plan the deployment of this object, type or host id
+    #[clap(long)]
+    id: Option<i64>,
+
+    /// Plan a full redeploy, as if nothing had been deployed before
+    #[clap(long)]
+    redeploy: bool,
+
+    /// Only show changes to this host
+    #[clap(long)]
+    host: Option<String>,
+
+    /// Do not color the diffs
+    #[clap(long)]
+    no_color: bool,
+}
+
+pub async fn deploy_plan(config: Config, args: DeployPlan) -> Result<()> {
+    let mut c = Connection::open(config, true).await?;
+    let msg_ref = Ref::random();
+    c.send(&IClientAction::DeploymentPlan(IDeploymentPlan {
+        r#ref: msg_ref.clone(),
+        id: args.id,
+        redeploy: args.redeploy,
+    }))
+    .await?;
+    let (objects, error) = loop {
+        match c.recv().await? {
+            IServerAction::DeploymentPlanRes(IDeploymentPlanRes {
+                r#ref,
+                objects,
+                error,
+            }) if r#ref == msg_ref => break (objects, error),
+            _ => continue,
+        }
+    };
+    if let Some(error) = error {
+        bail!("Unable to plan deployment: {}", error);
+    }
+
+    let (red, green, cyan, bold, reset) = if args.no_color {
+        ("", "", "", "", "")
+    } else {
+        ("\x1b[31m", "\x1b[32m", "\x1b[36m", "\x1b[1m", "\x1b[0m")
+    };
+
+    let mut stdout = std::io::stdout();
+    let mut cur_host = None;
+    let mut count = 0;
+    for object in objects {
+        if let Some(host) = &args.host
+            && host != &object.host_name
+        {
+            continue;
+        }
+        count += 1;
+        if cur_host != Some(object.host) {
+            cur_host = Some(object.host);
+            writeln!(
+                stdout,
+                "\n{bold}{:=^42}{reset}",
+                format!("> {} <", object.host_name)
+            )?;
+        }
+        let action = match object.action {
+            DeploymentObjectAction::Add => "add",
+            DeploymentObjectAction::Modify => "modify",
+            DeploymentObjectAction::Remove => "remove",
+            DeploymentObjectAction::Trigger => "trigger",
+        };
+        writeln!(
+            stdout,
+            "{bold}{action} {} ({}){reset}",
+            object.title, object.type_name
+        )?;
+        for diff in object.diffs {
+            for line in diff.diff.lines() {
+                let color = if line.starts_with("+++") || line.starts_with("---") {
+                    bold
+                } else if line.starts_with('+') {
+                    green
+                } else if line.starts_with('-') {
+                    red
+                } else if line.starts_with("@@") {
+                    cyan
+                } else {
+                    ""
+                };
+                writeln!(stdout, "{color}{line}{reset}")?;
+            }
+        }
+    }
+    if count == 0 {
+        println!("Everything up to date, nothing to deploy!");
+    }
+    Ok(())
+}
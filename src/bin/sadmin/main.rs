@@ -1,20 +1,24 @@
 use anyhow::{Context, Result, bail};
+use audit::Audit;
 use clap::Parser;
 #[cfg(feature = "daemon")]
 use client_daemon::ClientDaemon;
 use connection::{Config, Connection};
 #[cfg(feature = "daemon")]
 use debug_persist::DebugPersist;
+use deploy_plan::DeployPlan;
 use list_deployments::ListDeployments;
 use list_images::ListImages;
 #[cfg(feature = "daemon")]
 use persist_daemon::PersistDaemon;
+use prune_preview::PrunePreview;
 use sadmin2::action_types::{IClientAction, IDebug, IGetSecret, ILogout, IServerAction};
 #[cfg(feature = "daemon")]
 use service_control::Service;
 use service_deploy::{ServiceDeploy, ServiceRedeploy};
 use std::{borrow::Cow, path::PathBuf};
 use upgrade::{Setup, Upgrade};
+mod audit;
 #[cfg(feature = "daemon")]
 mod client_daemon;
 #[cfg(feature = "daemon")]
@@ -22,12 +26,14 @@ mod client_daemon_service;
 mod connection;
 #[cfg(feature = "daemon")]
 mod debug_persist;
+mod deploy_plan;
 mod dyn_format;
 mod list_deployments;
 mod list_images;
 #[cfg(feature = "daemon")]
 mod persist_daemon;
 mod port;
+mod prune_preview;
 mod run;
 #[cfg(feature = "daemon")]
 mod service_control;
@@ -96,6 +102,9 @@ enum Action {
     Setup(Setup),
     ServiceDeploy(ServiceDeploy),
     ServiceRedeploy(ServiceRedeploy),
+    DeployPlan(DeployPlan),
+    Audit(Audit),
+    PrunePreview(PrunePreview),
     #[cfg(feature = "daemon")]
     ClientDaemon(ClientDaemon),
     #[cfg(feature = "daemon")]
@@ -227,6 +236,9 @@ async fn main() -> Result<()> {
         Action::Setup(args) => upgrade::setup(args).await,
         Action::ServiceDeploy(args) => service_deploy::deploy(config, args).await,
         Action::ServiceRedeploy(args) => service_deploy::redeploy(config, args).await,
+        Action::DeployPlan(args) => deploy_plan::deploy_plan(config, args).await,
+        Action::Audit(args) => audit::audit(config, args).await,
+        Action::PrunePreview(args) => prune_preview::prune_preview(config, args).await,
         #[cfg(feature = "daemon")]
         Action::ClientDaemon(args) => client_daemon::client_daemon(config, args).await,
         #[cfg(feature = "daemon")]
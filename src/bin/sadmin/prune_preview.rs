@@ -0,0 +1,52 @@
+use anyhow::Result;
+use sadmin2::action_types::{
+    IClientAction, IDockerPrunePreview, IDockerPrunePreviewRes, IServerAction, Ref,
+};
+
+use crate::connection::{Config, Connection};
+
+/// List the docker images and blobs the next prune would remove
+#[derive(clap::Parser)]
+pub struct PrunePreview {
+    /// Only show images of this project
+    #[clap(long, short)]
+    image: Option<String>,
+}
+
+pub async fn prune_preview(config: Config, args: PrunePreview) -> Result<()> {
+    let mut c = Connection::open(config, false).await?;
+    c.prompt_auth().await?;
+    let msg_ref = Ref::random();
+    c.send(&IClientAction::DockerPrunePreview(IDockerPrunePreview {
+        r#ref: msg_ref.clone(),
+        image: args.image,
+    }))
+    .await?;
+    let res = loop {
+        match c.recv().await? {
+            IServerAction::DockerPrunePreviewRes(res) if res.r#ref == msg_ref => break res,
+            _ => continue,
+        }
+    };
+    let IDockerPrunePreviewRes {
+        mut images,
+        blobs,
+        bytes,
+        ..
+    } = res;
+    images.sort_unstable_by(|l, r| (&l.image, &l.tag, l.id).cmp(&(&r.image, &r.tag, r.id)));
+    for i in &images {
+        print!("{}:{} {}@{}", i.image, i.tag, i.image, i.hash);
+        if i.missing {
+            print!(" (missing blobs)");
+        }
+        println!();
+    }
+    println!(
+        "Would remove {} images and {} blobs freeing {:.1} MiB",
+        images.len(),
+        blobs,
+        bytes as f64 / (1024.0 * 1024.0)
+    );
+    Ok(())
+}
@@ -0,0 +1,253 @@
+use crate::{
+    action_types::{AuditOutcome, IAuditEntry},
+    state::State,
+};
+use anyhow::{Context, Result};
+use qusql_sqlx_type::{query, query_as};
+use sadmin2::{finite_float::ToFinite, type_types::HOST_ID};
+use sqlx::SqlitePool;
+
+/// An administrative action performed by a user
+pub struct AuditRecord<'a> {
+    pub user: Option<&'a str>,
+    pub remote: &'a str,
+    pub action: &'a str,
+    pub host: Option<&'a str>,
+    pub outcome: AuditOutcome,
+    pub details: Option<String>,
+}
+
+/// Append an entry to the audit log, returning its id
+pub async fn record(db: &SqlitePool, record: AuditRecord<'_>) -> Result<i64> {
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("Bad unix time")?
+        .as_secs_f64();
+    let outcome = record.outcome.as_str();
+    let id = query!(
+        "INSERT INTO `audit_log` (`time`, `user`, `remote`, `action`, `host`, `outcome`, `details`)
+        VALUES (?, ?, ?, ?, ?, ?, ?)",
+        time,
+        record.user,
+        record.remote,
+        record.action,
+        record.host,
+        outcome,
+        record.details
+    )
+    .execute(db)
+    .await
+    .context("Unable to write audit log")?
+    .last_insert_rowid();
+    Ok(id)
+}
+
+/// Store the outcome of an action recorded as started in the audit log
+pub async fn finish(
+    db: &SqlitePool,
+    id: i64,
+    outcome: AuditOutcome,
+    details: Option<String>,
+) -> Result<()> {
+    let outcome = outcome.as_str();
+    query!(
+        "UPDATE `audit_log` SET `outcome`=?, `details`=? WHERE `id`=?",
+        outcome,
+        details,
+        id
+    )
+    .execute(db)
+    .await
+    .context("Unable to write audit log")?;
+    Ok(())
+}
+
+/// Return the outcome and details to audit for the result of an action
+pub fn outcome<T, E: std::fmt::Display>(
+    r: &std::result::Result<T, E>,
+) -> (AuditOutcome, Option<String>) {
+    match r {
+        Ok(_) => (AuditOutcome::Success, None),
+        Err(e) => (AuditOutcome::Failure, Some(format!("{e:#}"))),
+    }
+}
+
+/// Look up the name of a host to record in the audit log
+pub async fn host_name(state: &State, host: i64) -> Result<String> {
+    let row = query!(
+        "SELECT `name` FROM `objects` WHERE `type`=? AND `id`=? AND `newest`",
+        HOST_ID,
+        host
+    )
+    .fetch_optional(&state.db)
+    .await?;
+    Ok(match row {
+        Some(row) => row.name,
+        None => host.to_string(),
+    })
+}
+
+struct AuditRow {
+    id: i64,
+    time: f64,
+    user: Option<String>,
+    remote: String,
+    action: String,
+    host: Option<String>,
+    outcome: String,
+    details: Option<String>,
+}
+
+/// List audit log entries, newest first
+pub async fn list(
+    db: &SqlitePool,
+    user: Option<String>,
+    since: Option<f64>,
+    limit: Option<i64>,
+) -> Result<Vec<IAuditEntry>> {
+    let since = since.unwrap_or(0.0);
+    let limit = limit.unwrap_or(100);
+    let rows = match user {
+        Some(user) => {
+            query_as!(
+                AuditRow,
+                "SELECT `id`, `time`, `user`, `remote`, `action`, `host`, `outcome`, `details`
+                FROM `audit_log` WHERE `user`=? AND `time`>=? ORDER BY `id` DESC LIMIT ?",
+                user,
+                since,
+                limit
+            )
+            .fetch_all(db)
+            .await?
+        }
+        None => {
+            query_as!(
+                AuditRow,
+                "SELECT `id`, `time`, `user`, `remote`, `action`, `host`, `outcome`, `details`
+                FROM `audit_log` WHERE `time`>=? ORDER BY `id` DESC LIMIT ?",
+                since,
+                limit
+            )
+            .fetch_all(db)
+            .await?
+        }
+    };
+    rows.into_iter()
+        .map(|row| {
+            Ok(IAuditEntry {
+                id: row.id,
+                time: row.time.to_finite()?,
+                user: row.user,
+                remote: row.remote,
+                action: row.action,
+                host: row.host,
+                outcome: row.outcome.parse()?,
+                details: row.details,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    fn started<'a>(user: &'a str, action: &'a str) -> AuditRecord<'a> {
+        AuditRecord {
+            user: Some(user),
+            remote: "127.0.0.1",
+            action,
+            host: Some("host1"),
+            outcome: AuditOutcome::Started,
+            details: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() -> Result<()> {
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await?;
+        crate::db::setup(&db).await?;
+
+        let a = record(&db, started("alice", "RunCommand")).await?;
+        finish(&db, a, AuditOutcome::Success, Some("exit 0".into())).await?;
+        let b = record(
+            &db,
+            AuditRecord {
+                outcome: AuditOutcome::Denied,
+                details: Some("Not admin".into()),
+                ..started("bob", "SocketConnect")
+            },
+        )
+        .await?;
+        let c = record(&db, started("alice", "CommandSpawn")).await?;
+
+        let entries = list(&db, None, None, None).await?;
+        let ids: Vec<_> = entries.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![c, b, a]);
+        assert_eq!(entries[0].outcome, AuditOutcome::Started);
+        assert_eq!(entries[1].outcome, AuditOutcome::Denied);
+        assert_eq!(entries[1].details.as_deref(), Some("Not admin"));
+        assert_eq!(entries[2].outcome, AuditOutcome::Success);
+        assert_eq!(entries[2].details.as_deref(), Some("exit 0"));
+        assert_eq!(entries[2].action, "RunCommand");
+        assert_eq!(entries[2].host.as_deref(), Some("host1"));
+
+        let ids = |v: Vec<IAuditEntry>| v.into_iter().map(|e| e.id).collect::<Vec<_>>();
+        assert_eq!(
+            ids(list(&db, Some("alice".into()), None, None).await?),
+            vec![c, a]
+        );
+        assert_eq!(ids(list(&db, None, None, Some(2)).await?), vec![c, b]);
+        assert_eq!(
+            ids(list(&db, None, Some(entries[0].time.into()), None).await?),
+            vec![c]
+        );
+        assert!(ids(list(&db, Some("carol".into()), None, None).await?).is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_append_only() -> Result<()> {
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await?;
+        crate::db::setup(&db).await?;
+
+        let a = record(&db, started("alice", "RunCommand")).await?;
+        // Only the outcome and details of a started action may be filled in
+        assert!(
+            query!("UPDATE `audit_log` SET `user`=? WHERE `id`=?", "bob", a)
+                .execute(&db)
+                .await
+                .is_err()
+        );
+        finish(&db, a, AuditOutcome::Failure, Some("exit 1".into())).await?;
+        // Finished rows are final
+        assert!(finish(&db, a, AuditOutcome::Success, None).await.is_err());
+        assert!(
+            query!("UPDATE `audit_log` SET `details`=? WHERE `id`=?", "", a)
+                .execute(&db)
+                .await
+                .is_err()
+        );
+        let b = record(&db, started("alice", "CommandSpawn")).await?;
+        for id in [a, b] {
+            assert!(
+                query!("DELETE FROM `audit_log` WHERE `id`=?", id)
+                    .execute(&db)
+                    .await
+                    .is_err()
+            );
+        }
+        let entries = list(&db, None, None, None).await?;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].outcome, AuditOutcome::Failure);
+        assert_eq!(entries[1].details.as_deref(), Some("exit 1"));
+        Ok(())
+    }
+}
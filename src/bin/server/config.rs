@@ -9,6 +9,259 @@ pub struct ConfigUser {
     pub password: String,
 }
 
+fn default_deploy_parallelism() -> usize {
+    1
+}
+
+fn default_rollout_pause_seconds() -> u64 {
+    60
+}
+
+/// Policy for deployments rolled out in waves, see the `rolloutWave` host field
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RolloutConfig {
+    /// Seconds to wait after a wave before checking its health and continuing
+    #[serde(default = "default_rollout_pause_seconds")]
+    pub pause_seconds: u64,
+    /// Abort the remaining waves if more than this fraction of a wave failed
+    #[serde(default)]
+    pub max_failure_ratio: f64,
+}
+
+impl Default for RolloutConfig {
+    fn default() -> Self {
+        Self {
+            pause_seconds: default_rollout_pause_seconds(),
+            max_failure_ratio: 0.0,
+        }
+    }
+}
+
+fn default_scan_initial_delay_seconds() -> u64 {
+    2 * 60
+}
+
+fn default_scan_interval_seconds() -> u64 {
+    12 * 60 * 60
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// When to scan hosts for modified files
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModifiedFilesScanConfig {
+    /// Seconds after startup before the first scan of all hosts
+    #[serde(default = "default_scan_initial_delay_seconds")]
+    pub initial_delay_seconds: u64,
+    /// Seconds between scans of all hosts
+    #[serde(default = "default_scan_interval_seconds")]
+    pub interval_seconds: u64,
+    /// Scan a host when it reconnects
+    #[serde(default = "default_true")]
+    pub on_reconnect: bool,
+}
+
+impl Default for ModifiedFilesScanConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay_seconds: default_scan_initial_delay_seconds(),
+            interval_seconds: default_scan_interval_seconds(),
+            on_reconnect: true,
+        }
+    }
+}
+
+/// A deployment started automatically by the scheduler
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledDeployment {
+    pub name: String,
+    /// Cron expression (with seconds) in the local time of the server, e.g. "0 0 2 * * *"
+    #[serde(default)]
+    pub cron: Option<String>,
+    /// Deploy once at this RFC 3339 time
+    #[serde(default)]
+    pub at: Option<String>,
+    /// Only deploy objects on these hosts
+    #[serde(default)]
+    pub hosts: Vec<i64>,
+    /// Only deploy these objects
+    #[serde(default)]
+    pub objects: Vec<i64>,
+    #[serde(default)]
+    pub redeploy: bool,
+}
+
+/// Require images of a project to be signed before they can be deployed as services
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignaturePolicy {
+    /// The project the policy applies to, a trailing * matches any suffix
+    pub project: String,
+    /// Names of root variables holding PEM encoded public keys trusted to sign the images
+    pub keys: Vec<String>,
+}
+
+/// Match a project or tag against a pattern, where a trailing * matches any suffix
+fn pattern_matches(pattern: &str, v: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => v.starts_with(prefix),
+        None => pattern == v,
+    }
+}
+
+impl SignaturePolicy {
+    pub fn matches(&self, project: &str) -> bool {
+        pattern_matches(&self.project, project)
+    }
+}
+
+/// Tags of a project that may not be moved to another image once pushed
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImmutableTagPolicy {
+    /// The project the policy applies to, a trailing * matches any suffix
+    pub project: String,
+    /// The immutable tags, e.g. "v*" or "release-*". A trailing * matches any suffix
+    pub tags: Vec<String>,
+}
+
+impl ImmutableTagPolicy {
+    pub fn matches(&self, project: &str, tag: &str) -> bool {
+        pattern_matches(&self.project, project) && self.tags.iter().any(|p| pattern_matches(p, tag))
+    }
+}
+
+/// Limit the blob storage used by the live images of a project
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageQuota {
+    /// The project the quota applies to, a trailing * matches any suffix. The
+    /// quota applies to each matching project on its own
+    pub project: String,
+    pub max_bytes: u64,
+}
+
+impl StorageQuota {
+    pub fn matches(&self, project: &str) -> bool {
+        pattern_matches(&self.project, project)
+    }
+}
+
+fn default_retention_project() -> String {
+    "*".to_string()
+}
+
+fn default_retention_tags() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+fn default_retention_max_age_hours() -> f64 {
+    14.0 * 24.0
+}
+
+/// How long the images of a project are kept before they are pruned. Pinned
+/// images and images of running deployments are always kept
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionRule {
+    /// The projects the rule applies to, a trailing * matches any suffix
+    #[serde(default = "default_retention_project")]
+    pub project: String,
+    /// The tags the rule applies to, a trailing * matches any suffix
+    #[serde(default = "default_retention_tags")]
+    pub tags: Vec<String>,
+    /// Prune images older than this many hours
+    #[serde(default = "default_retention_max_age_hours")]
+    pub max_age_hours: f64,
+    /// Always keep this many of the newest images of each tag, use 1 to protect a tag
+    #[serde(default)]
+    pub keep_last: usize,
+    /// Keep images that have been deployed or pulled for twice as long as they
+    /// were in use, on top of the max age
+    #[serde(default = "default_true")]
+    pub keep_used: bool,
+}
+
+impl RetentionRule {
+    pub fn matches(&self, project: &str, tag: &str) -> bool {
+        pattern_matches(&self.project, project) && self.tags.iter().any(|p| pattern_matches(p, tag))
+    }
+}
+
+/// The rules used for images not matched by any configured retention rule
+pub fn default_retention_rules() -> Vec<RetentionRule> {
+    vec![
+        // Tmp CI images are only ever meant to live for a short while before being
+        // re-tagged and pushed again, so we prune them aggressively
+        RetentionRule {
+            project: default_retention_project(),
+            tags: vec!["tmp_ci_*".to_string()],
+            max_age_hours: 1.0,
+            keep_last: 0,
+            keep_used: false,
+        },
+        RetentionRule {
+            project: default_retention_project(),
+            tags: vec!["latest".to_string(), "master".to_string()],
+            max_age_hours: default_retention_max_age_hours(),
+            keep_last: 1,
+            keep_used: true,
+        },
+        RetentionRule {
+            project: default_retention_project(),
+            tags: default_retention_tags(),
+            max_age_hours: default_retention_max_age_hours(),
+            keep_last: 0,
+            keep_used: true,
+        },
+    ]
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+/// An S3 compatible object store holding the registry blobs
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct S3Config {
+    /// Url of the object store, e.g. "http://localhost:9000" for a local MinIO
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Prefix of the object keys in the bucket
+    #[serde(default)]
+    pub prefix: String,
+    /// Redirect clients to presigned urls valid for this many seconds when
+    /// downloading blobs, instead of proxying the blobs through the server
+    #[serde(default)]
+    pub presign_seconds: Option<u64>,
+}
+
+/// Where the registry stores blobs
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum BlobStorageConfig {
+    Fs { path: String },
+    S3(S3Config),
+}
+
+impl Default for BlobStorageConfig {
+    fn default() -> Self {
+        Self::Fs {
+            path: crate::docker::DOCKER_BLOBS_PATH.to_string(),
+        }
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
@@ -28,6 +281,31 @@ pub struct Config {
     pub vanta_users_resource: Option<String>,
     #[serde(default)]
     pub vanta_hosts_resource: Option<String>,
+    #[serde(default)]
+    pub rollout: RolloutConfig,
+    /// The maximal number of hosts to deploy objects to at the same time. Defaults to
+    /// one, deploying the hosts one after another
+    #[serde(default = "default_deploy_parallelism")]
+    pub deploy_parallelism: usize,
+    /// When an object fails to deploy, undo the changes already applied to the host
+    #[serde(default)]
+    pub rollback_on_failure: bool,
+    #[serde(default)]
+    pub scheduled_deployments: Vec<ScheduledDeployment>,
+    #[serde(default)]
+    pub modified_files_scan: ModifiedFilesScanConfig,
+    #[serde(default)]
+    pub signature_policies: Vec<SignaturePolicy>,
+    #[serde(default)]
+    pub immutable_tags: Vec<ImmutableTagPolicy>,
+    /// Retention rules for registry images, the first rule matching the project
+    /// and tag of an image applies. Falls back to the default rules
+    #[serde(default)]
+    pub retention: Vec<RetentionRule>,
+    #[serde(default)]
+    pub storage_quotas: Vec<StorageQuota>,
+    #[serde(default)]
+    pub blob_storage: BlobStorageConfig,
 }
 
 pub fn read_config() -> Result<Config> {
@@ -190,6 +190,30 @@ subjectAltName = "
     Ok(String::from_utf8(res.stdout)?)
 }
 
+/// Check that `signature` is a valid sha256 signature of `data` by the PEM encoded `public_key`
+pub async fn verify_signature(public_key: &str, data: &[u8], signature: &[u8]) -> Result<bool> {
+    let mut t1 = NamedTempFile::new()?;
+    let mut t2 = NamedTempFile::new()?;
+    let mut t3 = NamedTempFile::new()?;
+    t1.write_all(public_key.as_bytes())?;
+    t2.write_all(data)?;
+    t3.write_all(signature)?;
+    let res = tokio::process::Command::new("openssl")
+        .arg("dgst")
+        .arg("-sha256")
+        .arg("-verify")
+        .arg(t1.path())
+        .arg("-signature")
+        .arg(t3.path())
+        .arg(t2.path())
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .output()
+        .await?;
+    Ok(res.status.success())
+}
+
 pub enum Type {
     Host,
     User,
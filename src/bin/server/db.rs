@@ -44,6 +44,24 @@ pub struct UserContent {
     pub email: Option<String>,
     #[serde(default)]
     pub system: bool,
+    /// Comma or space separated image projects the user may pull, a trailing * matches any suffix
+    #[serde(default)]
+    pub docker_pull_projects: Option<String>,
+    /// Comma or space separated image projects the user may push to
+    #[serde(default)]
+    pub docker_push_projects: Option<String>,
+    /// Comma or space separated image projects the user may deploy
+    #[serde(default)]
+    pub docker_deploy_projects: Option<String>,
+}
+
+/// Split a comma or space separated list of project patterns
+pub fn project_patterns(v: Option<&str>) -> Vec<String> {
+    v.unwrap_or_default()
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+        .collect()
 }
 
 const USER_ID: i64 = 4;
@@ -83,7 +101,9 @@ pub async fn setup(db: &SqlitePool) -> Result<i64> {
     con.execute(
         "CREATE TABLE IF NOT EXISTS `messages` (`id` INTEGER PRIMARY KEY, `host` INTEGER, `type` TEXT, `subtype` TEXT, `message` TEXT, `url` TEXT, `time` INTEGER, `dismissed` INTEGER)",
     ).await?;
-    // //await i("ALTER TABLE `messages` ADD COLUMN `dismissedTime` INTEGER");
+    let _ = con
+        .execute("ALTER TABLE `messages` ADD COLUMN `dismissedTime` REAL")
+        .await;
     con.execute("CREATE INDEX IF NOT EXISTS `messagesIdx` ON `messages` (dismissed, time)")
         .await?;
     con.execute(
@@ -97,6 +117,37 @@ pub async fn setup(db: &SqlitePool) -> Result<i64> {
         "CREATE UNIQUE INDEX IF NOT EXISTS `deployments_host_name` ON `deployments` (host, name)",
     )
     .await?;
+    con.execute(
+        "CREATE TABLE IF NOT EXISTS `deployment_history` (`id` INTEGER PRIMARY KEY, `user` TEXT, `startTime` REAL, `endTime` REAL, `markOnly` INTEGER, `message` TEXT, `objects` TEXT, `log` TEXT)",
+    ).await?;
+    con.execute(
+        "CREATE TABLE IF NOT EXISTS `deployment_history_hosts` (`deployment` INTEGER, `host` INTEGER)",
+    )
+    .await?;
+    con.execute(
+        "CREATE INDEX IF NOT EXISTS `deployment_history_hosts_host` ON `deployment_history_hosts` (`host`, `deployment`)",
+    )
+    .await?;
+    con.execute(
+        "CREATE TABLE IF NOT EXISTS `audit_log` (`id` INTEGER PRIMARY KEY, `time` REAL, `user` TEXT, `remote` TEXT, `action` TEXT, `host` TEXT, `outcome` TEXT, `details` TEXT)",
+    )
+    .await?;
+    con.execute("CREATE INDEX IF NOT EXISTS `audit_log_time` ON `audit_log` (`time`)")
+        .await?;
+    // The audit log is append only, except that the outcome and details of an
+    // action recorded as started are filled in once when it completes
+    con.execute(
+        "CREATE TRIGGER IF NOT EXISTS `audit_log_only_finish` BEFORE UPDATE ON `audit_log`
+        WHEN OLD.`outcome` != 'started' OR NEW.`id` IS NOT OLD.`id` OR NEW.`time` IS NOT OLD.`time`
+        OR NEW.`user` IS NOT OLD.`user` OR NEW.`remote` IS NOT OLD.`remote`
+        OR NEW.`action` IS NOT OLD.`action` OR NEW.`host` IS NOT OLD.`host`
+        BEGIN SELECT RAISE(ABORT, 'audit_log is append only'); END",
+    )
+    .await?;
+    con.execute(
+        "CREATE TRIGGER IF NOT EXISTS `audit_log_no_delete` BEFORE DELETE ON `audit_log` BEGIN SELECT RAISE(ABORT, 'audit_log is append only'); END",
+    )
+    .await?;
     con.execute(
         "CREATE TABLE IF NOT EXISTS `installedPackages` (`id` INTEGER, `host` INTEGR, `name` TEXT)",
     )
@@ -121,9 +172,19 @@ pub async fn setup(db: &SqlitePool) -> Result<i64> {
     let _ = con
         .execute("ALTER TABLE `docker_images` ADD COLUMN `content_type` TEXT")
         .await;
+    let _ = con
+        .execute("ALTER TABLE `docker_images` ADD COLUMN `subject` TEXT")
+        .await;
+    let _ = con
+        .execute("ALTER TABLE `docker_images` ADD COLUMN `artifact_type` TEXT")
+        .await;
 
     con.execute("CREATE INDEX IF NOT EXISTS `docker_images_hash` ON `docker_images` (`hash`)")
         .await?;
+    con.execute(
+        "CREATE INDEX IF NOT EXISTS `docker_images_subject` ON `docker_images` (`subject`)",
+    )
+    .await?;
 
     con.execute(
         "CREATE TABLE IF NOT EXISTS `docker_deployments` (`id` INTEGER PRIMARY KEY, `project` TEXT, `container` TEXT, `host` INTEGER, `startTime` INTEGER, `endTime` INTEGER, `config` TEXT, `hash` TEXT, `user` INTEGER)",
@@ -211,7 +272,7 @@ pub async fn setup(db: &SqlitePool) -> Result<i64> {
     // }
 
     let id = query!("SELECT max(`id`) as `id` FROM `objects`")
-        .fetch_optional(db)
+        .fetch_optional(&mut *con)
         .await?;
     let next_object_id = i64::max(
         10000,
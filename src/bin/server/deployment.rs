@@ -1,5 +1,5 @@
 use std::borrow::Cow;
-use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
 use std::io::Write;
 use std::ops::Deref;
 use std::sync::Arc;
@@ -7,12 +7,14 @@ use std::time::Duration;
 
 use crate::action_types::{
     DeploymentObjectAction, DeploymentObjectStatus, DeploymentStatus, IAddDeploymentLog,
-    IClearDeploymentLog, IDeploymentObject, IDeploymentTrigger, IObject2, IServerAction,
-    ISetDeploymentMessage, ISetDeploymentObjectStatus, ISetDeploymentObjects, ISetDeploymentStatus,
-    ISource, IToggleDeploymentObject, ObjectRow,
+    IClearDeploymentLog, IDeploymentHistory, IDeploymentObject, IDeploymentPlanDiff,
+    IDeploymentPlanObject, IDeploymentTrigger, IObject2, IServerAction, ISetDeploymentMessage,
+    ISetDeploymentObjectStatus, ISetDeploymentObjects, ISetDeploymentStatus, ISource,
+    IToggleDeploymentObject, ObjectRow,
 };
 use crate::arena::Arena;
 use crate::cmpref::CmpRef;
+use crate::db;
 use crate::hostclient::HostClient;
 use crate::ocell::{OCell, OCellAccess};
 use crate::ordered_json::JsonCmp;
@@ -22,12 +24,13 @@ use crate::webclient;
 use anyhow::{Context, Result, anyhow, bail};
 use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
-use futures::pin_mut;
+use futures::{StreamExt, pin_mut, stream};
 use log::{error, info};
 use qusql_sqlx_type::{query, query_as};
 use sadmin2::client_message::{
     ClientHostMessage, HostClientMessage, RunScriptMessage, RunScriptOutType, RunScriptStdinType,
 };
+use sadmin2::finite_float::ToFinite;
 use sadmin2::type_types::{
     COLLECTION_ID, COMPLEX_COLLECTION_ID, HOST_ID, HOST_VARIABLE_ID, IBoolTypeProp,
     IChoiceTypeProp, IContainsIter, IDependsIter, IDocumentTypeProp, INumberTypeProp,
@@ -36,8 +39,9 @@ use sadmin2::type_types::{
 };
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use sqlx::SqlitePool;
 use tokio::select;
-use tokio_tasks::{RunToken, set_location};
+use tokio_tasks::{RunToken, cancelable, set_location};
 
 type ValueMap = serde_json::Map<String, Value>;
 
@@ -96,6 +100,13 @@ fn num_len(n: i64) -> usize {
     }
 }
 
+/// Log output of a host that is held back while another host owns the log
+struct PendingHostLog {
+    host: i64,
+    lines: Vec<String>,
+    done: bool,
+}
+
 pub struct Deployment {
     pub status: DeploymentStatus,
     pub message: String,
@@ -103,16 +114,60 @@ pub struct Deployment {
     pub log: Vec<String>,
     delayed_actions: Vec<IServerAction>,
     current_deployment_token: Option<RunToken>,
+    log_host: Option<i64>,
+    pending_host_logs: VecDeque<PendingHostLog>,
 }
 
 impl Deployment {
-    fn add_header(&mut self, name: &str, big: bool) {
+    fn add_host_header(&mut self, host: i64, name: &str, big: bool) {
         let s = if big {
             format!("\r\n\x1b[91m{:=^1$}\x1b[0m\r\n", name, 100)
         } else {
             format!("\r\n\x1b[91m{:-^1$}\x1b[0m\r\n", name, 100)
         };
-        self.add_log(s)
+        self.add_host_log(host, s)
+    }
+
+    /// Add log output of a host. When several hosts are deployed at the same time only
+    /// one of them writes to the log, the output of the others is held back until it
+    /// is done, so that the log stays grouped per host.
+    fn add_host_log(&mut self, host: i64, line: String) {
+        match self.log_host {
+            None => {
+                self.log_host = Some(host);
+                self.add_log(line);
+            }
+            Some(h) if h == host => self.add_log(line),
+            Some(_) => match self.pending_host_logs.iter_mut().find(|p| p.host == host) {
+                Some(p) => p.lines.push(line),
+                None => self.pending_host_logs.push_back(PendingHostLog {
+                    host,
+                    lines: vec![line],
+                    done: false,
+                }),
+            },
+        }
+    }
+
+    /// Mark that a host will not produce more log output, and hand the log over to
+    /// the next host with held back output
+    fn end_host_log(&mut self, host: i64) {
+        if self.log_host != Some(host) {
+            if let Some(p) = self.pending_host_logs.iter_mut().find(|p| p.host == host) {
+                p.done = true;
+            }
+            return;
+        }
+        self.log_host = None;
+        while let Some(p) = self.pending_host_logs.pop_front() {
+            for line in p.lines {
+                self.add_log(line);
+            }
+            if !p.done {
+                self.log_host = Some(p.host);
+                break;
+            }
+        }
     }
 
     fn add_log(&mut self, line: String) {
@@ -158,6 +213,8 @@ impl Deployment {
 
     fn clear_log(&mut self) {
         self.log.clear();
+        self.log_host = None;
+        self.pending_host_logs.clear();
         self.delayed_actions
             .push(IServerAction::ClearDeploymentLog(IClearDeploymentLog {}));
     }
@@ -176,6 +233,8 @@ impl Default for Deployment {
             log: Default::default(),
             delayed_actions: Default::default(),
             current_deployment_token: None,
+            log_host: None,
+            pending_host_logs: Default::default(),
         }
     }
 }
@@ -1190,7 +1249,7 @@ async fn setup_deployment_inner<'a, M>(
     node_arena: &'a Arena<OCell<M, DagNode<'a, M>>, M>,
     string_arena: &'a Arena<u8, M>,
     access: &mut OCellAccess<M>,
-) -> Result<()> {
+) -> Result<Result<Vec<IDeploymentObject>, String>> {
     let mut visitor = Visitor {
         objects,
         types,
@@ -1251,33 +1310,13 @@ async fn setup_deployment_inner<'a, M>(
         })?;
     }
     if !visitor.errors.is_empty() {
-        let message = visitor.errors.join("\n");
-        mut_deployment(state, move |deployment| {
-            deployment.set_status(DeploymentStatus::InvilidTree);
-            deployment.set_message(message);
-            Ok(())
-        })
-        .await?;
-        return Ok(());
+        return Ok(Err(visitor.errors.join("\n")));
     }
 
     for (i, o) in new_deployment_objects.iter_mut().enumerate() {
         o.index = i;
     }
-
-    mut_deployment(state, move |deployment| {
-        if new_deployment_objects.is_empty() {
-            deployment.set_status(DeploymentStatus::Done);
-            deployment.set_message("Everything up to date, nothing to deploy!".to_string());
-        } else {
-            deployment.set_status(DeploymentStatus::ReviewChanges);
-            deployment.set_message("".to_string());
-        }
-        deployment.set_deploment_objects(new_deployment_objects);
-        Ok(())
-    })
-    .await?;
-    Ok(())
+    Ok(Ok(new_deployment_objects))
 }
 
 async fn deploy_single_inner(
@@ -1288,6 +1327,7 @@ async fn deploy_single_inner(
     content: Value,
 ) -> Result<()> {
     set_location!(rt);
+    let host = host_client.id();
     let mut jh = host_client
         .start_job(&HostClientMessage::RunScript(RunScriptMessage {
             id: host_client.next_job_id(),
@@ -1321,7 +1361,7 @@ async fn deploy_single_inner(
                 mut_deployment(state, move |deployment| {
                     let data = msg.data.as_str().context("Expected string")?;
                     let line = String::from_utf8(BASE64_STANDARD.decode(data)?)?;
-                    deployment.add_log(line);
+                    deployment.add_host_log(host, line);
                     Ok(())
                 })
                 .await?;
@@ -1391,12 +1431,14 @@ async fn setup_deployment_object_types_and_hosts(
     Ok((objects, types, hosts))
 }
 
+/// Build the deployment objects for review. Returns false without changing anything
+/// if another deployment is in progress
 pub async fn setup_deployment(
     state: &State,
     deploy_id: Option<i64>,
     redeploy: bool,
     cancel: bool,
-) -> Result<()> {
+) -> Result<bool> {
     if mut_deployment(state, move |deployment| {
         if deployment.status != DeploymentStatus::Done
             && (!cancel || deployment.status != DeploymentStatus::ReviewChanges)
@@ -1410,11 +1452,32 @@ pub async fn setup_deployment(
     })
     .await?
     {
-        return Ok(());
+        return Ok(false);
     }
 
-    let (objects, types, hosts) = match setup_deployment_object_types_and_hosts(state).await {
-        Ok(v) => v,
+    match build_deployment_objects(state, deploy_id, redeploy).await {
+        Ok(Ok(new_deployment_objects)) => {
+            mut_deployment(state, move |deployment| {
+                if new_deployment_objects.is_empty() {
+                    deployment.set_status(DeploymentStatus::Done);
+                    deployment.set_message("Everything up to date, nothing to deploy!".to_string());
+                } else {
+                    deployment.set_status(DeploymentStatus::ReviewChanges);
+                    deployment.set_message("".to_string());
+                }
+                deployment.set_deploment_objects(new_deployment_objects);
+                Ok(())
+            })
+            .await?;
+        }
+        Ok(Err(message)) => {
+            mut_deployment(state, move |deployment| {
+                deployment.set_status(DeploymentStatus::InvilidTree);
+                deployment.set_message(message);
+                Ok(())
+            })
+            .await?;
+        }
         Err(e) => {
             mut_deployment(state, |deployment| {
                 deployment.set_status(DeploymentStatus::InvilidTree);
@@ -1423,9 +1486,19 @@ pub async fn setup_deployment(
             })
             .await?;
             error!("Error in setup deployment: {e:?}");
-            return Ok(());
         }
-    };
+    }
+    Ok(true)
+}
+
+/// Compute the deployment objects for the given deployment without touching the
+/// shared deployment state. The inner error is the message for an invalid tree.
+async fn build_deployment_objects(
+    state: &State,
+    deploy_id: Option<i64>,
+    redeploy: bool,
+) -> Result<Result<Vec<IDeploymentObject>, String>> {
+    let (objects, types, hosts) = setup_deployment_object_types_and_hosts(state).await?;
 
     let mut access = unsafe {
         struct Marker;
@@ -1433,7 +1506,7 @@ pub async fn setup_deployment(
     };
     let node_arena = Arena::default();
     let string_arena = Arena::default();
-    if let Err(e) = setup_deployment_inner(
+    setup_deployment_inner(
         &objects,
         &types,
         &hosts,
@@ -1445,85 +1518,407 @@ pub async fn setup_deployment(
         &mut access,
     )
     .await
+}
+
+fn plan_value_text(v: Option<&Value>) -> String {
+    match v {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(v)) => v.clone(),
+        Some(v) => serde_json::to_string_pretty(v).unwrap_or_default(),
+    }
+}
+
+fn plan_diff(object: &IDeploymentObject, field: &str, old: &str, new: &str) -> String {
+    let mut diff = similar::TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(
+            &format!("a/{}:{}#{}", object.host_name, object.title, field),
+            &format!("b/{}:{}#{}", object.host_name, object.title, field),
+        )
+        .to_string();
+    if !diff.ends_with('\n') {
+        diff.push('\n');
+    }
+    diff
+}
+
+/// Render unified diffs of every changed field between what is deployed and what
+/// would be deployed for a single deployment object.
+fn plan_object(object: IDeploymentObject) -> IDeploymentPlanObject {
+    let empty = ValueMap::new();
+    let prev = object.prev_content.as_ref().unwrap_or(&empty);
+    let next = object.next_content.as_ref().unwrap_or(&empty);
+
+    let mut diffs = Vec::new();
+    let keys = next
+        .keys()
+        .chain(prev.keys().filter(|k| !next.contains_key(*k)));
+    for key in keys {
+        let (old, new) = (prev.get(key), next.get(key));
+        if old == new {
+            continue;
+        }
+        let old = plan_value_text(old);
+        let new = plan_value_text(new);
+        diffs.push(IDeploymentPlanDiff {
+            diff: plan_diff(&object, key, &old, &new),
+            field: key.clone(),
+        });
+    }
+    if let DeploymentObjectAction::Modify = object.action
+        && let Some(prev_script) = &object.prev_script
+        && prev_script != &object.script
     {
+        diffs.push(IDeploymentPlanDiff {
+            diff: plan_diff(&object, "script", prev_script, &object.script),
+            field: "script".to_string(),
+        });
+    }
+    IDeploymentPlanObject {
+        host: object.host,
+        host_name: object.host_name,
+        title: object.title,
+        name: object.name,
+        type_name: object.type_name,
+        action: object.action,
+        diffs,
+    }
+}
+
+/// Compute what a deployment would change on each host, without deploying anything
+/// or disturbing the deployment currently under review.
+pub async fn plan(
+    state: &State,
+    deploy_id: Option<i64>,
+    redeploy: bool,
+) -> Result<Vec<IDeploymentPlanObject>> {
+    let objects = match build_deployment_objects(state, deploy_id, redeploy).await? {
+        Ok(v) => v,
+        Err(message) => bail!("Invalid tree: {message}"),
+    };
+    Ok(objects.into_iter().map(plan_object).collect())
+}
+
+/// Find the rollout wave of each host. Hosts are deployed in increasing wave order,
+/// hosts without a wave are deployed in the last wave.
+async fn get_rollout_waves(
+    state: &State,
+    deployment_objects: &[IDeploymentObject],
+) -> Result<HashMap<i64, i64>> {
+    let mut waves = HashMap::new();
+    let mut seen = HashSet::new();
+    for object in deployment_objects {
+        if !seen.insert(object.host) {
+            continue;
+        }
+        let Some(host) =
+            db::get_object_by_id_and_type::<ValueMap>(state, object.host, HOST_ID).await?
+        else {
+            continue;
+        };
+        let wave = match host.content.get("rolloutWave") {
+            Some(Value::Number(v)) => v.as_i64(),
+            Some(Value::String(v)) if !v.is_empty() => Some(v.parse()?),
+            _ => db::get_host_variables(state, object.host)
+                .await?
+                .and_then(|v| v.get("rolloutWave").map(|v| v.parse()))
+                .transpose()
+                .with_context(|| format!("Invalid rolloutWave for {}", host.name))?,
+        };
+        if let Some(wave) = wave {
+            waves.insert(object.host, wave);
+        }
+    }
+    Ok(waves)
+}
+
+/// Group deployment objects by rollout wave and then by host, in increasing wave order
+/// with hosts without a wave last. The objects of each host keep their order
+fn group_waves(
+    deployment_objects: Vec<IDeploymentObject>,
+    rollout_waves: &HashMap<i64, i64>,
+) -> Vec<(Option<i64>, Vec<HostObjects>)> {
+    let mut deployment_objects: Vec<_> = deployment_objects
+        .into_iter()
+        .enumerate()
+        .filter(|(_, o)| o.enabled)
+        .collect();
+    deployment_objects.sort_by_key(|(_, o)| {
+        let wave = rollout_waves.get(&o.host);
+        (wave.is_none(), wave.copied())
+    });
+
+    let mut waves: Vec<(Option<i64>, Vec<HostObjects>)> = Vec::new();
+    for (index, object) in deployment_objects {
+        let wave = rollout_waves.get(&object.host).copied();
+        if waves.last().is_none_or(|(w, _)| *w != wave) {
+            waves.push((wave, Vec::new()));
+        }
+        let hosts = &mut waves.last_mut().unwrap().1;
+        match hosts.iter_mut().find(|h| h[0].1.host == object.host) {
+            Some(h) => h.push((index, object)),
+            None => hosts.push(vec![(index, object)]),
+        }
+    }
+    waves
+}
+
+/// The number of hosts of a wave that failed to deploy or are no longer connected
+fn wave_failures(
+    wave_hosts: &HashSet<i64>,
+    failed_hosts: &HashSet<i64>,
+    connected: impl Fn(&i64) -> bool,
+) -> usize {
+    wave_hosts
+        .iter()
+        .filter(|h| failed_hosts.contains(h) || !connected(h))
+        .count()
+}
+
+/// Check if no more than `max_failure_ratio` of the `hosts` of a wave failed
+fn wave_passes(failed: usize, hosts: usize, max_failure_ratio: f64) -> bool {
+    failed as f64 <= max_failure_ratio * hosts as f64
+}
+
+/// Pause after a rollout wave, and decide if the deployment may continue with the next
+/// wave based on how many hosts of the wave failed or went away.
+async fn rollout_gate(
+    rt: &RunToken,
+    state: &State,
+    wave_hosts: &HashSet<i64>,
+    failed_hosts: &HashSet<i64>,
+) -> Result<bool> {
+    let rollout = &state.config.rollout;
+    mut_deployment(state, |deployment| {
+        deployment.add_log(format!(
+            "Rollout wave done, waiting {}s before the next wave\r\n",
+            rollout.pause_seconds
+        ));
+        Ok(())
+    })
+    .await?;
+    if cancelable(
+        rt,
+        tokio::time::sleep(Duration::from_secs(rollout.pause_seconds)),
+    )
+    .await
+    .is_err()
+    {
+        return Ok(false);
+    }
+    let failed = {
+        let host_clients = state.host_clients.lock().unwrap();
+        wave_failures(wave_hosts, failed_hosts, |h| host_clients.contains_key(h))
+    };
+    if !wave_passes(failed, wave_hosts.len(), rollout.max_failure_ratio) {
+        let message = format!(
+            "Aborting remaining rollout waves: {} of {} hosts in the last wave failed",
+            failed,
+            wave_hosts.len()
+        );
         mut_deployment(state, |deployment| {
-            deployment.set_status(DeploymentStatus::InvilidTree);
-            deployment.set_message(format!("{e:?}"));
+            deployment.add_log(format!("{message}\r\n"));
+            deployment.set_message(message);
             Ok(())
         })
         .await?;
-        error!("Error in setup deployment: {e:?}");
+        return Ok(false);
     }
-    Ok(())
+    Ok(true)
 }
 
-async fn perform_deploy(rt: &RunToken, state: &State, mark_only: bool) -> Result<()> {
-    let Some(deployment_objects) = mut_deployment(state, |deployment| {
-        if deployment.status != DeploymentStatus::ReviewChanges {
-            return Ok(None);
-        }
-        deployment.current_deployment_token = Some(rt.clone());
-        deployment.set_status(DeploymentStatus::Deploying);
-        deployment.add_log("Deployment started\r\n".to_string());
-        Ok(Some(deployment.deployment_objects.clone()))
-    })
-    .await?
-    else {
-        return Ok(());
-    };
-    set_location!(rt);
-    let rows = query_as!(
-        ObjectRow,
-        "SELECT `id`, `name`, `content`, `category`, `version`, `comment`,
-        strftime('%s', `time`) AS `time`, `author`, `type` FROM `objects`
-        WHERE `newest` AND `type`=? ORDER BY `id`",
-        TYPE_ID
-    )
-    .fetch_all(&state.db)
-    .await?;
+/// A change applied to a host during a deployment, with what is needed to undo it
+struct AppliedChange {
+    indexes: Vec<usize>,
+    title: String,
+    script: String,
+    // The content to pass to the script to undo the change
+    undo: Value,
+    // The names of the rows in the deployments table written by the change
+    names: Vec<String>,
+}
 
-    let mut types = HashMap::new();
-    for row in rows {
-        let t: IObject2<IType> = row.try_into()?;
-        types.insert(t.id, t);
+/// The change undoing the deployment of a delta object: the old and new content are
+/// swapped, and the script the object was previously deployed with is run. New objects
+/// have no previous script, so the current one is used to remove them again.
+/// `tracked` is true if the object has a row in the deployments table
+fn delta_undo(index: usize, object: &IDeploymentObject, tracked: bool) -> AppliedChange {
+    let mut undo = ValueMap::new();
+    undo.insert("old".to_string(), object.next_content.clone().into());
+    undo.insert("new".to_string(), object.prev_content.clone().into());
+    AppliedChange {
+        indexes: vec![index],
+        title: format!("{} ({})", &object.title, &object.type_name),
+        script: object
+            .prev_script
+            .clone()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| object.script.clone()),
+        undo: undo.into(),
+        names: if tracked {
+            vec![object.name.clone()]
+        } else {
+            vec![]
+        },
     }
+}
 
-    let mut bad_host = false;
-    let mut cur_host = -1;
-    let mut host_objects: HashMap<_, ValueMap> = HashMap::new();
-    let mut it = deployment_objects.into_iter().enumerate().peekable();
-    while let Some((index, object)) = it.next() {
-        if !object.enabled {
-            continue;
-        }
+/// A row of the deployments table as it was before the deployment started
+struct DeploymentRow {
+    content: String,
+    r#type: i64,
+    title: String,
+}
 
-        if object.host != cur_host {
-            bad_host = false;
-            cur_host = object.host;
+/// Undo the changes applied to a host in this deployment, newest first, so that the
+/// host returns to the state of its last successful deployment
+async fn rollback_host(
+    rt: &RunToken,
+    state: &State,
+    host: i64,
+    applied: Vec<AppliedChange>,
+    prev_rows: &HashMap<String, DeploymentRow>,
+) -> Result<()> {
+    let host_client = state.host_clients.lock().unwrap().get(&host).cloned();
+    let Some(host_client) = host_client else {
+        set_location!(rt);
+        mut_deployment(state, |deployment| {
+            deployment.add_host_log(host, "Host went away, unable to roll back\r\n".to_string());
+            Ok(())
+        })
+        .await?;
+        return Ok(());
+    };
+    for change in applied.into_iter().rev() {
+        set_location!(rt);
+        mut_deployment(state, |deployment| {
+            deployment.add_host_header(host, &format!("Rolling back {}", change.title), false);
+            Ok(())
+        })
+        .await?;
+        set_location!(rt);
+        if let Err(e) = deploy_single(
+            rt,
+            state,
+            Some(host_client.clone()),
+            change.script,
+            change.undo,
+        )
+        .await
+        {
             set_location!(rt);
-            mut_deployment(state, |deployment| {
-                deployment.add_header(&object.host_name, true);
+            mut_deployment(state, move |deployment| {
+                deployment.add_host_log(host, format!("{e:?}\r\nRollback failed\r\n"));
                 Ok(())
             })
             .await?;
-
-            host_objects.clear();
+            return Ok(());
+        }
+        for name in change.names {
             set_location!(rt);
-            let res = query!(
-                "SELECT `name`, `content`, `type`, `title` FROM `deployments` WHERE `host`=?",
-                cur_host
-            )
-            .fetch_all(&state.db)
-            .await?;
-            for row in res {
-                let c: IDeployContent = serde_json::from_str(&row.content)?;
-                host_objects
-                    .entry(row.r#type)
-                    .or_default()
-                    .insert(row.name, Value::Object(c.content.unwrap_or_default()));
+            if let Some(row) = prev_rows.get(&name) {
+                query!(
+                    "REPLACE INTO `deployments`
+                    (`host`, `name`, `content`, `time`, `type`, `title`)
+                    VALUES (?, ?, ?, datetime('now'), ?, ?)",
+                    host,
+                    name,
+                    row.content,
+                    row.r#type,
+                    row.title
+                )
+                .execute(&state.db)
+                .await?;
+            } else {
+                query!(
+                    "DELETE FROM `deployments` WHERE `host`=? AND `name`=?",
+                    host,
+                    name,
+                )
+                .execute(&state.db)
+                .await?;
             }
         }
+        set_location!(rt);
+        mut_deployment(state, |deployment| {
+            for index in &change.indexes {
+                deployment.set_object_status(*index, DeploymentObjectStatus::Normal);
+            }
+            Ok(())
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+/// The enabled deployment objects of a host together with their deployment index
+type HostObjects = Vec<(usize, IDeploymentObject)>;
+
+/// Deploy the objects of a single host in order, returning false if the host failed
+async fn deploy_host(
+    rt: &RunToken,
+    state: &State,
+    types: &HashMap<i64, IObject2<IType>>,
+    mark_only: bool,
+    objects: HostObjects,
+) -> Result<bool> {
+    let host = objects[0].1.host;
+    let res = deploy_host_inner(rt, state, types, mark_only, host, objects).await;
+    set_location!(rt);
+    mut_deployment(state, |deployment| {
+        deployment.end_host_log(host);
+        Ok(())
+    })
+    .await?;
+    res
+}
+
+async fn deploy_host_inner(
+    rt: &RunToken,
+    state: &State,
+    types: &HashMap<i64, IObject2<IType>>,
+    mark_only: bool,
+    host: i64,
+    objects: HostObjects,
+) -> Result<bool> {
+    set_location!(rt);
+    mut_deployment(state, |deployment| {
+        deployment.add_host_header(host, &objects[0].1.host_name, true);
+        Ok(())
+    })
+    .await?;
+
+    let mut host_objects: HashMap<_, ValueMap> = HashMap::new();
+    let mut prev_rows = HashMap::new();
+    set_location!(rt);
+    let res = query!(
+        "SELECT `name`, `content`, `type`, `title` FROM `deployments` WHERE `host`=?",
+        host
+    )
+    .fetch_all(&state.db)
+    .await?;
+    for row in res {
+        let c: IDeployContent = serde_json::from_str(&row.content)?;
+        host_objects.entry(row.r#type).or_default().insert(
+            row.name.clone(),
+            Value::Object(c.content.unwrap_or_default()),
+        );
+        prev_rows.insert(
+            row.name,
+            DeploymentRow {
+                content: row.content,
+                r#type: row.r#type,
+                title: row.title,
+            },
+        );
+    }
 
+    let mut bad_host = false;
+    let mut script_failed = false;
+    let mut applied = Vec::new();
+    let mut it = objects.into_iter().peekable();
+    while let Some((index, object)) = it.next() {
         if bad_host {
             set_location!(rt);
             mut_deployment(state, |deployment| {
@@ -1547,7 +1942,7 @@ async fn perform_deploy(rt: &RunToken, state: &State, mark_only: bool) -> Result
                 bad_host = true;
                 set_location!(rt);
                 mut_deployment(state, |deployment| {
-                    deployment.add_log(format!("Host {} is down\r\n", object.host_name));
+                    deployment.add_host_log(host, format!("Host {} is down\r\n", object.host_name));
                     deployment.set_object_status(index, DeploymentObjectStatus::Failure);
                     Ok(())
                 })
@@ -1566,14 +1961,11 @@ async fn perform_deploy(rt: &RunToken, state: &State, mark_only: bool) -> Result
             // Temp workaronud for broken objects
             next_objects.retain(|_, v| v.as_object().map(|v| !v.is_empty()).unwrap_or_default());
 
+            let before = next_objects.clone();
             let script = object.script.clone();
             let mut sum_objects = vec![(index, object)];
             while let Some((_, o2)) = it.peek() {
-                if !o2.enabled {
-                    it.next();
-                    continue;
-                }
-                if o2.type_id != type_id || o2.host != cur_host || o2.script != script {
+                if o2.type_id != type_id || o2.host != host || o2.script != script {
                     break;
                 }
                 sum_objects.push(it.next().unwrap());
@@ -1591,7 +1983,7 @@ async fn perform_deploy(rt: &RunToken, state: &State, mark_only: bool) -> Result
             }
 
             if rt.is_cancelled() {
-                break;
+                return Ok(!bad_host);
             }
             set_location!(rt);
             mut_deployment(state, |deployment| {
@@ -1605,7 +1997,7 @@ async fn perform_deploy(rt: &RunToken, state: &State, mark_only: bool) -> Result
             let mut m = ValueMap::new();
             m.insert("objects".to_string(), Value::Object(next_objects.clone()));
             set_location!(rt);
-            let ret = deploy_single(rt, state, host_client, script, Value::Object(m)).await;
+            let ret = deploy_single(rt, state, host_client, script.clone(), Value::Object(m)).await;
 
             if let Err(e) = ret {
                 set_location!(rt);
@@ -1613,11 +2005,12 @@ async fn perform_deploy(rt: &RunToken, state: &State, mark_only: bool) -> Result
                     for (i2, _) in &sum_objects {
                         deployment.set_object_status(*i2, DeploymentObjectStatus::Failure);
                     }
-                    deployment.add_log(format!("{e:?}"));
+                    deployment.add_host_log(host, format!("{e:?}"));
                     Ok(())
                 })
                 .await?;
                 bad_host = true;
+                script_failed = true;
             } else {
                 set_location!(rt);
                 mut_deployment(state, |deployment| {
@@ -1627,6 +2020,15 @@ async fn perform_deploy(rt: &RunToken, state: &State, mark_only: bool) -> Result
                     Ok(())
                 })
                 .await?;
+                let mut undo = ValueMap::new();
+                undo.insert("objects".to_string(), Value::Object(before));
+                applied.push(AppliedChange {
+                    indexes: sum_objects.iter().map(|(i2, _)| *i2).collect(),
+                    title: sum_objects[0].1.type_name.clone(),
+                    script,
+                    undo: undo.into(),
+                    names: sum_objects.iter().map(|(_, o2)| o2.name.clone()).collect(),
+                });
                 for (_, o2) in sum_objects {
                     info!("DEBUG SET DEPLOYMENT {o2:?} type_id={type_id}");
                     set_deployment(state, o2, type_id).await?;
@@ -1636,11 +2038,15 @@ async fn perform_deploy(rt: &RunToken, state: &State, mark_only: bool) -> Result
         }
 
         if rt.is_cancelled() {
-            break;
+            return Ok(!bad_host);
         }
         set_location!(rt);
         mut_deployment(state, |deployment| {
-            deployment.add_header(&format!("{} ({})", &object.title, &object.type_name), false);
+            deployment.add_host_header(
+                host,
+                &format!("{} ({})", &object.title, &object.type_name),
+                false,
+            );
             deployment.set_object_status(index, DeploymentObjectStatus::Deplying);
             Ok(())
         })
@@ -1667,16 +2073,20 @@ async fn perform_deploy(rt: &RunToken, state: &State, mark_only: bool) -> Result
         if let Err(e) = ret {
             set_location!(rt);
             mut_deployment(state, move |deployment| {
-                deployment.add_log(format!("{e:?}"));
+                deployment.add_host_log(host, format!("{e:?}"));
                 Ok(())
             })
             .await?;
             if type_kind != Some(KindType::Trigger) {
                 bad_host = true;
+                script_failed = true;
+            }
+        } else if type_kind != Some(KindType::Trigger) {
+            applied.push(delta_undo(index, &object, type_kind.is_some()));
+            if type_kind.is_some() {
+                set_location!(rt);
+                set_deployment(state, object, type_id).await?;
             }
-        } else if type_kind != Some(KindType::Trigger) && type_kind.is_some() {
-            set_location!(rt);
-            set_deployment(state, object, type_id).await?;
         }
         set_location!(rt);
         mut_deployment(state, |deployment| {
@@ -1692,6 +2102,266 @@ async fn perform_deploy(rt: &RunToken, state: &State, mark_only: bool) -> Result
         })
         .await?;
     }
+
+    if script_failed
+        && state.config.rollback_on_failure
+        && !mark_only
+        && !applied.is_empty()
+        && !rt.is_cancelled()
+    {
+        set_location!(rt);
+        rollback_host(rt, state, host, applied, &prev_rows).await?;
+    }
+    Ok(!bad_host)
+}
+
+struct DeploymentHistoryRow {
+    id: i64,
+    user: Option<String>,
+    start_time: f64,
+    end_time: Option<f64>,
+    mark_only: bool,
+    message: String,
+}
+
+impl TryFrom<DeploymentHistoryRow> for IDeploymentHistory {
+    type Error = anyhow::Error;
+
+    fn try_from(row: DeploymentHistoryRow) -> Result<Self> {
+        Ok(IDeploymentHistory {
+            id: row.id,
+            user: row.user,
+            start_time: row.start_time.to_finite()?,
+            end_time: row.end_time.to_finite()?,
+            mark_only: row.mark_only,
+            message: row.message,
+        })
+    }
+}
+
+/// Record the start of a deployment in the deployment history
+async fn begin_history(
+    db: &SqlitePool,
+    user: Option<String>,
+    mark_only: bool,
+    objects: &[IDeploymentObject],
+) -> Result<i64> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("Bad unix time")?
+        .as_secs_f64();
+    let objects_json = serde_json::to_string(objects)?;
+    let id = query!(
+        "INSERT INTO `deployment_history` (`user`, `startTime`, `markOnly`, `message`, `objects`, `log`)
+        VALUES (?, ?, ?, '', ?, '')",
+        user,
+        now,
+        mark_only,
+        objects_json
+    )
+    .execute(db)
+    .await?
+    .last_insert_rowid();
+    let hosts: HashSet<_> = objects.iter().map(|o| o.host).collect();
+    for host in hosts {
+        query!(
+            "INSERT INTO `deployment_history_hosts` (`deployment`, `host`) VALUES (?, ?)",
+            id,
+            host
+        )
+        .execute(db)
+        .await?;
+    }
+    Ok(id)
+}
+
+/// Store the outcome, the object statuses and the log of a deployment in the deployment history
+async fn end_history(
+    db: &SqlitePool,
+    id: i64,
+    message: &str,
+    objects: &[IDeploymentObject],
+    log: &str,
+) -> Result<()> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("Bad unix time")?
+        .as_secs_f64();
+    let objects = serde_json::to_string(objects)?;
+    query!(
+        "UPDATE `deployment_history` SET `endTime`=?, `message`=?, `objects`=?, `log`=? WHERE `id`=?",
+        now,
+        message,
+        objects,
+        log,
+        id
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// List past deployments newest first, optionally only those that touched a given host
+pub async fn list_history(
+    db: &SqlitePool,
+    host: Option<i64>,
+    limit: Option<i64>,
+) -> Result<Vec<IDeploymentHistory>> {
+    let limit = limit.unwrap_or(100);
+    let rows = match host {
+        Some(host) => {
+            query_as!(
+                DeploymentHistoryRow,
+                "SELECT `id`, `user`, `startTime` AS `start_time`, `endTime` AS `end_time`,
+                `markOnly` AS `mark_only`, `message` FROM `deployment_history`
+                WHERE `id` IN (SELECT `deployment` FROM `deployment_history_hosts` WHERE `host`=?)
+                ORDER BY `id` DESC LIMIT ?",
+                host,
+                limit
+            )
+            .fetch_all(db)
+            .await?
+        }
+        None => {
+            query_as!(
+                DeploymentHistoryRow,
+                "SELECT `id`, `user`, `startTime` AS `start_time`, `endTime` AS `end_time`,
+                `markOnly` AS `mark_only`, `message` FROM `deployment_history`
+                ORDER BY `id` DESC LIMIT ?",
+                limit
+            )
+            .fetch_all(db)
+            .await?
+        }
+    };
+    rows.into_iter().map(|r| r.try_into()).collect()
+}
+
+/// Fetch a past deployment together with its objects and full log
+pub async fn get_history(
+    db: &SqlitePool,
+    id: i64,
+) -> Result<Option<(IDeploymentHistory, Vec<IDeploymentObject>, String)>> {
+    let Some(row) = query!(
+        "SELECT `id`, `user`, `startTime` AS `start_time`, `endTime` AS `end_time`,
+        `markOnly` AS `mark_only`, `message`, `objects`, `log` FROM `deployment_history`
+        WHERE `id`=?",
+        id
+    )
+    .fetch_optional(db)
+    .await?
+    else {
+        return Ok(None);
+    };
+    let deployment = DeploymentHistoryRow {
+        id: row.id,
+        user: row.user,
+        start_time: row.start_time,
+        end_time: row.end_time,
+        mark_only: row.mark_only,
+        message: row.message,
+    }
+    .try_into()?;
+    let objects = serde_json::from_str(&row.objects).context("Parsing deployment objects")?;
+    Ok(Some((deployment, objects, row.log)))
+}
+
+async fn perform_deploy(
+    rt: &RunToken,
+    state: &State,
+    mark_only: bool,
+    user: Option<String>,
+) -> Result<()> {
+    let Some(deployment_objects) = mut_deployment(state, |deployment| {
+        if deployment.status != DeploymentStatus::ReviewChanges {
+            return Ok(None);
+        }
+        deployment.current_deployment_token = Some(rt.clone());
+        deployment.set_status(DeploymentStatus::Deploying);
+        deployment.add_log("Deployment started\r\n".to_string());
+        Ok(Some(deployment.deployment_objects.clone()))
+    })
+    .await?
+    else {
+        return Ok(());
+    };
+    set_location!(rt);
+    let history_id = begin_history(&state.db, user, mark_only, &deployment_objects).await?;
+    let res = deploy_objects(rt, state, mark_only, deployment_objects).await;
+    set_location!(rt);
+    let (message, objects, log) = {
+        let deployment = state.deployment.lock().unwrap();
+        (
+            deployment.message.clone(),
+            deployment.deployment_objects.clone(),
+            deployment.log.concat(),
+        )
+    };
+    end_history(&state.db, history_id, &message, &objects, &log).await?;
+    res
+}
+
+async fn deploy_objects(
+    rt: &RunToken,
+    state: &State,
+    mark_only: bool,
+    deployment_objects: Vec<IDeploymentObject>,
+) -> Result<()> {
+    set_location!(rt);
+    let rows = query_as!(
+        ObjectRow,
+        "SELECT `id`, `name`, `content`, `category`, `version`, `comment`,
+        strftime('%s', `time`) AS `time`, `author`, `type` FROM `objects`
+        WHERE `newest` AND `type`=? ORDER BY `id`",
+        TYPE_ID
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut types = HashMap::new();
+    for row in rows {
+        let t: IObject2<IType> = row.try_into()?;
+        types.insert(t.id, t);
+    }
+
+    set_location!(rt);
+    let rollout_waves = get_rollout_waves(state, &deployment_objects).await?;
+    let waves = group_waves(deployment_objects, &rollout_waves);
+
+    let parallelism = state.config.deploy_parallelism.max(1);
+    let types = &types;
+    let mut wave_hosts = HashSet::new();
+    let mut failed_hosts = HashSet::new();
+    for (_, hosts) in waves {
+        if rt.is_cancelled() {
+            break;
+        }
+        if !wave_hosts.is_empty() && !mark_only {
+            set_location!(rt);
+            if !rollout_gate(rt, state, &wave_hosts, &failed_hosts).await? {
+                break;
+            }
+        }
+        wave_hosts = hosts.iter().map(|h| h[0].1.host).collect();
+        set_location!(rt);
+        let results: Vec<_> = stream::iter(hosts)
+            .map(|objects| async move {
+                let host = objects[0].1.host;
+                (
+                    host,
+                    deploy_host(rt, state, types, mark_only, objects).await,
+                )
+            })
+            .buffer_unordered(parallelism)
+            .collect()
+            .await;
+        failed_hosts.clear();
+        for (host, ok) in results {
+            if !ok? {
+                failed_hosts.insert(host);
+            }
+        }
+    }
     set_location!(rt);
     mut_deployment(state, |deployment| {
         deployment.add_log("Done".to_string());
@@ -1742,13 +2412,71 @@ async fn set_deployment(
     Ok(())
 }
 
-pub async fn start(rt: &RunToken, state: &State) -> Result<()> {
-    perform_deploy(rt, state, false).await?;
+pub async fn start(rt: &RunToken, state: &State, user: Option<String>) -> Result<()> {
+    perform_deploy(rt, state, false, user).await?;
     Ok(())
 }
 
-pub async fn mark_deployed(rt: &RunToken, state: &State) -> Result<()> {
-    perform_deploy(rt, state, true).await?;
+/// Set up and perform a deployment without user interaction. If hosts or objects are
+/// given only the matching objects are deployed. Returns the deployed objects, or None
+/// if another deployment is in progress.
+pub async fn deploy_unattended(
+    rt: &RunToken,
+    state: &State,
+    user: String,
+    hosts: &[i64],
+    objects: &[i64],
+    redeploy: bool,
+) -> Result<Option<Vec<IDeploymentObject>>> {
+    set_location!(rt);
+    if !setup_deployment(state, None, redeploy, false).await? {
+        return Ok(None);
+    }
+    let deployment_objects = mut_deployment(state, |deployment| match deployment.status {
+        DeploymentStatus::ReviewChanges => {
+            let mut deployment_objects = deployment.deployment_objects.clone();
+            for o in &mut deployment_objects {
+                if (!hosts.is_empty() && !hosts.contains(&o.host))
+                    || (!objects.is_empty() && !o.id.is_some_and(|id| objects.contains(&id)))
+                {
+                    o.enabled = false;
+                }
+            }
+            if !deployment_objects.iter().any(|o| o.enabled) {
+                // Nothing matched the filters, leave no review behind
+                deployment.set_status(DeploymentStatus::Done);
+                deployment.set_deploment_objects(Vec::new());
+                deployment.set_message("".to_string());
+                return Ok(None);
+            }
+            deployment.set_deploment_objects(deployment_objects.clone());
+            Ok(Some(deployment_objects))
+        }
+        DeploymentStatus::InvilidTree => bail!("Invalid tree: {}", deployment.message),
+        _ => Ok(None),
+    })
+    .await?;
+    let Some(deployment_objects) = deployment_objects else {
+        return Ok(Some(Vec::new()));
+    };
+    set_location!(rt);
+    perform_deploy(rt, state, false, Some(user)).await?;
+    let statuses = state.deployment.lock().unwrap().deployment_objects.clone();
+    Ok(Some(
+        deployment_objects
+            .into_iter()
+            .zip(statuses)
+            .filter(|(o, _)| o.enabled)
+            .map(|(mut o, s)| {
+                o.status = s.status;
+                o
+            })
+            .collect(),
+    ))
+}
+
+pub async fn mark_deployed(rt: &RunToken, state: &State, user: Option<String>) -> Result<()> {
+    perform_deploy(rt, state, true, user).await?;
     Ok(())
 }
 
@@ -1816,3 +2544,197 @@ pub async fn toggle_object(state: &State, index: Option<usize>, enabled: bool) -
     )?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn motd_object(prev_script: Option<&str>) -> IDeploymentObject {
+        let mut prev = ValueMap::new();
+        prev.insert("path".into(), "/etc/motd".into());
+        prev.insert("data".into(), "hello\nworld\n".into());
+        let mut next = prev.clone();
+        next.insert("data".into(), "hello\nthere\n".into());
+        IDeploymentObject {
+            index: 0,
+            host: 1,
+            host_name: "host1".into(),
+            title: "/etc/motd".into(),
+            name: ".10".into(),
+            enabled: true,
+            status: DeploymentObjectStatus::Normal,
+            action: DeploymentObjectAction::Modify,
+            script: "script".into(),
+            prev_script: prev_script.map(|v| v.to_string()),
+            next_content: Some(next),
+            prev_content: Some(prev),
+            id: Some(10),
+            type_id: 6,
+            type_name: "File".into(),
+            triggers: Vec::new(),
+            deployment_order: 0,
+        }
+    }
+
+    #[test]
+    fn test_plan_object() {
+        let plan = plan_object(motd_object(Some("script")));
+        assert_eq!(plan.diffs.len(), 1);
+        assert_eq!(plan.diffs[0].field, "data");
+        assert_eq!(
+            plan.diffs[0].diff,
+            "--- a/host1:/etc/motd#data\n+++ b/host1:/etc/motd#data\n@@ -1,2 +1,2 @@\n hello\n-world\n+there\n"
+        );
+    }
+
+    #[test]
+    fn test_delta_undo() {
+        let object = motd_object(Some("old script"));
+        let change = delta_undo(3, &object, true);
+        assert_eq!(change.indexes, [3]);
+        assert_eq!(change.names, [".10"]);
+        // The previous script undoes the change, with old and new content swapped
+        assert_eq!(change.script, "old script");
+        assert_eq!(change.undo["old"]["data"], "hello\nthere\n");
+        assert_eq!(change.undo["new"]["data"], "hello\nworld\n");
+
+        // New objects are removed by the script that added them
+        let mut object = motd_object(Some(""));
+        object.prev_content = None;
+        let change = delta_undo(0, &object, false);
+        assert_eq!(change.script, "script");
+        assert!(change.names.is_empty());
+        assert_eq!(change.undo["new"], Value::Null);
+        assert_eq!(delta_undo(0, &motd_object(None), true).script, "script");
+    }
+
+    #[test]
+    fn test_group_waves() {
+        let object = |host: i64, title: &str| {
+            let mut o = motd_object(None);
+            o.host = host;
+            o.title = title.into();
+            o
+        };
+        let mut disabled = object(2, "disabled");
+        disabled.enabled = false;
+        let objects = vec![
+            object(1, "a"),
+            object(2, "b"),
+            object(3, "c"),
+            object(1, "d"),
+            disabled,
+            object(4, "e"),
+            object(2, "f"),
+        ];
+        // Host 3 has no wave and goes last, host 1 and 4 share a wave
+        let rollout_waves = HashMap::from([(1, 5), (2, 1), (4, 5)]);
+        let waves: Vec<_> = group_waves(objects, &rollout_waves)
+            .into_iter()
+            .map(|(wave, hosts)| {
+                let hosts: Vec<Vec<_>> = hosts
+                    .into_iter()
+                    .map(|h| h.into_iter().map(|(i, o)| (i, o.title)).collect())
+                    .collect();
+                (wave, hosts)
+            })
+            .collect();
+        let t = |i: usize, title: &str| (i, title.to_string());
+        assert_eq!(
+            waves,
+            vec![
+                (Some(1), vec![vec![t(1, "b"), t(6, "f")]]),
+                (Some(5), vec![vec![t(0, "a"), t(3, "d")], vec![t(5, "e")]]),
+                (None, vec![vec![t(2, "c")]]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rollout_gate() {
+        let wave_hosts = HashSet::from([1, 2, 3, 4]);
+        let connected = |h: &i64| *h != 4;
+        assert_eq!(wave_failures(&wave_hosts, &HashSet::new(), |_| true), 0);
+        // Failed and disconnected hosts both count, but only once
+        assert_eq!(
+            wave_failures(&wave_hosts, &HashSet::from([1]), connected),
+            2
+        );
+        assert_eq!(
+            wave_failures(&wave_hosts, &HashSet::from([4]), connected),
+            1
+        );
+
+        // The default ratio of zero stops at the first failure
+        assert!(wave_passes(0, 4, 0.0));
+        assert!(!wave_passes(1, 4, 0.0));
+        assert!(wave_passes(1, 4, 0.25));
+        assert!(!wave_passes(2, 4, 0.25));
+        assert!(wave_passes(4, 4, 1.0));
+    }
+
+    #[test]
+    fn test_host_log_grouping() {
+        let mut d = Deployment::default();
+        d.add_host_log(1, "a1".into());
+        d.add_host_log(2, "b1".into());
+        d.add_host_log(3, "c1".into());
+        d.add_host_log(1, "a2".into());
+        d.end_host_log(3);
+        d.add_host_log(2, "b2".into());
+        d.end_host_log(1);
+        d.add_host_log(2, "b3".into());
+        d.end_host_log(2);
+        assert_eq!(d.log, ["a1", "a2", "b1", "b2", "b3", "c1"]);
+        assert_eq!(d.log_host, None);
+    }
+
+    #[tokio::test]
+    async fn test_history_round_trip() -> Result<()> {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await?;
+        crate::db::setup(&db).await?;
+
+        let first = motd_object(Some("script"));
+        let mut second = motd_object(None);
+        second.index = 1;
+        second.host = 2;
+        second.host_name = "host2".into();
+        let objects = vec![first, second];
+
+        let a = begin_history(&db, Some("alice".into()), false, &objects).await?;
+        let b = begin_history(&db, None, true, &objects[..1]).await?;
+
+        let (pending, _, log) = get_history(&db, a).await?.context("Missing history")?;
+        assert_eq!(pending.end_time, None);
+        assert!(log.is_empty());
+
+        let mut done = objects.clone();
+        done[0].status = DeploymentObjectStatus::Success;
+        done[1].status = DeploymentObjectStatus::Failure;
+        end_history(&db, a, "Deployment failed", &done, "line1\r\nline2\r\n").await?;
+
+        let (deployment, stored, log) = get_history(&db, a).await?.context("Missing history")?;
+        assert_eq!(deployment.id, a);
+        assert_eq!(deployment.user.as_deref(), Some("alice"));
+        assert!(!deployment.mark_only);
+        assert!(deployment.end_time.is_some());
+        assert_eq!(deployment.message, "Deployment failed");
+        assert_eq!(log, "line1\r\nline2\r\n");
+        assert_eq!(serde_json::to_value(&stored)?, serde_json::to_value(&done)?);
+        assert!(get_history(&db, b + 1).await?.is_none());
+
+        let ids = |v: Vec<IDeploymentHistory>| v.into_iter().map(|d| d.id).collect::<Vec<_>>();
+        assert_eq!(ids(list_history(&db, None, None).await?), vec![b, a]);
+        assert_eq!(ids(list_history(&db, None, Some(1)).await?), vec![b]);
+        assert_eq!(ids(list_history(&db, Some(1), None).await?), vec![b, a]);
+        assert_eq!(ids(list_history(&db, Some(2), None).await?), vec![a]);
+        assert_eq!(
+            ids(list_history(&db, Some(3), None).await?),
+            Vec::<i64>::new()
+        );
+        Ok(())
+    }
+}
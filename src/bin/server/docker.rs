@@ -2,10 +2,10 @@ use crate::{
     action_types::{
         DockerDeployment, DockerImageTag, DockerImageTagRow, IDockerDeployEnd,
         IDockerDeploymentsChanged, IDockerListDeploymentHistory, IDockerListDeploymentHistoryRes,
-        IDockerListDeployments, IDockerListDeploymentsRes, IServerAction, IServiceDeployStart,
-        IServiceRedeployStart, Ref,
+        IDockerListDeployments, IDockerListDeploymentsRes, IDockerPruneImage, IDockerPrunePreview,
+        IDockerPrunePreviewRes, IServerAction, IServiceDeployStart, IServiceRedeployStart, Ref,
     },
-    crt, crypt, db,
+    config, crt, crypt, db, docker_signature, docker_storage,
     state::State,
     webclient::{self, WebClient},
 };
@@ -38,8 +38,32 @@ use tokio_tasks::{RunToken, cancelable};
 pub const DOCKER_BLOBS_PATH: &str = "/var/simpleadmin_docker_blobs/";
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ManifestConfig {
     pub digest: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub media_type: Option<String>,
+}
+
+impl ManifestConfig {
+    /// Is this the config of a container image, as opposed to the config of an OCI artifact
+    pub fn is_image(&self) -> bool {
+        matches!(
+            self.media_type.as_deref(),
+            None | Some(
+                "application/vnd.oci.image.config.v1+json"
+                    | "application/vnd.docker.container.image.v1+json"
+            )
+        )
+    }
+}
+
+/// The manifest an OCI 1.1 referrer (signature, SBOM, ...) refers to
+#[derive(Deserialize)]
+pub struct ManifestSubject {
+    pub digest: String,
 }
 
 #[derive(Deserialize)]
@@ -50,21 +74,46 @@ pub struct ManifestLayer {
     pub media_type: String,
 }
 
+impl ManifestLayer {
+    /// Foreign layers (e.g. Windows base OS layers) are hosted externally and not stored locally
+    pub fn is_foreign(&self) -> bool {
+        matches!(
+            self.media_type.as_str(),
+            "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip"
+                | "application/vnd.oci.image.layer.nondistributable.v1.tar"
+                | "application/vnd.oci.image.layer.nondistributable.v1.tar+gzip"
+                | "application/vnd.oci.image.layer.nondistributable.v1.tar+zstd"
+        )
+    }
+}
+
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Manifest {
     pub config: ManifestConfig,
     pub layers: Vec<ManifestLayer>,
+    #[serde(default)]
+    pub subject: Option<ManifestSubject>,
+    #[serde(default)]
+    pub artifact_type: Option<String>,
 }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct IndexManifestEntry {
     pub digest: String,
+    #[serde(default)]
+    pub size: u64,
 }
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ImageIndex {
     pub manifests: Vec<IndexManifestEntry>,
+    #[serde(default)]
+    pub subject: Option<ManifestSubject>,
+    #[serde(default)]
+    pub artifact_type: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -74,15 +123,98 @@ pub enum ManifestOrIndex {
     Index(ImageIndex),
 }
 
-async fn prune_inner(state: &State) -> Result<()> {
-    info!("Prune started");
-    let mut files = HashSet::new();
-    let mut reader = tokio::fs::read_dir(DOCKER_BLOBS_PATH).await?;
-    while let Some(ent) = reader.next_entry().await? {
-        if let Ok(v) = ent.file_name().into_string() {
-            files.insert(v);
+impl ManifestOrIndex {
+    /// The digest of the manifest this manifest refers to, if any
+    pub fn subject(&self) -> Option<&str> {
+        match self {
+            ManifestOrIndex::Image(m) => m.subject.as_ref(),
+            ManifestOrIndex::Index(i) => i.subject.as_ref(),
+        }
+        .map(|s| s.digest.as_str())
+    }
+
+    /// The artifact type as reported by the referrers API, for image manifests
+    /// without an explicit artifactType this is the media type of the config
+    pub fn artifact_type(&self) -> Option<&str> {
+        match self {
+            ManifestOrIndex::Image(m) => m
+                .artifact_type
+                .as_deref()
+                .or(m.config.media_type.as_deref()),
+            ManifestOrIndex::Index(i) => i.artifact_type.as_deref(),
         }
     }
+}
+
+/// The images and blobs removed by a prune
+struct PruneReport {
+    /// The removed images, with the blobs they reference
+    images: Vec<(IDockerPruneImage, HashSet<String>)>,
+    /// The removed blobs, with their sizes when this is a dry run
+    blobs: HashMap<String, u64>,
+}
+
+/// When an image was pushed and used, as needed by the retention rules
+struct ImageUse {
+    /// When the image was pushed
+    time: f64,
+    /// When the first deployment of the image started
+    start: Option<i64>,
+    /// When the last deployment of the image ended
+    end: Option<i64>,
+    /// When the image was last pulled
+    used: Option<f64>,
+}
+
+/// Find the retention rule of an image, the first matching configured rule
+/// takes precedence over the default rules
+fn retention_rule<'a>(
+    rules: &'a [config::RetentionRule],
+    default_rules: &'a [config::RetentionRule],
+    project: &str,
+    tag: &str,
+) -> Option<&'a config::RetentionRule> {
+    rules
+        .iter()
+        .chain(default_rules)
+        .find(|r| r.matches(project, tag))
+}
+
+/// Should an image be kept according to its retention rule, `rank` is the position
+/// of the image among the images of its tag, newest first
+fn retained(rule: &config::RetentionRule, rank: usize, row: &ImageUse, now: f64) -> bool {
+    let grace = rule.max_age_hours * 60.0 * 60.0;
+    if rank < rule.keep_last || row.time + grace > now {
+        return true;
+    }
+    if !rule.keep_used {
+        return false;
+    }
+    // Keep images that have been in use for twice as long as they were used
+    if let (Some(start), Some(end)) = (row.start, row.end)
+        && 2.0 * (end - start) as f64 + grace > now - start as f64
+    {
+        return true;
+    }
+    if let Some(used) = row.used
+        && 2.0 * (used - row.time) + grace > now - used
+    {
+        return true;
+    }
+    false
+}
+
+/// Remove images no longer kept by the retention rules, and the blobs only
+/// they use. With `dry_run` nothing is removed, we only report what would be
+async fn prune_inner(state: &State, dry_run: bool) -> Result<PruneReport> {
+    info!("Prune started{}", if dry_run { " (dry run)" } else { "" });
+    let files: HashSet<String> = state
+        .blob_storage
+        .list()
+        .await
+        .context("Listing blobs")?
+        .into_iter()
+        .collect();
     info!("Prune found {} files", files.len());
 
     let mut used = HashSet::new();
@@ -93,9 +225,6 @@ async fn prune_inner(state: &State) -> Result<()> {
         .context("Bad unix time")?
         .as_secs_f64();
 
-    let grace = 60.0 * 60.0 * 24.0 * 14.0; // Number of seconds to keep something around
-    let tmp_ci_grace = 60.0 * 60.0; // tmp_ci_* tags are pruned aggressively, once they are an hour old
-
     let rows = query!("SELECT
               `docker_images`.`manifest`,
               `docker_images`.`id`,
@@ -103,6 +232,7 @@ async fn prune_inner(state: &State) -> Result<()> {
               `docker_images`.`time`,
               `docker_images`.`project`,
               `docker_images`.`hash`,
+              `docker_images`.`subject`,
               MIN(`docker_deployments`.`startTime`) AS `start`,
               MAX(`docker_deployments`.`endTime`) AS `end`,
               COUNT(`docker_deployments`.`startTime`) - COUNT(`docker_deployments`.`endTime`) AS `active`,
@@ -117,26 +247,80 @@ async fn prune_inner(state: &State) -> Result<()> {
             WHERE `removed` IS NULL
             GROUP BY `docker_images`.`id`").fetch_all(&state.db).await.context("Running query in docker prune")?;
 
+    // The live images of each tag, newest first
+    let mut tag_images: HashMap<(&str, &str), Vec<i64>> = HashMap::new();
     for row in &rows {
-        let keep = if row.tag.starts_with("tmp_ci_") {
-            // Tmp CI images are only ever meant to live for a short while before being
-            // re-tagged and pushed again, so we prune them aggressively instead of using
-            // the normal grace period.
-            row.pin || row.active > 0 || row.time + tmp_ci_grace > now
-        } else {
-            row.pin
-                || (row.newest == Some(row.id) && row.tagPin)
-                || ((&row.tag == "latest" || &row.tag == "master") && row.newest == Some(row.id))
-                || row.active > 0
-                || (row.start.is_some()
-                    && row.end.is_some()
-                    && 2.0 * (row.end.unwrap() - row.start.unwrap()) as f64 + grace
-                        > now - row.start.unwrap() as f64)
-                || (row.used.is_some()
-                    && 2.0 * (row.used.unwrap() - row.time) + grace > now - row.used.unwrap())
-                || row.time + grace > now
-        };
+        tag_images
+            .entry((row.project.as_str(), row.tag.as_str()))
+            .or_default()
+            .push(row.id);
+    }
+    for ids in tag_images.values_mut() {
+        ids.sort_unstable_by(|a, b| b.cmp(a));
+    }
+
+    let default_rules = config::default_retention_rules();
+    let mut keep: Vec<bool> = rows
+        .iter()
+        .map(|row| {
+            if row.pin || (row.newest == Some(row.id) && row.tagPin) || row.active > 0 {
+                return true;
+            }
+            let Some(rule) = retention_rule(
+                &state.config.retention,
+                &default_rules,
+                &row.project,
+                &row.tag,
+            ) else {
+                return true;
+            };
+            let rank = tag_images
+                .get(&(row.project.as_str(), row.tag.as_str()))
+                .and_then(|ids| ids.iter().position(|id| *id == row.id))
+                .unwrap_or_default();
+            let image_use = ImageUse {
+                time: row.time,
+                start: row.start,
+                end: row.end,
+                used: row.used,
+            };
+            retained(rule, rank, &image_use, now)
+        })
+        .collect();
+
+    // Referrers (signatures, SBOMs, ...) live as long as the image they refer to.
+    // Repeat until nothing changes, as a referrer may itself have referrers.
+    loop {
+        let kept: HashSet<(&str, &str)> = rows
+            .iter()
+            .zip(&keep)
+            .filter(|(_, keep)| **keep)
+            .map(|(row, _)| (row.project.as_str(), row.hash.as_str()))
+            .collect();
+        let mut changed = false;
+        for (row, keep) in rows.iter().zip(keep.iter_mut()) {
+            // Registries without the referrers api get cosign signatures, attestations
+            // and SBOMs pushed under a sha256-<hex>.<suffix> tag instead
+            let tag_subject = row
+                .tag
+                .strip_prefix("sha256-")
+                .and_then(|v| v.split_once('.'))
+                .map(|(hex, _)| format!("sha256:{hex}"));
+            if !*keep
+                && let Some(subject) = row.subject.as_ref().or(tag_subject.as_ref())
+                && kept.contains(&(row.project.as_str(), subject.as_str()))
+            {
+                *keep = true;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
 
+    let mut removed = Vec::new();
+    for (row, keep) in rows.iter().zip(keep) {
         // Collect references reachable from this manifest/index, recursing into sub-manifests as needed.
         let mut refs: HashSet<String> = HashSet::new();
         let mut stack: Vec<String> = vec![row.manifest.clone()];
@@ -173,12 +357,15 @@ async fn prune_inner(state: &State) -> Result<()> {
                             // Sub-manifest stored as a blob (e.g. attestation manifest).
                             refs.insert(sub.digest.clone());
 
-                            match tokio::fs::read_to_string(
-                                std::path::Path::new(DOCKER_BLOBS_PATH).join(&sub.digest),
-                            )
-                            .await
-                            {
-                                Ok(sub_json) => stack.push(sub_json),
+                            match state.blob_storage.read(&sub.digest).await {
+                                Ok(Some(sub_json)) => {
+                                    stack.push(String::from_utf8_lossy(&sub_json).into_owned())
+                                }
+                                Ok(None) => {
+                                    warn!("Sub-manifest {} missing", sub.digest);
+                                    missing = true;
+                                    break;
+                                }
                                 Err(e) => {
                                     warn!("Failed to read sub-manifest {}: {e:?}", sub.digest);
                                     missing = true;
@@ -231,17 +418,26 @@ async fn prune_inner(state: &State) -> Result<()> {
                 "  active: {}, start: {:?}, end: {:?}, now: {}",
                 row.active, row.start, row.end, now
             );
-            info!(
-                "  used: {:?}, time: {}, grace: {}",
-                row.used, row.time, grace
-            );
-            query!(
-                "UPDATE `docker_images` SET `removed`=? WHERE `id`=?",
-                now,
-                row.id
-            )
-            .execute(&state.db)
-            .await?;
+            info!("  used: {:?}, time: {}", row.used, row.time);
+            if !dry_run {
+                query!(
+                    "UPDATE `docker_images` SET `removed`=? WHERE `id`=?",
+                    now,
+                    row.id
+                )
+                .execute(&state.db)
+                .await?;
+            }
+            removed.push((
+                IDockerPruneImage {
+                    id: row.id,
+                    image: row.project.clone(),
+                    tag: row.tag.clone(),
+                    hash: row.hash.clone(),
+                    missing,
+                },
+                refs,
+            ));
         }
     }
 
@@ -252,23 +448,89 @@ async fn prune_inner(state: &State) -> Result<()> {
         files.len(),
         rem.len()
     );
-    let futures: Vec<_> = rem
-        .into_iter()
-        .map(|p| {
-            let path = std::path::Path::new(DOCKER_BLOBS_PATH).join(p);
-            async move {
-                if let Err(e) = tokio::fs::remove_file(&path).await {
-                    warn!("Unable to remove {path:?}: {e:?}");
+    let blobs: HashMap<String, u64> = if dry_run {
+        let futures: Vec<_> = rem
+            .iter()
+            .map(|p| async move {
+                let size = match state.blob_storage.size(p).await {
+                    Ok(v) => v.unwrap_or_default(),
+                    Err(e) => {
+                        warn!("Unable to get size of {p}: {e:?}");
+                        0
+                    }
+                };
+                (p.to_string(), size)
+            })
+            .collect();
+        join_all(futures).await.into_iter().collect()
+    } else {
+        let futures: Vec<_> = rem
+            .iter()
+            .map(|p| async move {
+                if let Err(e) = state.blob_storage.delete(p).await {
+                    warn!("Unable to remove {p}: {e:?}");
                 }
-            }
-        })
-        .collect();
-    join_all(futures).await;
+            })
+            .collect();
+        join_all(futures).await;
+        rem.iter().map(|p| (p.to_string(), 0)).collect()
+    };
     info!("Prune done");
+    Ok(PruneReport {
+        images: removed,
+        blobs,
+    })
+}
+
+pub async fn prune_preview(
+    rt: &RunToken,
+    state: &State,
+    client: &WebClient,
+    act: IDockerPrunePreview,
+) -> Result<()> {
+    let report = prune_inner(state, true).await?;
+    let auth = client.get_auth();
+    let visible = |project: &str| {
+        (auth.admin || auth.can_docker_pull(project))
+            && act.image.as_deref().is_none_or(|v| v == project)
+    };
+    // Blobs are shared between projects, so when only some projects are shown we
+    // count the removed blobs referenced by the removed images of those projects
+    let mut images = Vec::new();
+    let mut blobs: HashSet<&str> = HashSet::new();
+    for (image, refs) in &report.images {
+        if !visible(&image.image) {
+            continue;
+        }
+        blobs.extend(
+            refs.iter()
+                .filter(|r| report.blobs.contains_key(*r))
+                .map(String::as_str),
+        );
+        images.push(image.clone());
+    }
+    if auth.admin && act.image.is_none() {
+        blobs = report.blobs.keys().map(String::as_str).collect();
+    }
+    let bytes: u64 = blobs.iter().map(|b| report.blobs[*b]).sum();
+    client
+        .send_message(
+            rt,
+            IServerAction::DockerPrunePreviewRes(IDockerPrunePreviewRes {
+                r#ref: act.r#ref,
+                images,
+                blobs: blobs.len() as i64,
+                bytes: bytes as i64,
+            }),
+        )
+        .await?;
     Ok(())
 }
 
 pub async fn docker_prune(state: Arc<State>, run_token: RunToken) -> Result<()> {
+    if let Err(e) = docker_storage::refresh(&state).await {
+        error!("Error computing docker storage usage: {e:?}");
+    }
     loop {
         if cancelable(&run_token, tokio::time::sleep(Duration::from_secs(60 * 60)))
             .await
@@ -279,9 +541,12 @@ pub async fn docker_prune(state: Arc<State>, run_token: RunToken) -> Result<()>
         // TODO(jakobt) make prune_inner cancable
         // Prune docker images every hour, so tmp_ci_* images (which are pruned
         // aggressively, an hour after creation) don't pile up for too long.
-        if let Err(e) = prune_inner(&state).await {
+        if let Err(e) = prune_inner(&state, false).await {
             error!("Error pruning docker blobs: {e:?}");
         }
+        if let Err(e) = docker_storage::refresh(&state).await {
+            error!("Error computing docker storage usage: {e:?}");
+        }
     }
     Ok(())
 }
@@ -391,7 +656,7 @@ async fn deploy_server_inner2(
 ) -> Result<(), anyhow::Error> {
     info!("service deploy start ref: {ref:?}");
     let auth = client.get_auth();
-    let user = auth.user.context("Missing user")?;
+    let user = auth.user.clone().context("Missing user")?;
     let mut variables = db::get_host_variables(state, host_id)
         .await?
         .context("Could not find root or host")?;
@@ -511,9 +776,13 @@ async fn deploy_server_inner2(
     } else {
         bail!("Missing project in description");
     };
+    if !auth.can_docker_deploy(&project) {
+        bail!("User {user} may not deploy images of {project}");
+    }
     let Some(hash) = hash else {
         bail!("Missing hash");
     };
+    docker_signature::verify_image(state, &project, &hash).await?;
     extra_env.insert("DOCKER_HASH".to_string(), hash.to_string());
     let mut buf = [0; 64];
     crypt::random_fill(&mut buf)?;
@@ -970,3 +1239,130 @@ pub async fn list_deployment_history(
         .await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: f64 = 60.0 * 60.0;
+    const DAY: f64 = 24.0 * HOUR;
+
+    /// The retention logic used before retention rules were configurable
+    fn old_retained(tag: &str, rank: usize, row: &ImageUse, now: f64) -> bool {
+        let grace = 14.0 * DAY;
+        if tag.starts_with("tmp_ci_") {
+            return row.time + HOUR > now;
+        }
+        ((tag == "latest" || tag == "master") && rank == 0)
+            || (row.start.is_some()
+                && row.end.is_some()
+                && 2.0 * (row.end.unwrap() - row.start.unwrap()) as f64 + grace
+                    > now - row.start.unwrap() as f64)
+            || (row.used.is_some()
+                && 2.0 * (row.used.unwrap() - row.time) + grace > now - row.used.unwrap())
+            || row.time + grace > now
+    }
+
+    fn rule(json: serde_json::Value) -> config::RetentionRule {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_default_rules_match_old_behaviour() {
+        let defaults = config::default_retention_rules();
+        let now = 1000.0 * DAY;
+        let ages = [0.5 * HOUR, 2.0 * HOUR, 10.0 * DAY, 20.0 * DAY, 100.0 * DAY];
+        for tag in ["tmp_ci_42", "latest", "master", "v1.2", "feature"] {
+            let rule = retention_rule(&[], &defaults, "app", tag).unwrap();
+            for rank in [0, 1, 2] {
+                for age in ages {
+                    let time = now - age;
+                    let deployments = [
+                        (None, None),
+                        (Some(time), Some(time + DAY)),
+                        (Some(time), Some(time + age / 2.0)),
+                    ];
+                    for (start, end) in deployments {
+                        for used in [None, Some(time + HOUR), Some(now - HOUR)] {
+                            let row = ImageUse {
+                                time,
+                                start: start.map(|v| v as i64),
+                                end: end.map(|v| v as i64),
+                                used,
+                            };
+                            assert_eq!(
+                                retained(rule, rank, &row, now),
+                                old_retained(tag, rank, &row, now),
+                                "tag {tag} rank {rank} age {age} start {start:?} end {end:?} used {used:?}"
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_retained() {
+        let now = 1000.0 * DAY;
+        let old = ImageUse {
+            time: now - 30.0 * DAY,
+            start: None,
+            end: None,
+            used: None,
+        };
+        let keep_last = rule(serde_json::json!({"keepLast": 2}));
+        assert!(retained(&keep_last, 0, &old, now));
+        assert!(retained(&keep_last, 1, &old, now));
+        assert!(!retained(&keep_last, 2, &old, now));
+
+        let max_age = rule(serde_json::json!({"maxAgeHours": 48.0}));
+        let young = ImageUse {
+            time: now - DAY,
+            ..old
+        };
+        assert!(retained(&max_age, 5, &young, now));
+        let stale = ImageUse {
+            time: now - 3.0 * DAY,
+            ..old
+        };
+        assert!(!retained(&max_age, 5, &stale, now));
+
+        // Pulled recently, for much longer than it has been unused
+        let used = ImageUse {
+            used: Some(now - HOUR),
+            ..stale
+        };
+        assert!(retained(&max_age, 5, &used, now));
+        let no_keep_used = rule(serde_json::json!({"maxAgeHours": 48.0, "keepUsed": false}));
+        assert!(!retained(&no_keep_used, 5, &used, now));
+        let deployed = ImageUse {
+            start: Some((now - 3.0 * DAY) as i64),
+            end: Some((now - HOUR) as i64),
+            ..stale
+        };
+        assert!(retained(&max_age, 5, &deployed, now));
+        assert!(!retained(&no_keep_used, 5, &deployed, now));
+    }
+
+    #[test]
+    fn test_retention_rule_precedence() {
+        let defaults = config::default_retention_rules();
+        let rules = vec![
+            rule(serde_json::json!({"project": "app", "tags": ["tmp_ci_*"], "maxAgeHours": 24.0})),
+            rule(serde_json::json!({"project": "app*", "maxAgeHours": 1.0})),
+        ];
+        let r = retention_rule(&rules, &defaults, "app", "tmp_ci_1").unwrap();
+        assert_eq!(r.max_age_hours, 24.0);
+        // The first matching configured rule wins, even over a more specific default
+        let r = retention_rule(&rules, &defaults, "app2", "latest").unwrap();
+        assert_eq!(r.max_age_hours, 1.0);
+        assert_eq!(r.keep_last, 0);
+        // Unmatched projects fall back to the defaults
+        let r = retention_rule(&rules, &defaults, "other", "tmp_ci_1").unwrap();
+        assert_eq!(r.max_age_hours, 1.0);
+        assert!(!r.keep_used);
+        let r = retention_rule(&rules, &defaults, "other", "master").unwrap();
+        assert_eq!(r.keep_last, 1);
+    }
+}
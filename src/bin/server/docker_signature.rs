@@ -0,0 +1,295 @@
+//! Verification of cosign style image signatures, used to gate service deployments
+//! on the signature policy of the project.
+//!
+//! A signature is a manifest whose layers are simple signing payloads naming the
+//! digest of the signed image, with the base64 encoded signature of the payload in
+//! the `dev.cosignproject.cosign/signature` annotation. We find signatures both as
+//! OCI 1.1 referrers of the image, and under the `sha256-<hex>.sig` tag cosign
+//! uses for registries without the referrers api.
+use anyhow::{Context, Result, bail};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use log::{info, warn};
+use qusql_sqlx_type::query;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::{crt, db, state::State};
+
+const SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SignatureLayer {
+    digest: String,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct SignatureManifest {
+    #[serde(default)]
+    layers: Vec<SignatureLayer>,
+}
+
+#[derive(Deserialize)]
+struct PayloadImage {
+    #[serde(rename = "docker-manifest-digest")]
+    docker_manifest_digest: String,
+}
+
+#[derive(Deserialize)]
+struct PayloadCritical {
+    image: PayloadImage,
+}
+
+#[derive(Deserialize)]
+struct Payload {
+    critical: PayloadCritical,
+}
+
+/// The signature and payload digest of the signature layers of a manifest
+fn signature_layers(manifest: &str) -> Vec<(String, String)> {
+    let Ok(manifest) = serde_json::from_str::<SignatureManifest>(manifest) else {
+        return Vec::new();
+    };
+    manifest
+        .layers
+        .into_iter()
+        .filter_map(|mut layer| {
+            let signature = layer.annotations.remove(SIGNATURE_ANNOTATION)?;
+            Some((layer.digest, signature))
+        })
+        .collect()
+}
+
+/// Find the key that made `signature` of a signing payload for the image `hash`
+async fn check_signature<'a>(
+    keys: &[(&'a String, &str)],
+    hash: &str,
+    payload: &[u8],
+    signature: &str,
+) -> Result<Option<&'a String>> {
+    // The payload must name the image, otherwise a signature of one image
+    // could be attached to another
+    match serde_json::from_slice::<Payload>(payload) {
+        Ok(p) if p.critical.image.docker_manifest_digest == hash => (),
+        _ => return Ok(None),
+    }
+    let Ok(signature) = BASE64_STANDARD.decode(signature) else {
+        return Ok(None);
+    };
+    for (name, key) in keys {
+        if crt::verify_signature(key, payload, &signature).await? {
+            return Ok(Some(name));
+        }
+    }
+    Ok(None)
+}
+
+/// Check that the image `project@hash` is signed by one of the keys trusted by the
+/// signature policy of the project. Images of projects without a policy always pass.
+pub async fn verify_image(state: &State, project: &str, hash: &str) -> Result<()> {
+    let Some(policy) = state
+        .config
+        .signature_policies
+        .iter()
+        .find(|p| p.matches(project))
+    else {
+        return Ok(());
+    };
+
+    let variables = db::get_root_variables(state).await?;
+    let mut keys = Vec::new();
+    for name in &policy.keys {
+        let key = variables
+            .get(name.as_str())
+            .with_context(|| format!("Missing signature key variable {name}"))?;
+        keys.push((name, key.as_ref()));
+    }
+
+    let hash = if hash.starts_with("sha256:") {
+        hash.to_string()
+    } else {
+        format!("sha256:{hash}")
+    };
+    let sig_tag = format!("{}.sig", hash.replacen(':', "-", 1));
+    let rows = query!(
+        "SELECT `manifest` FROM `docker_images`
+        WHERE `project`=? AND (`subject`=? OR `tag`=?) AND `removed` IS NULL",
+        project,
+        hash,
+        sig_tag
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut signatures = 0;
+    for row in rows {
+        for (digest, signature) in signature_layers(&row.manifest) {
+            signatures += 1;
+            if !digest
+                .strip_prefix("sha256:")
+                .is_some_and(|v| v.bytes().all(|c| c.is_ascii_hexdigit()))
+            {
+                continue;
+            }
+            let payload = match state.blob_storage.read(&digest).await {
+                Ok(Some(v)) => v,
+                Ok(None) => {
+                    warn!("Signature payload {digest} missing");
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Unable to read signature payload {digest}: {e:?}");
+                    continue;
+                }
+            };
+            if let Some(name) = check_signature(&keys, &hash, &payload, &signature).await? {
+                info!("Image {project}@{hash} is signed by {name}");
+                return Ok(());
+            }
+        }
+    }
+    if signatures == 0 {
+        bail!("Signature verification failed: image {project}@{hash} is not signed");
+    }
+    bail!(
+        "Signature verification failed: none of the {signatures} signatures of image {project}@{hash} is valid for the keys {}",
+        policy.keys.join(", ")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SignaturePolicy;
+
+    const HASH: &str = "sha256:0123456789abcdef";
+
+    fn openssl(args: &[&str]) {
+        let status = std::process::Command::new("openssl")
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    /// Generate a key pair, returning the PEM public key and a signer
+    fn key_pair(dir: &std::path::Path, name: &str) -> (String, impl Fn(&[u8]) -> String) {
+        let key = dir.join(format!("{name}.pem"));
+        let public = dir.join(format!("{name}.pub"));
+        let (key, public) = (key.to_str().unwrap(), public.to_str().unwrap());
+        openssl(&[
+            "ecparam",
+            "-name",
+            "prime256v1",
+            "-genkey",
+            "-noout",
+            "-out",
+            key,
+        ]);
+        openssl(&["ec", "-in", key, "-pubout", "-out", public]);
+        let public = std::fs::read_to_string(public).unwrap();
+        let key = key.to_string();
+        let dir = dir.to_path_buf();
+        let sign = move |data: &[u8]| {
+            let payload = dir.join("payload");
+            let signature = dir.join("signature");
+            std::fs::write(&payload, data).unwrap();
+            openssl(&[
+                "dgst",
+                "-sha256",
+                "-sign",
+                &key,
+                "-out",
+                signature.to_str().unwrap(),
+                payload.to_str().unwrap(),
+            ]);
+            BASE64_STANDARD.encode(std::fs::read(signature).unwrap())
+        };
+        (public, sign)
+    }
+
+    fn payload(hash: &str) -> Vec<u8> {
+        format!(
+            r#"{{"critical":{{"identity":{{"docker-reference":"r/p"}},"image":{{"docker-manifest-digest":"{hash}"}},"type":"cosign container image signature"}},"optional":null}}"#
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_policy_matches() {
+        let policy = |project: &str| SignaturePolicy {
+            project: project.to_string(),
+            keys: Vec::new(),
+        };
+        assert!(policy("prod/web").matches("prod/web"));
+        assert!(!policy("prod/web").matches("prod/web2"));
+        assert!(policy("prod/*").matches("prod/web"));
+        assert!(!policy("prod/*").matches("dev/web"));
+        assert!(policy("*").matches("anything"));
+    }
+
+    #[test]
+    fn test_signature_layers() {
+        let manifest = format!(
+            r#"{{"layers":[
+                {{"digest":"sha256:aa","annotations":{{"{SIGNATURE_ANNOTATION}":"c2ln"}}}},
+                {{"digest":"sha256:bb","annotations":{{"other":"x"}}}},
+                {{"digest":"sha256:cc"}}
+            ]}}"#
+        );
+        assert_eq!(
+            signature_layers(&manifest),
+            [("sha256:aa".to_string(), "c2ln".to_string())]
+        );
+        assert!(signature_layers("not json").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_check_signature() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (trusted_key, sign) = key_pair(dir.path(), "trusted");
+        let (other_key, sign_other) = key_pair(dir.path(), "other");
+        let (trusted, other) = ("trusted".to_string(), "other".to_string());
+        let keys = [
+            (&other, other_key.as_str()),
+            (&trusted, trusted_key.as_str()),
+        ];
+
+        let good = payload(HASH);
+        let signature = sign(&good);
+        assert_eq!(
+            check_signature(&keys, HASH, &good, &signature).await?,
+            Some(&trusted)
+        );
+
+        // Signed by a key the policy does not trust
+        let signature = sign_other(&good);
+        assert_eq!(
+            check_signature(&keys[1..], HASH, &good, &signature).await?,
+            None
+        );
+
+        // A valid signature of a payload naming another image
+        let wrong = payload("sha256:fedcba9876543210");
+        let signature = sign(&wrong);
+        assert_eq!(
+            check_signature(&keys, HASH, &wrong, &signature).await?,
+            None
+        );
+
+        // The payload does not match the signature
+        let mut tampered = good.clone();
+        tampered.extend_from_slice(b" ");
+        let signature = sign(&good);
+        assert_eq!(
+            check_signature(&keys, HASH, &tampered, &signature).await?,
+            None
+        );
+
+        assert_eq!(check_signature(&keys, HASH, &good, "!!!").await?, None);
+        assert_eq!(check_signature(&keys, HASH, b"{}", &signature).await?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+//! Accounting of the registry blob storage used by the images of each project.
+//!
+//! Blobs are stored once no matter how many images use them, so we split the
+//! bytes used by a project into blobs only its images use, and blobs shared
+//! with images of other projects.
+use anyhow::Result;
+use log::warn;
+use qusql_sqlx_type::query_as;
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    action_types::IDockerProjectUsage, config::StorageQuota, docker::ManifestOrIndex, state::State,
+};
+
+struct ImageRow {
+    project: String,
+    hash: String,
+    manifest: String,
+}
+
+/// Add the blobs referenced by a manifest to `blobs`, following the sub-manifests
+/// of image indexes. `manifests` maps the hashes of the manifests of the project
+/// to their content, sub-manifests for which `counted` returns true are skipped
+/// as their blobs are already accounted for
+async fn add_manifest_blobs(
+    state: &State,
+    manifests: &HashMap<&str, &str>,
+    counted: impl Fn(&str) -> bool,
+    manifest: &str,
+    blobs: &mut HashMap<String, u64>,
+) -> Result<()> {
+    let mut stack = vec![manifest.to_string()];
+    let mut seen = HashSet::new();
+    while let Some(json) = stack.pop() {
+        let parsed: ManifestOrIndex = match serde_json::from_str(&json) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Failed to parse manifest while computing storage usage: {e:?}");
+                continue;
+            }
+        };
+        match parsed {
+            ManifestOrIndex::Image(m) => {
+                blobs.insert(m.config.digest, m.config.size);
+                for layer in m.layers {
+                    if !layer.is_foreign() {
+                        blobs.insert(layer.digest, layer.size);
+                    }
+                }
+            }
+            ManifestOrIndex::Index(idx) => {
+                for sub in idx.manifests {
+                    if !seen.insert(sub.digest.clone()) || counted(&sub.digest) {
+                        continue;
+                    }
+                    if let Some(m) = manifests.get(sub.digest.as_str()) {
+                        stack.push(m.to_string());
+                    } else if let Some(v) = state.blob_storage.read(&sub.digest).await? {
+                        // Sub-manifest stored as a blob
+                        blobs.insert(sub.digest, sub.size);
+                        stack.push(String::from_utf8_lossy(&v).into_owned());
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct ProjectBlobs {
+    images: HashSet<String>,
+    blobs: HashMap<String, u64>,
+}
+
+/// The blobs used by the live images of each project. Computed by [refresh] in
+/// the hourly prune, and kept up to date by [record_manifest] as manifests are
+/// pushed. Images removed since the last prune are still accounted for, as their
+/// blobs are not deleted before the prune
+#[derive(Default)]
+pub struct StorageUsage {
+    projects: HashMap<String, ProjectBlobs>,
+}
+
+/// Recompute the storage used by each project from the live images
+pub async fn refresh(state: &State) -> Result<()> {
+    let rows = query_as!(
+        ImageRow,
+        "SELECT `project`, `hash`, `manifest` FROM `docker_images` WHERE `removed` IS NULL"
+    )
+    .fetch_all(&state.db)
+    .await?;
+    let mut manifests: HashMap<&str, HashMap<&str, &str>> = HashMap::new();
+    for row in &rows {
+        manifests
+            .entry(row.project.as_str())
+            .or_default()
+            .insert(row.hash.as_str(), row.manifest.as_str());
+    }
+    let mut projects: HashMap<String, ProjectBlobs> = HashMap::new();
+    for row in &rows {
+        let p = projects.entry(row.project.clone()).or_default();
+        if !p.images.insert(row.hash.clone()) {
+            continue;
+        }
+        add_manifest_blobs(
+            state,
+            &manifests[row.project.as_str()],
+            |_| false,
+            &row.manifest,
+            &mut p.blobs,
+        )
+        .await?;
+    }
+    state.docker_storage.lock().unwrap().projects = projects;
+    Ok(())
+}
+
+/// Split the bytes used by each project into blobs only it uses and blobs
+/// shared with other projects
+fn project_usage(
+    projects: &HashMap<String, ProjectBlobs>,
+    quotas: &[StorageQuota],
+) -> Vec<IDockerProjectUsage> {
+    let mut users: HashMap<&str, usize> = HashMap::new();
+    for p in projects.values() {
+        for digest in p.blobs.keys() {
+            *users.entry(digest.as_str()).or_default() += 1;
+        }
+    }
+    let mut res = Vec::new();
+    for (project, p) in projects {
+        let mut unique_bytes = 0;
+        let mut shared_bytes = 0;
+        for (digest, size) in &p.blobs {
+            if users.get(digest.as_str()).copied().unwrap_or_default() > 1 {
+                shared_bytes += size;
+            } else {
+                unique_bytes += size;
+            }
+        }
+        res.push(IDockerProjectUsage {
+            project: project.clone(),
+            images: p.images.len() as i64,
+            unique_bytes: unique_bytes as i64,
+            shared_bytes: shared_bytes as i64,
+            quota_bytes: quotas
+                .iter()
+                .find(|q| q.matches(project))
+                .map(|q| q.max_bytes as i64),
+        });
+    }
+    res.sort_unstable_by(|l, r| l.project.cmp(&r.project));
+    res
+}
+
+/// The storage used by each project, as of the last prune and the manifests pushed since
+pub fn usage(state: &State) -> Vec<IDockerProjectUsage> {
+    project_usage(
+        &state.docker_storage.lock().unwrap().projects,
+        &state.config.storage_quotas,
+    )
+}
+
+/// The blobs used by a manifest pushed to `project`, not counting sub-manifests
+/// already pushed to the project
+pub async fn manifest_blobs(
+    state: &State,
+    project: &str,
+    manifest: &str,
+) -> Result<HashMap<String, u64>> {
+    let mut blobs = HashMap::new();
+    let counted = |digest: &str| {
+        state
+            .docker_storage
+            .lock()
+            .unwrap()
+            .projects
+            .get(project)
+            .is_some_and(|p| p.images.contains(digest))
+    };
+    add_manifest_blobs(state, &HashMap::new(), counted, manifest, &mut blobs).await?;
+    Ok(blobs)
+}
+
+/// If adding `blobs` to a project currently using `current` takes it above
+/// `max_bytes`, return the bytes it would use. Pushes that add no new blobs are
+/// always allowed, so images can be re-tagged in a project over its quota
+fn check_quota(
+    current: &HashMap<String, u64>,
+    blobs: &HashMap<String, u64>,
+    max_bytes: u64,
+) -> Option<u64> {
+    let current_bytes: u64 = current.values().sum();
+    let added: u64 = blobs
+        .iter()
+        .filter(|(digest, _)| !current.contains_key(*digest))
+        .map(|(_, size)| size)
+        .sum();
+    let used = current_bytes + added;
+    (used > max_bytes && used > current_bytes).then_some(used)
+}
+
+/// If adding the `blobs` of a manifest to `project` would make the project exceed
+/// its quota, return the bytes it would use and the quota
+pub fn exceeded_quota(
+    state: &State,
+    project: &str,
+    blobs: &HashMap<String, u64>,
+) -> Option<(u64, u64)> {
+    let quota = state
+        .config
+        .storage_quotas
+        .iter()
+        .find(|q| q.matches(project))?;
+    let usage = state.docker_storage.lock().unwrap();
+    let empty = HashMap::new();
+    let current = usage.projects.get(project).map_or(&empty, |p| &p.blobs);
+    check_quota(current, blobs, quota.max_bytes).map(|used| (used, quota.max_bytes))
+}
+
+/// Account for a manifest pushed to `project` using `blobs`
+pub fn record_manifest(state: &State, project: &str, hash: &str, blobs: HashMap<String, u64>) {
+    let mut usage = state.docker_storage.lock().unwrap();
+    let p = usage.projects.entry(project.to_string()).or_default();
+    p.images.insert(hash.to_string());
+    p.blobs.extend(blobs);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(images: &[&str], blobs: &[(&str, u64)]) -> ProjectBlobs {
+        ProjectBlobs {
+            images: images.iter().map(|v| v.to_string()).collect(),
+            blobs: blobs.iter().map(|(d, s)| (d.to_string(), *s)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_project_usage() {
+        let mut projects = HashMap::new();
+        projects.insert(
+            "a".to_string(),
+            project(&["i1", "i2"], &[("base", 100), ("a1", 10), ("a2", 20)]),
+        );
+        projects.insert(
+            "b".to_string(),
+            project(&["i3"], &[("base", 100), ("b1", 5), ("shared", 7)]),
+        );
+        projects.insert("c".to_string(), project(&["i4"], &[("shared", 7)]));
+        let quotas = vec![StorageQuota {
+            project: "a*".to_string(),
+            max_bytes: 1000,
+        }];
+        let usage = project_usage(&projects, &quotas);
+        let summary: Vec<_> = usage
+            .iter()
+            .map(|u| {
+                (
+                    u.project.as_str(),
+                    u.images,
+                    u.unique_bytes,
+                    u.shared_bytes,
+                    u.quota_bytes,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("a", 2, 30, 100, Some(1000)),
+                ("b", 1, 5, 107, None),
+                ("c", 1, 0, 7, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_quota() {
+        let current: HashMap<String, u64> =
+            [("base".to_string(), 100), ("a1".to_string(), 50)].into();
+        let blobs = |v: &[(&str, u64)]| -> HashMap<String, u64> {
+            v.iter().map(|(d, s)| (d.to_string(), *s)).collect()
+        };
+        // Within the quota
+        assert_eq!(check_quota(&current, &blobs(&[("a2", 50)]), 200), None);
+        // New blobs taking the project over its quota
+        assert_eq!(
+            check_quota(&current, &blobs(&[("base", 100), ("a2", 51)]), 200),
+            Some(201)
+        );
+        // A project already over its quota may still push images adding no new blobs
+        assert_eq!(
+            check_quota(&current, &blobs(&[("base", 100), ("a1", 50)]), 100),
+            None
+        );
+        assert_eq!(
+            check_quota(&current, &blobs(&[("base", 100), ("a2", 1)]), 100),
+            Some(151)
+        );
+    }
+}
@@ -1,13 +1,12 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
-use std::os::unix::fs::MetadataExt;
 use std::sync::Arc;
 use std::sync::atomic::AtomicU64;
 
 use anyhow::{Context, anyhow};
 use axum::Router;
 use axum::body::Body;
-use axum::extract::{FromRequestParts, Query, Request, State as WState};
+use axum::extract::{FromRequestParts, Query, RawPathParams, Request, State as WState};
 use axum::http::request::Parts;
 use axum::http::{Method, StatusCode};
 use axum::middleware::Next;
@@ -24,14 +23,13 @@ use sha2::Digest;
 use sha2::Sha256;
 use tokio::io::AsyncWriteExt;
 use tokio::sync::Mutex as TMutex;
-use tokio_util::io::ReaderStream;
 use uuid::Uuid;
 
 use crate::action_types::{
     DockerImageTag, DockerImageTagRow, IAuthStatus, IDockerListImageTagsCharged, IServerAction,
 };
 use crate::crypt::cost_time_compare;
-use crate::docker::DOCKER_BLOBS_PATH;
+use crate::docker_storage;
 use crate::get_auth::get_auth;
 use crate::state::State;
 use crate::web_util::{ContentLength, ContentRange, WebError, WrappedError};
@@ -39,7 +37,7 @@ use crate::webclient;
 
 const DOCKER_UPLOAD_PATH: &str = "/var/tmp/simpleadmin_docker_uploads/";
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Clone, Debug)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 #[allow(dead_code)]
 enum RegistryErrorCode {
@@ -50,6 +48,8 @@ enum RegistryErrorCode {
     ManifestBlobUnknown,
     ManifestInvalid,
     ManifestUnknown,
+    NameUnknown,
+    PaginationNumberInvalid,
     SizeInvalid,
     TagInvalid,
     Unauthorized,
@@ -58,18 +58,19 @@ enum RegistryErrorCode {
     Unknown,
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Clone, Debug)]
 struct RegistryError {
     code: RegistryErrorCode,
     message: Cow<'static, str>,
     detail: Option<serde_json::Value>,
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Clone, Debug)]
 struct RegistryErrors {
     errors: Vec<RegistryError>,
 }
 
+#[derive(Debug)]
 struct ApiError {
     status_code: StatusCode,
     errors: RegistryErrors,
@@ -154,11 +155,6 @@ pub async fn init_upload() -> Result<(), anyhow::Error> {
     tokio::fs::create_dir(DOCKER_UPLOAD_PATH)
         .await
         .with_context(|| format!("Failure creating {DOCKER_UPLOAD_PATH}"))?;
-    match tokio::fs::create_dir(DOCKER_BLOBS_PATH).await {
-        Ok(()) => (),
-        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => (),
-        Err(e) => return Err(e).context(format!("Failure creating {DOCKER_BLOBS_PATH}")),
-    }
     Ok(())
 }
 struct UploadInner {
@@ -240,42 +236,56 @@ async fn check_docker_path<T: Sync, F: FnOnce(IAuthStatus) -> Option<T>>(
         .into_response())
 }
 
-pub struct DockerAuthPull;
+/// The image project named in the request path, if any
+async fn path_project(parts: &mut Parts, state: &Arc<State>) -> Option<String> {
+    let params = RawPathParams::from_request_parts(parts, state).await.ok()?;
+    params
+        .iter()
+        .find(|(k, _)| *k == "name" || *k == "project")
+        .map(|(_, v)| v.to_string())
+}
+
+/// The user may pull images of the project in the request path, or of some
+/// project for requests that do not name one
+pub struct DockerAuthPull(IAuthStatus);
 
 impl FromRequestParts<Arc<State>> for DockerAuthPull {
     type Rejection = Response;
 
     async fn from_request_parts(parts: &mut Parts, state: &Arc<State>) -> Result<Self, Response> {
-        check_docker_path(
-            parts,
-            state,
-            |a| if a.docker_pull { Some(()) } else { None },
-        )
+        let project = path_project(parts, state).await;
+        let auth = check_docker_path(parts, state, |a| {
+            let ok = match &project {
+                Some(project) => a.can_docker_pull(project),
+                None => a.can_docker_pull_any(),
+            };
+            ok.then_some(a)
+        })
         .await?;
-        Ok(Self)
+        Ok(Self(auth))
     }
 }
 
+/// The user may push images to the project in the request path
 pub struct DockerAuthPush {
     user: String,
+    auth: IAuthStatus,
 }
 impl FromRequestParts<Arc<State>> for DockerAuthPush {
     type Rejection = Response;
 
     async fn from_request_parts(parts: &mut Parts, state: &Arc<State>) -> Result<Self, Response> {
-        let user = check_docker_path(parts, state, |a| {
-            if a.docker_push {
-                if a.user.as_deref() == Some("docker_client") {
-                    None
-                } else {
-                    a.user
-                }
-            } else {
-                None
+        let project = path_project(parts, state).await;
+        let (user, auth) = check_docker_path(parts, state, |a| {
+            if a.user.as_deref() == Some("docker_client")
+                || !project.as_deref().is_some_and(|p| a.can_docker_push(p))
+            {
+                return None;
             }
+            a.user.clone().map(|user| (user, a))
         })
         .await?;
-        Ok(Self { user })
+        Ok(Self { user, auth })
     }
 }
 
@@ -449,26 +459,28 @@ async fn get_blob_upload_status(
 
 // GET /v2/<name>/blobs/<digest> Blob Retrieve the blob from the registry identified by digest. A HEAD request can also be issued to this endpoint to obtain resource information without receiving all data.
 async fn get_blob(
-    _: DockerAuthPull,
-    Path((_, digest)): Path<(String, String)>,
+    DockerAuthPull(auth): DockerAuthPull,
+    WState(state): WState<Arc<State>>,
+    Path((name, digest)): Path<(String, String)>,
     method: Method,
 ) -> Result<Response, ApiError> {
     if !is_docker_hash(&digest) {
         api_error!(BAD_REQUEST, Unknown, "Bad name {}", digest);
     }
-    let path = std::path::Path::new(DOCKER_BLOBS_PATH).join(&digest);
-    let file = match tokio::fs::File::open(path).await {
-        Ok(v) => v,
-        Err(e) => {
-            api_error!(NOT_FOUND, BlobUnknown, "Not found {} {:?}", digest, e);
-        }
-    };
-    let length = file
-        .metadata()
-        .await
-        .to_api_error("Failed to get metadata")?
-        .len();
+    // Blobs are shared between all projects, so users that may only pull some
+    // projects must not be able to read blobs of other projects through them
+    if !auth.docker_pull && project_blob_size(&state, &name, &digest).await?.is_none() {
+        api_error!(NOT_FOUND, BlobUnknown, "Not found {}", digest);
+    }
     if method == Method::HEAD {
+        let Some(length) = state
+            .blob_storage
+            .size(&digest)
+            .await
+            .to_api_error("Failed to get blob size")?
+        else {
+            api_error!(NOT_FOUND, BlobUnknown, "Not found {}", digest);
+        };
         return Ok((
             StatusCode::OK,
             [
@@ -481,15 +493,37 @@ async fn get_blob(
             .into_response());
     }
 
-    let stream = ReaderStream::new(file);
-    let body = Body::from_stream(stream);
+    // Let the client fetch the blob directly from the storage backend if it can
+    if let Some(url) = state
+        .blob_storage
+        .redirect_url(&digest)
+        .await
+        .to_api_error("Failed to create blob url")?
+    {
+        return Ok((
+            StatusCode::TEMPORARY_REDIRECT,
+            [("Docker-Content-Digest", digest), ("Location", url)],
+            (),
+        )
+            .into_response());
+    }
+
+    let Some(blob) = state
+        .blob_storage
+        .stream(&digest)
+        .await
+        .to_api_error("Failed to open blob")?
+    else {
+        api_error!(NOT_FOUND, BlobUnknown, "Not found {}", digest);
+    };
+    let body = Body::from_stream(blob.stream);
 
     Ok((
         StatusCode::OK,
         [
             ("Docker-Content-Digest", digest),
             ("Content-Type", "application/octet-stream".to_string()),
-            ("Content-Length", length.to_string()),
+            ("Content-Length", blob.size.to_string()),
         ],
         body,
     )
@@ -510,7 +544,8 @@ async fn get_manifest(
     let prefixed_hash = format!("sha256:{bare_hash}");
     let row = query!(
         "SELECT `manifest`, `hash`, `content_type` FROM `docker_images`
-        WHERE `project`=? AND (`tag`=? OR `hash`=? OR `hash`=?) ORDER BY `time` DESC LIMIT 1",
+        WHERE `project`=? AND (`tag`=? OR `hash`=? OR `hash`=?) AND `removed` IS NULL
+        ORDER BY `time` DESC LIMIT 1",
         name,
         reference,
         prefixed_hash,
@@ -557,6 +592,125 @@ async fn get_manifest(
         .into_response())
 }
 
+#[derive(Deserialize)]
+struct PaginationQuery {
+    n: Option<i64>,
+    last: Option<String>,
+}
+
+/// Cut `items` down to the requested page size, returning the Link header
+/// pointing at the next page if there are more items
+fn paginate(items: &mut Vec<String>, n: Option<i64>, path: &str) -> Option<String> {
+    let n = n?;
+    if items.len() as i64 <= n {
+        return None;
+    }
+    items.truncate(n as usize);
+    let last = items.last()?;
+    // Repository names and tags only contain url safe characters
+    Some(format!("<{path}?n={n}&last={last}>; rel=\"next\""))
+}
+
+/// Translate the n query parameter into a row limit, fetching one extra row
+/// to know if there is a next page
+fn page_limit(n: Option<i64>) -> Result<i64, ApiError> {
+    match n {
+        None => Ok(-1),
+        Some(n) if n < 0 => {
+            api_error!(BAD_REQUEST, PaginationNumberInvalid, "Invalid n={}", n);
+        }
+        Some(n) => Ok(n.saturating_add(1)),
+    }
+}
+
+#[derive(Serialize)]
+struct TagList {
+    name: String,
+    tags: Vec<String>,
+}
+
+// GET /v2/<name>/tags/list Tags Fetch the tags under the repository identified by name.
+async fn get_tags(
+    _: DockerAuthPull,
+    WState(state): WState<Arc<State>>,
+    Path(name): Path<String>,
+    Query(PaginationQuery { n, last }): Query<PaginationQuery>,
+) -> Result<Response, ApiError> {
+    let limit = page_limit(n)?;
+    let last = last.unwrap_or_default();
+    let mut tags: Vec<String> = query!(
+        "SELECT DISTINCT `tag` FROM `docker_images`
+        WHERE `project`=? AND `removed` IS NULL AND `tag`>? AND `tag` NOT LIKE 'sha256:%'
+        ORDER BY `tag` LIMIT ?",
+        name,
+        last,
+        limit
+    )
+    .map(|row| row.tag)
+    .fetch_all(&state.db)
+    .await
+    .to_api_error("Query failed")?;
+    if tags.is_empty() && last.is_empty() {
+        let exists = query!(
+            "SELECT `id` FROM `docker_images` WHERE `project`=? LIMIT 1",
+            name
+        )
+        .fetch_optional(&state.db)
+        .await
+        .to_api_error("Query failed")?;
+        if exists.is_none() {
+            api_error!(NOT_FOUND, NameUnknown, "Unknown repository {}", name);
+        }
+    }
+    let link = paginate(&mut tags, n, &format!("/v2/{name}/tags/list"));
+    let mut res = Json(TagList { name, tags }).into_response();
+    if let Some(link) = link {
+        res.headers_mut()
+            .insert("Link", link.try_into().to_api_error("Bad link header")?);
+    }
+    Ok(res)
+}
+
+#[derive(Serialize)]
+struct Catalog {
+    repositories: Vec<String>,
+}
+
+// GET /v2/_catalog Catalog Retrieve a sorted list of the available repositories.
+async fn get_catalog(
+    DockerAuthPull(auth): DockerAuthPull,
+    WState(state): WState<Arc<State>>,
+    Query(PaginationQuery { n, last }): Query<PaginationQuery>,
+) -> Result<Response, ApiError> {
+    let limit = page_limit(n)?;
+    let last = last.unwrap_or_default();
+    // Users that may only pull some projects only see those, so we have to
+    // filter before limiting
+    let mut repositories: Vec<String> = query!(
+        "SELECT DISTINCT `project` FROM `docker_images`
+        WHERE `removed` IS NULL AND `project`>? ORDER BY `project` LIMIT ?",
+        last,
+        if auth.docker_pull { limit } else { -1 }
+    )
+    .map(|row| row.project)
+    .fetch_all(&state.db)
+    .await
+    .to_api_error("Query failed")?;
+    if !auth.docker_pull {
+        repositories.retain(|p| auth.can_docker_pull(p));
+        if limit >= 0 {
+            repositories.truncate(limit as usize);
+        }
+    }
+    let link = paginate(&mut repositories, n, "/v2/_catalog");
+    let mut res = Json(Catalog { repositories }).into_response();
+    if let Some(link) = link {
+        res.headers_mut()
+            .insert("Link", link.try_into().to_api_error("Bad link header")?);
+    }
+    Ok(res)
+}
+
 #[derive(Deserialize)]
 struct PutBlobUploadQuery {
     digest: String,
@@ -662,12 +816,14 @@ async fn put_blob_upload(
     }
 
     info!("Docker put blob uuid={uuid} total_size={count} digest={actual_digest}");
-    tokio::fs::rename(
-        std::path::Path::new(DOCKER_UPLOAD_PATH).join(uuid.to_string()),
-        std::path::Path::new(DOCKER_BLOBS_PATH).join(&actual_digest),
-    )
-    .await
-    .to_api_error("Rename failed")?;
+    state
+        .blob_storage
+        .store(
+            &actual_digest,
+            &std::path::Path::new(DOCKER_UPLOAD_PATH).join(uuid.to_string()),
+        )
+        .await
+        .to_api_error("Storing blob failed")?;
 
     Ok((
         StatusCode::CREATED,
@@ -682,7 +838,7 @@ async fn put_blob_upload(
 }
 
 async fn validate_image_manifest(
-    _state: &Arc<State>,
+    state: &Arc<State>,
     _name: &str,
     manifest: &crate::docker::Manifest,
 ) -> Result<HashMap<String, String>, ApiError> {
@@ -695,31 +851,19 @@ async fn validate_image_manifest(
                 layer.digest
             );
         }
-        let is_foreign = matches!(
-            layer.media_type.as_str(),
-            "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip"
-                | "application/vnd.oci.image.layer.nondistributable.v1.tar"
-                | "application/vnd.oci.image.layer.nondistributable.v1.tar+gzip"
-                | "application/vnd.oci.image.layer.nondistributable.v1.tar+zstd"
-        );
-
-        // Foreign layers (e.g. Windows base OS layers) are hosted externally and not stored locally.
-        if !is_foreign {
-            let size = match tokio::fs::metadata(
-                std::path::Path::new(DOCKER_BLOBS_PATH).join(&layer.digest),
-            )
-            .await
-            {
-                Ok(v) => v.size(),
-                Err(e) => {
-                    api_error!(
-                        NOT_FOUND,
-                        ManifestBlobUnknown,
-                        "Blob error {:?} for {}",
-                        e,
-                        layer.digest
-                    );
-                }
+        if !layer.is_foreign() {
+            let Some(size) = state
+                .blob_storage
+                .size(&layer.digest)
+                .await
+                .to_api_error("Failed to get blob size")?
+            else {
+                api_error!(
+                    NOT_FOUND,
+                    ManifestBlobUnknown,
+                    "Blob {} not found",
+                    layer.digest
+                );
             };
             if size != layer.size {
                 api_error!(
@@ -734,29 +878,68 @@ async fn validate_image_manifest(
         }
     }
 
-    extract_config_labels(&manifest.config.digest).await
+    if let Some(subject) = &manifest.subject
+        && !is_docker_hash(&subject.digest)
+    {
+        api_error!(
+            BAD_REQUEST,
+            ManifestInvalid,
+            "Bad subject digest {}",
+            subject.digest
+        );
+    }
+
+    if manifest.config.is_image() {
+        return extract_config_labels(state, &manifest.config.digest).await;
+    }
+    // The config of an artifact is opaque to us, so we only check that it is there
+    if !is_docker_hash(&manifest.config.digest) {
+        api_error!(
+            BAD_REQUEST,
+            ManifestInvalid,
+            "Bad config digest {}",
+            manifest.config.digest
+        );
+    }
+    if state
+        .blob_storage
+        .size(&manifest.config.digest)
+        .await
+        .to_api_error("Failed to get blob size")?
+        .is_none()
+    {
+        api_error!(
+            NOT_FOUND,
+            ManifestBlobUnknown,
+            "Config {} not found",
+            manifest.config.digest
+        );
+    }
+    Ok(HashMap::new())
 }
 
 /// Reads the image config blob identified by `digest` and extracts the labels
 /// stored under its `config.Labels` field.
-async fn extract_config_labels(digest: &str) -> Result<HashMap<String, String>, ApiError> {
+async fn extract_config_labels(
+    state: &State,
+    digest: &str,
+) -> Result<HashMap<String, String>, ApiError> {
     if !is_docker_hash(digest) {
         api_error!(BAD_REQUEST, ManifestInvalid, "Bad config digest {}", digest);
     }
-    let config =
-        match tokio::fs::read_to_string(std::path::Path::new(DOCKER_BLOBS_PATH).join(digest)).await
-        {
-            Ok(v) => v,
-            Err(e) => {
-                api_error!(
-                    NOT_FOUND,
-                    ManifestBlobUnknown,
-                    "Unable to read config {}: {:?}",
-                    digest,
-                    e
-                );
-            }
-        };
+    let Some(config) = state
+        .blob_storage
+        .read(digest)
+        .await
+        .to_api_error("Failed to read config")?
+    else {
+        api_error!(
+            NOT_FOUND,
+            ManifestBlobUnknown,
+            "Config {} not found",
+            digest
+        );
+    };
     #[derive(Deserialize)]
     struct ConfigConfig {
         #[serde(default, alias = "Labels")]
@@ -769,7 +952,7 @@ async fn extract_config_labels(digest: &str) -> Result<HashMap<String, String>,
         config: Option<ConfigConfig>,
     }
 
-    let config: Config = match serde_json::from_str(&config) {
+    let config: Config = match serde_json::from_slice(&config) {
         Ok(v) => v,
         Err(e) => {
             api_error!(
@@ -789,6 +972,16 @@ async fn validate_image_index(
     name: &str,
     index: &crate::docker::ImageIndex,
 ) -> Result<HashMap<String, String>, ApiError> {
+    if let Some(subject) = &index.subject
+        && !is_docker_hash(&subject.digest)
+    {
+        api_error!(
+            BAD_REQUEST,
+            ManifestInvalid,
+            "Bad subject digest {}",
+            subject.digest
+        );
+    }
     let mut labels = HashMap::new();
     for sub in &index.manifests {
         if !is_docker_hash(&sub.digest) {
@@ -800,14 +993,13 @@ async fn validate_image_index(
             );
         }
         // Sub-manifest may be stored as a manifest (in docker_images) or as a blob.
-        let blob_exists =
-            tokio::fs::try_exists(std::path::Path::new(DOCKER_BLOBS_PATH).join(&sub.digest))
-                .await
-                .unwrap_or(false);
-        let sub_manifest = if blob_exists {
-            tokio::fs::read_to_string(std::path::Path::new(DOCKER_BLOBS_PATH).join(&sub.digest))
-                .await
-                .to_api_error("Failed to read sub-manifest blob")?
+        let blob = state
+            .blob_storage
+            .read(&sub.digest)
+            .await
+            .to_api_error("Failed to read sub-manifest blob")?;
+        let sub_manifest = if let Some(blob) = blob {
+            String::from_utf8(blob).to_api_error("Sub-manifest blob is not utf-8")?
         } else {
             let row = query!(
                 "SELECT `manifest` FROM `docker_images` WHERE `project`=? AND `hash`=? LIMIT 1",
@@ -831,8 +1023,9 @@ async fn validate_image_index(
         // other non-image sub-manifests are tolerated and simply contribute no labels.
         if let Ok(crate::docker::ManifestOrIndex::Image(manifest)) =
             serde_json::from_str::<crate::docker::ManifestOrIndex>(&sub_manifest)
+            && manifest.config.is_image()
         {
-            labels.extend(extract_config_labels(&manifest.config.digest).await?);
+            labels.extend(extract_config_labels(state, &manifest.config.digest).await?);
         }
     }
     Ok(labels)
@@ -840,7 +1033,7 @@ async fn validate_image_index(
 
 // PUT /v2/<name>/manifests/<reference> Manifest Put the manifest identified by name and reference where reference can be a tag or digest.
 async fn put_manifest(
-    DockerAuthPush { user }: DockerAuthPush,
+    DockerAuthPush { user, .. }: DockerAuthPush,
     WState(state): WState<Arc<State>>,
     Path((name, reference)): Path<(String, String)>,
     req: Request,
@@ -868,19 +1061,56 @@ async fn put_manifest(
         }
     };
 
-    let labels = match parsed {
+    let labels = match &parsed {
         crate::docker::ManifestOrIndex::Image(manifest) => {
-            validate_image_manifest(&state, &name, &manifest).await?
+            validate_image_manifest(&state, &name, manifest).await?
         }
         crate::docker::ManifestOrIndex::Index(index) => {
-            validate_image_index(&state, &name, &index).await?
+            validate_image_index(&state, &name, index).await?
         }
     };
+    let subject = parsed.subject().map(str::to_string);
+    let artifact_type = parsed.artifact_type().map(str::to_string);
     let labels_string =
         serde_json::to_string(&labels).to_api_error("Unable to convert labels to string")?;
     let digest = sha2::Sha256::digest(&body);
     let hash = format!("sha256:{}", hex::encode(digest));
 
+    if !reference.starts_with("sha256:")
+        && state
+            .config
+            .immutable_tags
+            .iter()
+            .any(|p| p.matches(&name, &reference))
+    {
+        // Removed images count as well, otherwise the tag could be moved by
+        // deleting it first
+        let row = query!(
+            "SELECT `hash` FROM `docker_images` WHERE `project`=? AND `tag`=?
+            ORDER BY `time` DESC LIMIT 1",
+            name,
+            reference
+        )
+        .fetch_optional(&state.db)
+        .await
+        .to_api_error("Query failed")?;
+        check_immutable_tag(&name, &reference, &hash, row.map(|r| r.hash).as_deref())?;
+    }
+
+    let blobs = docker_storage::manifest_blobs(&state, &name, &body)
+        .await
+        .to_api_error("Quota check failed")?;
+    if let Some((used, quota)) = docker_storage::exceeded_quota(&state, &name, &blobs) {
+        api_error!(
+            FORBIDDEN,
+            Denied,
+            "Project {} would use {} bytes, exceeding its quota of {} bytes",
+            name,
+            used,
+            quota
+        );
+    }
+
     let time = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .to_api_error("Invalid unix time")?
@@ -903,8 +1133,8 @@ async fn put_manifest(
         .to_api_error("Database query failed")?;
         let id = query!(
             "INSERT INTO `docker_images` (`project`, `tag`, `manifest`, `hash`,
-            `user`, `time`, `pin`, `labels`, `content_type`)
-            VALUES (?, ?, ?, ?, ?, ?, false, ?, ?)",
+            `user`, `time`, `pin`, `labels`, `content_type`, `subject`, `artifact_type`)
+            VALUES (?, ?, ?, ?, ?, ?, false, ?, ?, ?, ?)",
             name,
             reference,
             body,
@@ -913,6 +1143,8 @@ async fn put_manifest(
             time,
             labels_string,
             content_type,
+            subject,
+            artifact_type,
         )
         .execute(&mut *tx)
         .await
@@ -921,6 +1153,7 @@ async fn put_manifest(
         tx.commit().await.to_api_error("Database commit failed")?;
         id
     };
+    docker_storage::record_manifest(&state, &name, &hash, blobs);
 
     webclient::broadcast(
         &state,
@@ -943,7 +1176,7 @@ async fn put_manifest(
     )
     .to_api_error("Broadcast failed")?;
 
-    Ok((
+    let mut res = (
         StatusCode::CREATED,
         [
             ("ContentLength", "0".to_string()),
@@ -952,7 +1185,312 @@ async fn put_manifest(
         ],
         (),
     )
-        .into_response())
+        .into_response();
+    // Tell the client that we support the referrers api, so it does not fall
+    // back to the tag schema
+    if let Some(subject) = subject {
+        res.headers_mut().insert(
+            "OCI-Subject",
+            subject.try_into().to_api_error("Bad subject header")?,
+        );
+    }
+    Ok(res)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReferrersQuery {
+    artifact_type: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReferrerDescriptor {
+    media_type: String,
+    digest: String,
+    size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artifact_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    annotations: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReferrersIndex {
+    schema_version: u32,
+    media_type: &'static str,
+    manifests: Vec<ReferrerDescriptor>,
+}
+
+struct ReferrerRow {
+    hash: String,
+    manifest: String,
+    content_type: Option<String>,
+    artifact_type: Option<String>,
+}
+
+/// Describe the referrers of a manifest, optionally only those of the given artifact type
+fn referrer_descriptors(
+    rows: Vec<ReferrerRow>,
+    artifact_type: Option<&str>,
+) -> Vec<ReferrerDescriptor> {
+    let mut seen = std::collections::HashSet::new();
+    let mut manifests = Vec::new();
+    for row in rows {
+        if artifact_type.is_some() && row.artifact_type.as_deref() != artifact_type {
+            continue;
+        }
+        if !seen.insert(row.hash.clone()) {
+            continue;
+        }
+        let annotations = serde_json::from_str::<serde_json::Value>(&row.manifest)
+            .ok()
+            .and_then(|mut v| v.get_mut("annotations").map(serde_json::Value::take));
+        manifests.push(ReferrerDescriptor {
+            media_type: row
+                .content_type
+                .unwrap_or_else(|| "application/vnd.oci.image.manifest.v1+json".to_string()),
+            digest: row.hash,
+            size: row.manifest.len(),
+            artifact_type: row.artifact_type,
+            annotations,
+        });
+    }
+    manifests
+}
+
+/// Refuse to move the immutable tag `reference` of `name` from the image `existing` to `hash`
+fn check_immutable_tag(
+    name: &str,
+    reference: &str,
+    hash: &str,
+    existing: Option<&str>,
+) -> Result<(), ApiError> {
+    if let Some(existing) = existing
+        && existing != hash
+    {
+        api_error!(
+            FORBIDDEN,
+            Denied,
+            "Tag {} of {} is immutable and already refers to {}",
+            reference,
+            name,
+            existing
+        );
+    }
+    Ok(())
+}
+
+// GET /v2/<name>/referrers/<digest> Referrers List the manifests that refer to the manifest identified by name and digest.
+async fn get_referrers(
+    _: DockerAuthPull,
+    WState(state): WState<Arc<State>>,
+    Path((name, digest)): Path<(String, String)>,
+    Query(ReferrersQuery { artifact_type }): Query<ReferrersQuery>,
+) -> Result<Response, ApiError> {
+    if !is_docker_hash(&digest) {
+        api_error!(BAD_REQUEST, DigestInvalid, "Bad digest {}", digest);
+    }
+    let rows = qusql_sqlx_type::query_as!(
+        ReferrerRow,
+        "SELECT `hash`, `manifest`, `content_type`, `artifact_type` FROM `docker_images`
+        WHERE `project`=? AND `subject`=? AND `removed` IS NULL ORDER BY `time`",
+        name,
+        digest
+    )
+    .fetch_all(&state.db)
+    .await
+    .to_api_error("Query failed")?;
+    let manifests = referrer_descriptors(rows, artifact_type.as_deref());
+
+    let mut res = (
+        [("Content-Type", "application/vnd.oci.image.index.v1+json")],
+        Json(ReferrersIndex {
+            schema_version: 2,
+            media_type: "application/vnd.oci.image.index.v1+json",
+            manifests,
+        }),
+    )
+        .into_response();
+    if artifact_type.is_some() {
+        res.headers_mut().insert(
+            "OCI-Filters-Applied",
+            "artifactType"
+                .try_into()
+                .to_api_error("Bad filter header")?,
+        );
+    }
+    Ok(res)
+}
+
+/// Mark the given images as removed, so that the next prune reclaims their blobs.
+/// Refuses if any of the images are pinned or in use by an active deployment.
+async fn remove_images(state: &State, ids: Vec<i64>) -> Result<(), ApiError> {
+    let rows = query!(
+        "SELECT `tag`, `hash`, `pin`,
+          EXISTS (SELECT * FROM `docker_image_tag_pins` WHERE `docker_image_tag_pins`.`project`=`docker_images`.`project` AND `docker_image_tag_pins`.`tag`=`docker_images`.`tag`) AS `tagPin`,
+          EXISTS (SELECT * FROM `docker_deployments` WHERE `docker_deployments`.`hash`=`docker_images`.`hash` AND `docker_deployments`.`endTime` IS NULL) AS `active`
+        FROM `docker_images` WHERE `id` IN (_LIST_)",
+        ids
+    )
+    .fetch_all(&state.db)
+    .await
+    .to_api_error("Query failed")?;
+    for row in rows {
+        if row.pin || row.tagPin {
+            api_error!(
+                CONFLICT,
+                Denied,
+                "Image {} ({}) is pinned",
+                row.tag.clone(),
+                row.hash.clone()
+            );
+        }
+        if row.active {
+            api_error!(
+                CONFLICT,
+                Denied,
+                "Image {} ({}) is in use by an active deployment",
+                row.tag.clone(),
+                row.hash.clone()
+            );
+        }
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .to_api_error("Invalid unix time")?
+        .as_secs_f64();
+    query!(
+        "UPDATE `docker_images` SET `removed`=? WHERE `id` IN (_LIST_)",
+        now,
+        ids
+    )
+    .execute(&state.db)
+    .await
+    .to_api_error("Database query failed")?;
+
+    let rows = qusql_sqlx_type::query_as!(
+        DockerImageTagRow,
+        "SELECT `id`, `hash`, `time`, `project`, `user`, `tag`, `pin`, `labels`,
+        `removed` FROM `docker_images` WHERE `id` IN (_LIST_)",
+        ids
+    )
+    .fetch_all(&state.db)
+    .await
+    .to_api_error("Query failed")?;
+    let mut changed = Vec::new();
+    for row in rows {
+        changed.push(row.try_into().to_api_error("Invalid image row")?);
+    }
+    webclient::broadcast(
+        state,
+        IServerAction::DockerListImageTagsChanged(IDockerListImageTagsCharged {
+            changed,
+            removed: Vec::new(),
+            image_tag_pin_changed: None,
+        }),
+    )
+    .to_api_error("Broadcast failed")?;
+    Ok(())
+}
+
+// DELETE /v2/<name>/manifests/<reference> Manifest Delete the manifest identified by name and reference where reference can be a tag or digest.
+async fn delete_manifest(
+    DockerAuthPush { user, .. }: DockerAuthPush,
+    WState(state): WState<Arc<State>>,
+    Path((name, reference)): Path<(String, String)>,
+) -> Result<Response, ApiError> {
+    if state.read_only {
+        api_error!(SERVICE_UNAVAILABLE, Unsupported, "Service read only",);
+    }
+    info!("Docker delete manifest name={name} reference={reference} user={user}");
+    let bare_hash = reference.strip_prefix("sha256:").unwrap_or(&reference);
+    let prefixed_hash = format!("sha256:{bare_hash}");
+    let ids: Vec<i64> = if reference.starts_with("sha256:") {
+        query!(
+            "SELECT `id` FROM `docker_images`
+            WHERE `project`=? AND (`hash`=? OR `hash`=?) AND `removed` IS NULL",
+            name,
+            prefixed_hash,
+            bare_hash
+        )
+        .map(|row| row.id)
+        .fetch_all(&state.db)
+        .await
+    } else {
+        query!(
+            "SELECT `id` FROM `docker_images`
+            WHERE `project`=? AND `tag`=? AND `removed` IS NULL",
+            name,
+            reference
+        )
+        .map(|row| row.id)
+        .fetch_all(&state.db)
+        .await
+    }
+    .to_api_error("Query failed")?;
+    if ids.is_empty() {
+        api_error!(
+            NOT_FOUND,
+            ManifestUnknown,
+            "Docker delete manifest: not found project: {}, identifer: {}",
+            name,
+            reference
+        );
+    }
+    remove_images(&state, ids).await?;
+    Ok(StatusCode::ACCEPTED.into_response())
+}
+
+// DELETE /v2/<name>/blobs/<digest> Blob Delete the blob identified by name and digest.
+// The images in the repository referencing the blob are removed, and the next prune
+// deletes the blob once nothing references it any more.
+async fn delete_blob(
+    DockerAuthPush { user, .. }: DockerAuthPush,
+    WState(state): WState<Arc<State>>,
+    Path((name, digest)): Path<(String, String)>,
+) -> Result<Response, ApiError> {
+    if !is_docker_hash(&digest) {
+        api_error!(BAD_REQUEST, DigestInvalid, "Bad digest {}", digest);
+    }
+    if state.read_only {
+        api_error!(SERVICE_UNAVAILABLE, Unsupported, "Service read only",);
+    }
+    info!("Docker delete blob name={name} digest={digest} user={user}");
+    // Deleting a blob never removes images. Blob storage is shared between projects,
+    // so a blob referenced as config or layer by a live image in any project stays.
+    // Digests are unique enough that a substring match on the manifest finds the
+    // images referencing the blob.
+    let pattern = format!("%\"{digest}\"%");
+    let referenced = query!(
+        "SELECT `id` FROM `docker_images`
+        WHERE `manifest` LIKE ? AND `removed` IS NULL LIMIT 1",
+        pattern
+    )
+    .fetch_optional(&state.db)
+    .await
+    .to_api_error("Query failed")?;
+    if referenced.is_some() {
+        api_error!(
+            FORBIDDEN,
+            Denied,
+            "Blob {} is referenced by a live image",
+            digest
+        );
+    }
+    if state
+        .blob_storage
+        .size(&digest)
+        .await
+        .to_api_error("Failed to get blob size")?
+        .is_none()
+    {
+        api_error!(NOT_FOUND, BlobUnknown, "Not found {}", digest);
+    }
+    // The unreferenced blob is removed from storage by the next prune
+    Ok(StatusCode::ACCEPTED.into_response())
 }
 
 // PATCH /v2/<name>/blobs/uploads/<uuid> Blob Upload Upload a chunk of data for the specified upload.
@@ -1054,30 +1592,91 @@ async fn patch_blob_upload(
 #[derive(Deserialize)]
 struct PostBlobUploadQuery {
     digest: Option<String>,
+    mount: Option<String>,
+    from: Option<String>,
+}
+
+/// Return the size of the blob `digest` if it exists and is referenced by an image in `project`
+async fn project_blob_size(
+    state: &State,
+    project: &str,
+    digest: &str,
+) -> Result<Option<u64>, ApiError> {
+    let Some(size) = state
+        .blob_storage
+        .size(digest)
+        .await
+        .to_api_error("Failed to get blob size")?
+    else {
+        return Ok(None);
+    };
+    let pattern = format!("%\"{digest}\"%");
+    let row = query!(
+        "SELECT `id` FROM `docker_images`
+        WHERE `project`=? AND `manifest` LIKE ? AND `removed` IS NULL LIMIT 1",
+        project,
+        pattern
+    )
+    .fetch_optional(&state.db)
+    .await
+    .to_api_error("Query failed")?;
+    Ok(row.map(|_| size))
 }
 
 // POST /v2/<name>/blobs/uploads/ Initiate Blob Upload Initiate a resumable blob upload.
 // If successful, an upload location will be provided to complete the upload.
 // Optionally, if the digest parameter is present, the request body will be used to complete the upload in a single request.
 async fn post_blob_upload(
-    _: DockerAuthPush,
+    DockerAuthPush { user, auth }: DockerAuthPush,
     WState(state): WState<Arc<State>>,
     Path(name): Path<String>,
-    Query(PostBlobUploadQuery { digest }): Query<PostBlobUploadQuery>,
+    Query(PostBlobUploadQuery {
+        digest,
+        mount,
+        from,
+    }): Query<PostBlobUploadQuery>,
     body: Body,
 ) -> Result<Response, ApiError> {
     if state.read_only {
         api_error!(SERVICE_UNAVAILABLE, Unsupported, "Service read only",);
     }
 
+    // Cross repository mount: blobs are stored in one flat directory, so if the
+    // blob is part of `from` we only have to tell the client that it is there.
+    // If we can not mount we fall through to a normal upload.
+    if let (Some(mount), Some(from)) = (mount, from)
+        && auth.can_docker_pull(&from)
+        && is_docker_hash(&mount)
+        && let Some(size) = project_blob_size(&state, &from, &mount).await?
+    {
+        info!(
+            "Docker mount blob name={name} from={from} digest={mount} user={user}, saved uploading {size} bytes"
+        );
+        return Ok((
+            StatusCode::CREATED,
+            [
+                ("Content-Length", "0".to_string()),
+                ("Location", format!("/v2/{name}/blobs/{mount}")),
+                ("Docker-Content-Digest", mount),
+            ],
+            (),
+        )
+            .into_response());
+    }
+
     // Single-shot upload: digest provided in query string, full blob is in the body.
     if let Some(digest) = digest {
         if !is_docker_hash(&digest) {
             api_error!(BAD_REQUEST, DigestInvalid, "Bad digest {}", digest);
         }
-        let dest = std::path::Path::new(DOCKER_BLOBS_PATH).join(&digest);
         // Fast-path: blob already exists.
-        if tokio::fs::metadata(&dest).await.is_ok() {
+        if state
+            .blob_storage
+            .size(&digest)
+            .await
+            .to_api_error("Failed to get blob size")?
+            .is_some()
+        {
             return Ok((
                 StatusCode::CREATED,
                 [
@@ -1132,9 +1731,11 @@ async fn post_blob_upload(
             );
         }
         info!("Docker post blob (single-shot) total_size={count} digest={actual_digest}");
-        tokio::fs::rename(&tmp_path, &dest)
+        state
+            .blob_storage
+            .store(&actual_digest, &tmp_path)
             .await
-            .to_api_error("Rename failed")?;
+            .to_api_error("Storing blob failed")?;
         return Ok((
             StatusCode::CREATED,
             [
@@ -1193,16 +1794,22 @@ pub fn docker_api_routes() -> Result<Router<Arc<State>>, anyhow::Error> {
     use axum::routing::{get, post};
     let router = Router::new()
         .route("/", get(basic_check))
+        .route("/_catalog", get(get_catalog))
+        .route("/{name}/tags/list", get(get_tags))
+        .route("/{name}/referrers/{digest}", get(get_referrers))
         .route(
             "/{name}/blobs/uploads/{uuid}",
             get(get_blob_upload_status)
                 .patch(patch_blob_upload)
                 .put(put_blob_upload),
         )
-        .route("/{name}/blobs/{digist}", get(get_blob))
+        .route("/{name}/blobs/{digist}", get(get_blob).delete(delete_blob))
         .route(
             "/{name}/manifests/{reference}",
-            get(get_manifest).head(get_manifest).put(put_manifest),
+            get(get_manifest)
+                .head(get_manifest)
+                .put(put_manifest)
+                .delete(delete_manifest),
         )
         .route("/{name}/blobs/uploads/", post(post_blob_upload))
         .layer(axum::middleware::from_fn(docker_api_middleware));
@@ -1260,3 +1867,282 @@ pub async fn upload_reaper(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BlobStorageConfig, Config};
+
+    /// A state with a fresh database, and blob storage in `dir`
+    async fn test_state(dir: &std::path::Path) -> anyhow::Result<Arc<State>> {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await?;
+        let next_object_id = crate::db::setup(&db).await?;
+        // Avoid generating a docker CA
+        for key in ["ca_key", "ca_crt"] {
+            query!(
+                "INSERT INTO `kvp` (`key`, `value`) VALUES (?, ?)",
+                key,
+                "unused"
+            )
+            .execute(&db)
+            .await?;
+        }
+        let docker = crate::docker::Docker::new(&db).await?;
+        let blob_storage = crate::blob_storage::open(&BlobStorageConfig::Fs {
+            path: dir.join("blobs").to_string_lossy().into_owned(),
+        })
+        .await?;
+        Ok(Arc::new(State {
+            db,
+            config: Config::default(),
+            next_object_id: next_object_id.into(),
+            modified_files: Default::default(),
+            modified_files_scan: Default::default(),
+            docker_storage: Default::default(),
+            deployment: Default::default(),
+            docker,
+            host_clients: Default::default(),
+            web_clients: Default::default(),
+            docker_uploads: Default::default(),
+            blob_storage,
+            read_only: false,
+            login_attempts: Default::default(),
+            otp_failures: Default::default(),
+        }))
+    }
+
+    /// Request mounting `digest` from `from` into the project app
+    async fn mount(state: &Arc<State>, auth: &IAuthStatus, digest: &str, from: &str) -> Response {
+        let res = post_blob_upload(
+            DockerAuthPush {
+                user: "alice".to_string(),
+                auth: auth.clone(),
+            },
+            WState(state.clone()),
+            Path("app".to_string()),
+            Query(PostBlobUploadQuery {
+                digest: None,
+                mount: Some(digest.to_string()),
+                from: Some(from.to_string()),
+            }),
+            Body::empty(),
+        )
+        .await
+        .unwrap();
+        // Drop the upload started when falling back to a normal upload
+        if let Some(uuid) = res.headers().get("Docker-Upload-UUID") {
+            let uuid = uuid.to_str().unwrap();
+            state
+                .docker_uploads
+                .lock()
+                .unwrap()
+                .remove(&uuid.parse().unwrap());
+            std::fs::remove_file(std::path::Path::new(DOCKER_UPLOAD_PATH).join(uuid)).unwrap();
+        }
+        res
+    }
+
+    #[tokio::test]
+    async fn test_mount_blob() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let state = test_state(dir.path()).await?;
+        tokio::fs::create_dir_all(DOCKER_UPLOAD_PATH).await?;
+
+        let live = format!("sha256:{}", "1".repeat(64));
+        let old = format!("sha256:{}", "2".repeat(64));
+        let unreferenced = format!("sha256:{}", "3".repeat(64));
+        for digest in [&live, &old, &unreferenced] {
+            let upload = dir.path().join("upload");
+            std::fs::write(&upload, digest)?;
+            state.blob_storage.store(digest, &upload).await?;
+        }
+        for (project, tag, digest, removed) in [
+            ("base", "live", &live, None),
+            ("base", "old", &old, Some(1.0)),
+            ("secret", "live", &live, None),
+        ] {
+            let manifest = format!(r#"{{"layers":[{{"digest":"{digest}","size":71}}]}}"#);
+            let time = 1.0;
+            query!(
+                "INSERT INTO `docker_images` (`project`, `tag`, `manifest`, `hash`, `user`,
+                `time`, `pin`, `removed`) VALUES (?, ?, ?, ?, ?, ?, false, ?)",
+                project,
+                tag,
+                manifest,
+                tag,
+                "bob",
+                time,
+                removed
+            )
+            .execute(&state.db)
+            .await?;
+        }
+        let auth = IAuthStatus {
+            docker_pull_projects: vec!["base".to_string()],
+            docker_push_projects: vec!["app".to_string()],
+            ..Default::default()
+        };
+
+        // A project the user may not pull
+        let res = mount(&state, &auth, &live, "secret").await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        // A blob no image of the project references
+        let res = mount(&state, &auth, &unreferenced, "base").await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        // A blob only referenced by a removed image
+        let res = mount(&state, &auth, &old, "base").await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+
+        let res = mount(&state, &auth, &live, "base").await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let header = |name: &str| res.headers().get(name).unwrap().to_str().unwrap();
+        assert_eq!(header("Location"), format!("/v2/app/blobs/{live}"));
+        assert_eq!(header("Docker-Content-Digest"), live);
+        Ok(())
+    }
+
+    /// Fetch the page after `last` the way the tag and catalog queries do
+    fn fetch_page(
+        all: &[&str],
+        n: Option<i64>,
+        last: &str,
+    ) -> Result<(Vec<String>, Option<String>), ApiError> {
+        let limit = page_limit(n)?;
+        let mut items: Vec<String> = all
+            .iter()
+            .filter(|v| **v > last)
+            .take(if limit < 0 {
+                usize::MAX
+            } else {
+                limit as usize
+            })
+            .map(|v| v.to_string())
+            .collect();
+        let link = paginate(&mut items, n, "/v2/_catalog");
+        Ok((items, link))
+    }
+
+    #[test]
+    fn test_pagination() {
+        let all = ["a", "b", "c", "d", "e"];
+
+        // Without n everything is returned in one page
+        let (items, link) = fetch_page(&all, None, "").unwrap();
+        assert_eq!(items, all);
+        assert_eq!(link, None);
+
+        let (items, link) = fetch_page(&all, Some(2), "").unwrap();
+        assert_eq!(items, ["a", "b"]);
+        assert_eq!(
+            link.as_deref(),
+            Some("</v2/_catalog?n=2&last=b>; rel=\"next\"")
+        );
+
+        let (items, link) = fetch_page(&all, Some(2), "b").unwrap();
+        assert_eq!(items, ["c", "d"]);
+        assert_eq!(
+            link.as_deref(),
+            Some("</v2/_catalog?n=2&last=d>; rel=\"next\"")
+        );
+
+        // The last page has no link, also when it is exactly full
+        let (items, link) = fetch_page(&all, Some(2), "d").unwrap();
+        assert_eq!(items, ["e"]);
+        assert_eq!(link, None);
+        let (items, link) = fetch_page(&all, Some(5), "").unwrap();
+        assert_eq!(items, all);
+        assert_eq!(link, None);
+
+        // last need not be an existing item
+        let (items, _) = fetch_page(&all, Some(2), "bb").unwrap();
+        assert_eq!(items, ["c", "d"]);
+        let (items, link) = fetch_page(&all, Some(2), "z").unwrap();
+        assert!(items.is_empty());
+        assert_eq!(link, None);
+
+        let (items, link) = fetch_page(&all, Some(0), "").unwrap();
+        assert!(items.is_empty());
+        assert_eq!(link, None);
+
+        assert!(fetch_page(&all, Some(-1), "").is_err());
+    }
+
+    #[test]
+    fn test_referrers_artifact_type_filter() {
+        let signature = "application/vnd.dev.cosign.artifact.sig.v1+json";
+        let sbom = "application/spdx+json";
+        let referrer = |hash: &str, manifest: &str| {
+            let parsed: crate::docker::ManifestOrIndex = serde_json::from_str(manifest).unwrap();
+            ReferrerRow {
+                hash: hash.to_string(),
+                manifest: manifest.to_string(),
+                content_type: None,
+                artifact_type: parsed.artifact_type().map(str::to_string),
+            }
+        };
+        let rows = || {
+            vec![
+                // The artifact type is given explicitly
+                referrer(
+                    "sha256:a",
+                    &format!(
+                        r#"{{"artifactType":"{signature}","config":{{"digest":"sha256:c"}},"layers":[],"annotations":{{"k":"v"}}}}"#
+                    ),
+                ),
+                // The artifact type falls back to the media type of the config
+                referrer(
+                    "sha256:b",
+                    &format!(
+                        r#"{{"config":{{"digest":"sha256:c","mediaType":"{sbom}"}},"layers":[]}}"#
+                    ),
+                ),
+                // The same manifest pushed twice is listed once
+                referrer(
+                    "sha256:b",
+                    &format!(
+                        r#"{{"config":{{"digest":"sha256:c","mediaType":"{sbom}"}},"layers":[]}}"#
+                    ),
+                ),
+                // An index without an artifact type
+                referrer("sha256:d", r#"{"manifests":[]}"#),
+            ]
+        };
+        let digests =
+            |v: Vec<ReferrerDescriptor>| v.into_iter().map(|d| d.digest).collect::<Vec<_>>();
+
+        let all = referrer_descriptors(rows(), None);
+        assert_eq!(all[0].artifact_type.as_deref(), Some(signature));
+        assert_eq!(all[0].annotations, Some(serde_json::json!({"k": "v"})));
+        assert_eq!(all[1].artifact_type.as_deref(), Some(sbom));
+        assert_eq!(
+            all[1].media_type,
+            "application/vnd.oci.image.manifest.v1+json"
+        );
+        assert_eq!(digests(all), ["sha256:a", "sha256:b", "sha256:d"]);
+
+        assert_eq!(
+            digests(referrer_descriptors(rows(), Some(signature))),
+            ["sha256:a"]
+        );
+        assert_eq!(
+            digests(referrer_descriptors(rows(), Some(sbom))),
+            ["sha256:b"]
+        );
+        assert!(referrer_descriptors(rows(), Some("application/unknown")).is_empty());
+    }
+
+    #[test]
+    fn test_immutable_tag() {
+        assert!(check_immutable_tag("p", "v1", "sha256:a", None).is_ok());
+        assert!(check_immutable_tag("p", "v1", "sha256:a", Some("sha256:a")).is_ok());
+        let e = check_immutable_tag("p", "v1", "sha256:b", Some("sha256:a")).unwrap_err();
+        assert_eq!(e.status_code, StatusCode::FORBIDDEN);
+        assert_eq!(
+            serde_json::to_value(&e.errors).unwrap()["errors"][0]["code"],
+            "DENIED"
+        );
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{action_types::IAuthStatus, db::get_user_content, state::State};
+use crate::{
+    action_types::IAuthStatus,
+    db::{get_user_content, project_patterns},
+    state::State,
+};
 use anyhow::{Context, Result};
 use qusql_sqlx_type::query;
 
@@ -23,6 +27,9 @@ pub async fn get_auth(state: &State, host: Option<&str>, sid: Option<&str>) -> R
                 docker_pull: content.docker_pull,
                 docker_push: content.docker_push,
                 session: Some(sid.to_string()),
+                docker_pull_projects: project_patterns(content.docker_pull_projects.as_deref()),
+                docker_push_projects: project_patterns(content.docker_push_projects.as_deref()),
+                docker_deploy_projects: project_patterns(content.docker_deploy_projects.as_deref()),
                 ..Default::default()
             })
         } else {
@@ -136,6 +143,21 @@ pub async fn get_auth(state: &State, host: Option<&str>, sid: Option<&str>) -> R
                 && (content.admin || content.docker_deploy || content.docker_push),
             docker_deploy: pwd && otp && (content.admin || content.docker_deploy),
             sslname: if pwd && otp { content.sslname } else { None },
+            docker_pull_projects: if pwd && otp {
+                project_patterns(content.docker_pull_projects.as_deref())
+            } else {
+                Vec::new()
+            },
+            docker_push_projects: if pwd && otp {
+                project_patterns(content.docker_push_projects.as_deref())
+            } else {
+                Vec::new()
+            },
+            docker_deploy_projects: if pwd && otp {
+                project_patterns(content.docker_deploy_projects.as_deref())
+            } else {
+                Vec::new()
+            },
             session: Some(sid.to_string()),
             auth_days,
             message: None,
@@ -31,7 +31,7 @@ use sadmin2::client_message::{
 
 use crate::{
     action_types::{IHostDown, IHostUp, IObject2, IObjectChanged, IServerAction, ObjectType},
-    crt, crypt, db,
+    crt, crypt, db, modified_files, msg,
     state::{LoginAttempts, State},
     webclient::{self},
 };
@@ -349,6 +349,16 @@ impl HostClient {
                                     }
                                 }
                             }
+                            ClientHostMessage::ServiceFailed{ name, message } => {
+                                error!("Service {name} failed on host {}: {message}", self.hostname);
+                                let host = self.id;
+                                let state = state.clone();
+                                TaskBuilder::new(format!("service_failed_{}_{name}", self.hostname))
+                                    .shutdown_order(-1)
+                                    .create(move |_| async move {
+                                        msg::emit(&state, host, "Service failed".to_string(), message).await
+                                    });
+                            }
                             msg => {
                                 if let Some(id) = msg.job_id() {
                                     if let Some(job) = self.job_sinks.lock().unwrap().get(&id) {
@@ -994,6 +1004,18 @@ async fn handle_host_client(
 
     webclient::broadcast(&state, IServerAction::HostUp(IHostUp { id }))?;
 
+    if state.config.modified_files_scan.on_reconnect && !state.read_only {
+        let state = state.clone();
+        TaskBuilder::new(format!("modified_files_scan_{}", hc.hostname))
+            .shutdown_order(-1)
+            .create(move |rt| async move {
+                if let Ok(Err(e)) = cancelable(&rt, modified_files::scan(&state, Some(id))).await {
+                    error!("Error in modified_files.scan of host {id}: {e:?}");
+                }
+                Ok::<(), ()>(())
+            });
+    }
+
     if let Err(e) = hc
         .clone()
         .handle_messages(state.clone(), &mut reader, buf)
@@ -10,6 +10,7 @@ use docker::docker_prune;
 use hostclient::run_host_server;
 use log::LevelFilter;
 use modified_files::modified_files_scan;
+use scheduler::deployment_scheduler;
 use simple_logger::SimpleLogger;
 use sqlx::ConnectOptions;
 use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode};
@@ -18,6 +19,8 @@ use tokio_tasks::{TaskBuilder, run_tasks, shutdown};
 
 use sadmin2::action_types;
 mod arena;
+mod audit;
+mod blob_storage;
 mod cmpref;
 mod config;
 mod crt;
@@ -25,6 +28,8 @@ mod crypt;
 mod db;
 mod deployment;
 mod docker;
+mod docker_signature;
+mod docker_storage;
 mod docker_web;
 mod get_auth;
 mod hostclient;
@@ -33,6 +38,7 @@ mod msg;
 mod mustache;
 mod ocell;
 mod ordered_json;
+mod scheduler;
 mod setup;
 mod state;
 mod terminal;
@@ -91,17 +97,21 @@ async fn main() -> Result<()> {
     let next_object_id = db::setup(&db).await?;
 
     let docker = docker::Docker::new(&db).await?;
+    let blob_storage = blob_storage::open(&config.blob_storage).await?;
 
     let state = Arc::new(State {
         db,
         config,
         next_object_id: AtomicI64::new(next_object_id),
         modified_files: Default::default(),
+        modified_files_scan: Default::default(),
+        docker_storage: Default::default(),
         deployment: Default::default(),
         docker,
         host_clients: Default::default(),
         web_clients: Default::default(),
         docker_uploads: Default::default(),
+        blob_storage,
         read_only: args.read_only,
         login_attempts: Default::default(),
         otp_failures: Default::default(),
@@ -134,6 +144,12 @@ async fn main() -> Result<()> {
         .shutdown_order(1)
         .create(|rt| run_web_clients(state.clone(), rt));
 
+    if !state.config.scheduled_deployments.is_empty() && !state.read_only {
+        TaskBuilder::new("deployment_scheduler")
+            .shutdown_order(1)
+            .create(|rt| deployment_scheduler(state.clone(), rt));
+    }
+
     if state.config.vanta_client_id.is_some() {
         TaskBuilder::new("vanta_loop")
             .main()
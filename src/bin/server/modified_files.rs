@@ -1,9 +1,9 @@
 use crate::{
     action_types::{
-        IModifiedFilesChanged, IModifiedFilesList, IModifiedFilesResolve, IObject2, IObjectChanged,
-        IServerAction, ModifiedFile,
+        IModifiedFileDrift, IModifiedFilesChanged, IModifiedFilesList, IModifiedFilesResolve,
+        IObject2, IObjectChanged, IServerAction, ModifiedFile, ModifiedFileAttribute,
     },
-    db::{IV, change_object},
+    db::{IV, change_object, get_object_by_id_and_type},
     hostclient::HostClient,
     msg,
     state::State,
@@ -19,9 +19,10 @@ use sadmin2::{
         RunScriptOutType, RunScriptStdinType, SuccessMessage,
     },
     finite_float::ToFinite,
-    type_types::ValueMap,
+    type_types::{DriftPolicy, HOST_ID, IType, TYPE_ID, ValueMap},
 };
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use std::{borrow::Cow, collections::HashMap, sync::Arc, time::Duration};
 use tokio_tasks::{RunToken, cancelable};
 
@@ -35,6 +36,12 @@ struct DeploymentContentFile {
     data: Option<String>,
     #[serde(default)]
     path: Option<String>,
+    #[serde(default)]
+    mode: Option<serde_json::Value>,
+    #[serde(default)]
+    user: Option<String>,
+    #[serde(default)]
+    group: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -62,6 +69,8 @@ struct DeploymentContent<T> {
 struct Prop {
     dead: bool,
     updated: bool,
+    /// Applying the drift policy to the file has failed, and this has been reported
+    remediation_failed: bool,
 }
 
 #[derive(Default)]
@@ -72,11 +81,33 @@ pub struct ModifiedFiles {
     modified_files: Vec<(ModifiedFile, Prop)>,
 }
 
+impl ModifiedFiles {
+    /// Remember that remediating the modified file with the given id failed.
+    /// Returns true only the first time, so the failure is reported once
+    fn note_remediation_failure(&mut self, id: i64) -> bool {
+        let Some((_, p)) = self.modified_files.iter_mut().find(|(f, _)| f.id == id) else {
+            return false;
+        };
+        !std::mem::replace(&mut p.remediation_failed, true)
+    }
+}
+
 #[derive(Deserialize)]
 struct FileContent<'a> {
     #[serde(borrow)]
     path: Cow<'a, str>,
     data: Option<Cow<'a, str>>,
+    hash: Option<Cow<'a, str>>,
+    mode: Option<Cow<'a, str>>,
+    owner: Option<Cow<'a, str>>,
+    group: Option<Cow<'a, str>>,
+}
+
+#[derive(Default)]
+struct Attributes {
+    mode: Option<String>,
+    owner: Option<String>,
+    group: Option<String>,
 }
 
 struct Obj {
@@ -84,6 +115,67 @@ struct Obj {
     r#type: i64,
     data: String,
     object: i64,
+    attributes: Attributes,
+}
+
+/// Normalize a file mode given as octal digits, e.g. "0644" or 644, to the form
+/// reported by the host scan
+fn normalize_mode(mode: &serde_json::Value) -> Option<String> {
+    let mode = match mode {
+        serde_json::Value::String(v) => v.trim().to_string(),
+        serde_json::Value::Number(v) => v.to_string(),
+        _ => return None,
+    };
+    u32::from_str_radix(&mode, 8).ok().map(|v| format!("{v:o}"))
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Find the attributes of a file on a host that differ from what was deployed
+fn find_drift(obj: &Obj, content: &FileContent) -> Vec<IModifiedFileDrift> {
+    let mut drift = Vec::new();
+    let deployed_hash = sha256_hex(obj.data.as_bytes());
+    let actual_hash = match &content.hash {
+        Some(v) => v.to_string(),
+        None => sha256_hex(b""),
+    };
+    if deployed_hash != actual_hash {
+        drift.push(IModifiedFileDrift {
+            attribute: ModifiedFileAttribute::Content,
+            deployed: deployed_hash,
+            actual: actual_hash,
+        });
+    }
+    for (attribute, deployed, actual) in [
+        (
+            ModifiedFileAttribute::Mode,
+            &obj.attributes.mode,
+            &content.mode,
+        ),
+        (
+            ModifiedFileAttribute::Owner,
+            &obj.attributes.owner,
+            &content.owner,
+        ),
+        (
+            ModifiedFileAttribute::Group,
+            &obj.attributes.group,
+            &content.group,
+        ),
+    ] {
+        if let (Some(deployed), Some(actual)) = (deployed, actual)
+            && deployed != actual
+        {
+            drift.push(IModifiedFileDrift {
+                attribute,
+                deployed: deployed.clone(),
+                actual: actual.to_string(),
+            });
+        }
+    }
+    drift
 }
 
 async fn broadcast_changes(state: &State) -> Result<()> {
@@ -116,25 +208,27 @@ async fn broadcast_changes(state: &State) -> Result<()> {
     Ok(())
 }
 
-pub async fn scan(state: &State) -> Result<()> {
+/// Scan all hosts, or only the given host, for modified files
+pub async fn scan(state: &State, host: Option<i64>) -> Result<()> {
+    // Queue behind a running scan rather than dropping the request, a scan of a
+    // single host on reconnect must not be lost to a periodic scan of all hosts
+    let _scan = state.modified_files_scan.lock().await;
     let orig_last_scan_time = {
         let mut content = state.modified_files.lock().unwrap();
-        if content.scanning {
-            return Ok(());
-        }
-
         let orig_last_scan_time = content.last_scan;
         content.scanning = true;
 
-        content.last_scan = Some(
-            std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .context("Bad unix time")?
-                .as_secs_f64(),
-        );
+        if host.is_none() {
+            content.last_scan = Some(
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .context("Bad unix time")?
+                    .as_secs_f64(),
+            );
+        }
         orig_last_scan_time
     };
-    match scan_inner(state).await {
+    match scan_inner(state, host).await {
         Ok(v) => Ok(v),
         Err(e) => {
             {
@@ -148,7 +242,7 @@ pub async fn scan(state: &State) -> Result<()> {
     }
 }
 
-pub async fn scan_inner(state: &State) -> Result<()> {
+pub async fn scan_inner(state: &State, host: Option<i64>) -> Result<()> {
     info!("Scanning for modified files");
     broadcast_changes(state).await?;
 
@@ -167,12 +261,21 @@ pub async fn scan_inner(state: &State) -> Result<()> {
     // to the type somehow
     let mut objects: HashMap<_, Vec<_>> = HashMap::new();
     for row in rows {
+        if host.is_some_and(|h| h != row.host) {
+            continue;
+        }
+        let mut attributes = Attributes::default();
         let (data, path, object) = match row.r#type {
             FILE_ID => {
                 let content: DeploymentContent<Option<DeploymentContentFile>> =
                     serde_json::from_str(&row.content)
                         .with_context(|| format!("Unable to parse file content {}", row.content))?;
                 let Some(c) = content.content else { continue };
+                attributes = Attributes {
+                    mode: c.mode.as_ref().and_then(normalize_mode),
+                    owner: c.user.filter(|v| !v.is_empty()),
+                    group: c.group.filter(|v| !v.is_empty()),
+                };
                 (c.data, c.path, content.object)
             }
             SYSTEMD_SERVICE_ID => {
@@ -204,6 +307,7 @@ pub async fn scan_inner(state: &State) -> Result<()> {
             r#type: row.r#type,
             data,
             object,
+            attributes,
         });
     }
 
@@ -243,7 +347,8 @@ pub async fn scan_inner(state: &State) -> Result<()> {
                 if obj.path != content.path {
                     bail!("Paths do no match {} vs {}", obj.path, content.path);
                 }
-                if obj.data == content.data.as_deref().unwrap_or_default() {
+                let drift = find_drift(&obj, content);
+                if drift.is_empty() {
                     continue;
                 }
                 modified.insert(
@@ -253,6 +358,7 @@ pub async fn scan_inner(state: &State) -> Result<()> {
                         obj.r#type,
                         obj.object,
                         content.data.as_deref().unwrap_or_default().to_string(),
+                        drift,
                     ),
                 );
             }
@@ -260,7 +366,8 @@ pub async fn scan_inner(state: &State) -> Result<()> {
                 if m.host != host {
                     continue;
                 }
-                let Some((deployed, r#type, object, actual)) = modified.remove(&m.path) else {
+                let Some((deployed, r#type, object, actual, drift)) = modified.remove(&m.path)
+                else {
                     alter(&mut p.dead, true, &mut p.updated);
                     continue;
                 };
@@ -269,8 +376,9 @@ pub async fn scan_inner(state: &State) -> Result<()> {
                 alter(&mut m.deployed, deployed, &mut p.updated);
                 alter(&mut m.object, object, &mut p.updated);
                 alter(&mut m.r#type, r#type, &mut p.updated);
+                alter(&mut m.drift, drift, &mut p.updated);
             }
-            for (path, (deployed, r#type, object, actual)) in modified.into_iter() {
+            for (path, (deployed, r#type, object, actual, drift)) in modified.into_iter() {
                 messages.push((
                     host,
                     format!("The file {path} has been modified since it was deployed"),
@@ -287,10 +395,12 @@ pub async fn scan_inner(state: &State) -> Result<()> {
                         actual,
                         current: None,
                         path,
+                        drift,
                     },
                     Prop {
                         dead: false,
                         updated: true,
+                        remediation_failed: false,
                     },
                 ));
             }
@@ -338,6 +448,310 @@ pub async fn scan_inner(state: &State) -> Result<()> {
     }
     broadcast_changes(state).await?;
 
+    remediate(state, host).await?;
+
+    Ok(())
+}
+
+fn python_str(v: Option<&str>) -> Result<String> {
+    Ok(match v {
+        Some(v) => serde_json::to_string(v)?,
+        None => "None".to_string(),
+    })
+}
+
+fn content_key(r#type: i64) -> Result<&'static str> {
+    Ok(match r#type {
+        FILE_ID => "data",
+        SYSTEMD_SERVICE_ID => "unit",
+        CRON_ID => "script",
+        _ => bail!("Unknown object type"),
+    })
+}
+
+fn mark_resolved(state: &State, id: i64) {
+    for (f, p) in &mut state.modified_files.lock().unwrap().modified_files {
+        if f.id == id {
+            p.dead = true;
+            p.updated = true;
+        }
+    }
+}
+
+fn deployed_attribute(f: &ModifiedFile, attribute: ModifiedFileAttribute) -> Option<&str> {
+    f.drift
+        .iter()
+        .find(|d| d.attribute == attribute)
+        .map(|d| d.deployed.as_str())
+}
+
+/// Write the deployed content, mode and ownership back to a modified file
+async fn redeploy_file(state: &State, f: &ModifiedFile) -> Result<()> {
+    let Some(host) = state.host_clients.lock().unwrap().get(&f.host).cloned() else {
+        bail!("Host is not up");
+    };
+
+    let script = format!(
+        "
+import os, shutil, stat
+path = {}
+with open(path, 'w', encoding='utf-8') as f:
+  f.write({})
+mode = {}
+mode = int(mode, 8) if mode is not None else stat.S_IMODE(os.stat(path).st_mode)
+owner, group = {}, {}
+if owner is not None or group is not None:
+  shutil.chown(path, owner, group)
+# chown clears the setuid and setgid bits, so the mode is set after it
+os.chmod(path, mode)
+",
+        serde_json::to_string(&f.path)?,
+        serde_json::to_string(&f.deployed)?,
+        python_str(deployed_attribute(f, ModifiedFileAttribute::Mode))?,
+        python_str(deployed_attribute(f, ModifiedFileAttribute::Owner))?,
+        python_str(deployed_attribute(f, ModifiedFileAttribute::Group))?,
+    );
+
+    let mut jh = host
+        .start_job(&HostClientMessage::RunScript(RunScriptMessage {
+            id: host.next_job_id(),
+            name: "revert.py".to_string(),
+            interperter: "/usr/bin/python3".to_string(),
+            content: script.to_string(),
+            args: Vec::new(),
+            input_json: None,
+            stdin_type: Some(RunScriptStdinType::None),
+            stdout_type: Some(RunScriptOutType::None),
+            stderr_type: Some(RunScriptOutType::Text),
+        }))
+        .await?;
+
+    match jh.next_message().await? {
+        Some(ClientHostMessage::Success(SuccessMessage { code, .. })) => {
+            jh.done();
+            if let Some(code) = code
+                && code != 0
+            {
+                bail!("Resolve job failed with code {}", code);
+            }
+        }
+        Some(ClientHostMessage::Failure(FailureMessage { .. })) => {
+            jh.done();
+            bail!("Failure in resolve job")
+        }
+        Some(ClientHostMessage::Data(m)) => {
+            info!("Unexpected data in resolve {m:?}");
+        }
+        Some(msg) => {
+            bail!("Got unknown message {} in resolve", msg.tag());
+        }
+        None => {
+            bail!("Host dissapeared")
+        }
+    }
+
+    mark_resolved(state, f.id);
+    broadcast_changes(state).await?;
+    Ok(())
+}
+
+/// Store new values for keys in the current content of the object of a modified file
+async fn update_current(
+    state: &State,
+    f: &ModifiedFile,
+    updates: &[(&str, String)],
+    author: &str,
+) -> Result<()> {
+    let r = query!(
+        "SELECT `id`, `version`, `type`, `name`, `content`, `category`, `comment`,
+        strftime('%s', `time`) AS `time`, `author` FROM `objects`
+        WHERE `id`=? AND `newest`",
+        f.object
+    )
+    .fetch_one(&state.db)
+    .await?;
+
+    let mut obj = IObject2::<ValueMap> {
+        id: f.object,
+        r#type: r.r#type.try_into()?,
+        name: r.name,
+        category: r.category.unwrap_or_default(),
+        content: serde_json::from_str(&r.content)?,
+        version: Some(r.version),
+        comment: r.comment,
+        author: r.author,
+        time: Some(r.time.parse()?),
+    };
+
+    for (key, value) in updates {
+        obj.content
+            .insert(key.to_string(), serde_json::Value::String(value.clone()));
+    }
+
+    let IV { id, version } = change_object(state, f.object, Some(&obj), author).await?;
+    obj.version = Some(version);
+    webclient::broadcast(
+        state,
+        IServerAction::ObjectChanged(IObjectChanged {
+            id,
+            object: vec![obj],
+        }),
+    )?;
+    Ok(())
+}
+
+/// Find the changes to the object of a modified file needed to adopt the file as is
+fn adopt_updates(f: &ModifiedFile) -> Result<Vec<(&'static str, String)>> {
+    if f.current.as_ref() != Some(&f.deployed) {
+        bail!("The object has been changed since it was deployed");
+    }
+    let mut updates = Vec::new();
+    for d in &f.drift {
+        match d.attribute {
+            ModifiedFileAttribute::Content => {
+                if sha256_hex(f.actual.as_bytes()) != d.actual {
+                    bail!("Binary content can not be adopted");
+                }
+                updates.push((content_key(f.r#type)?, f.actual.clone()));
+            }
+            ModifiedFileAttribute::Mode => updates.push(("mode", d.actual.clone())),
+            ModifiedFileAttribute::Owner => updates.push(("user", d.actual.clone())),
+            ModifiedFileAttribute::Group => updates.push(("group", d.actual.clone())),
+        }
+    }
+    Ok(updates)
+}
+
+/// Compute the new deployed content of the rows in `deployments` holding the object
+/// of a modified file. Adopting is refused if the object is also deployed to other
+/// hosts, as they would no longer match what the object describes
+fn adopt_deployments(
+    f: &ModifiedFile,
+    rows: impl IntoIterator<Item = (i64, String, String)>,
+    updates: &[(&str, String)],
+) -> Result<Vec<(String, String)>> {
+    let mut ans = Vec::new();
+    for (host, name, content) in rows {
+        let mut content: ValueMap = serde_json::from_str(&content)?;
+        if content.get("object").and_then(|v| v.as_i64()) != Some(f.object) {
+            continue;
+        }
+        if host != f.host {
+            bail!("The object is also deployed to host {host}");
+        }
+        if let Some(serde_json::Value::Object(c)) = content.get_mut("content") {
+            for (key, value) in updates {
+                c.insert(key.to_string(), serde_json::Value::String(value.clone()));
+            }
+        }
+        ans.push((name, serde_json::to_string(&content)?));
+    }
+    Ok(ans)
+}
+
+/// Make the content of a modified file the current and deployed content of its object
+async fn adopt_file(state: &State, f: &ModifiedFile) -> Result<()> {
+    let updates = adopt_updates(f)?;
+    let rows = query!(
+        "SELECT `host`, `name`, `content` FROM `deployments` WHERE `type`=?",
+        f.r#type
+    )
+    .fetch_all(&state.db)
+    .await?;
+    let deployments = adopt_deployments(
+        f,
+        rows.into_iter().map(|r| (r.host, r.name, r.content)),
+        &updates,
+    )?;
+
+    update_current(state, f, &updates, "drift policy").await?;
+
+    // Record the adopted content as deployed, so the file is no longer seen as modified
+    for (name, content) in deployments {
+        query!(
+            "UPDATE `deployments` SET `content`=? WHERE `host`=? AND `name`=?",
+            content,
+            f.host,
+            name
+        )
+        .execute(&state.db)
+        .await?;
+    }
+
+    mark_resolved(state, f.id);
+    broadcast_changes(state).await?;
+    Ok(())
+}
+
+/// Find the drift policy for a modified file, the policy of the host takes precedence
+/// over the policy of the object type
+async fn drift_policy(state: &State, f: &ModifiedFile) -> Result<DriftPolicy> {
+    if let Some(host) = get_object_by_id_and_type::<ValueMap>(state, f.host, HOST_ID).await?
+        && let Some(policy) = host.content.get("driftPolicy")
+        && !policy.is_null()
+    {
+        return serde_json::from_value(policy.clone())
+            .with_context(|| format!("Invalid driftPolicy on host {}", host.name));
+    }
+    Ok(get_object_by_id_and_type::<IType>(state, f.r#type, TYPE_ID)
+        .await?
+        .and_then(|t| t.content.drift_policy)
+        .unwrap_or(DriftPolicy::Alert))
+}
+
+/// Apply the drift policies to the modified files of the scanned host, or all hosts,
+/// logging every automatic action
+async fn remediate(state: &State, host: Option<i64>) -> Result<()> {
+    let files: Vec<_> = state
+        .modified_files
+        .lock()
+        .unwrap()
+        .modified_files
+        .iter()
+        .filter(|(f, p)| !p.dead && host.is_none_or(|h| h == f.host))
+        .map(|(f, _)| f.clone())
+        .collect();
+    for f in files {
+        let (message, failed) = match drift_policy(state, &f).await {
+            Ok(DriftPolicy::Alert) => continue,
+            Ok(DriftPolicy::Redeploy) => match redeploy_file(state, &f).await {
+                Ok(()) => (format!("Redeployed the modified file {}", f.path), false),
+                Err(e) => (
+                    format!("Unable to redeploy the modified file {}: {e:?}", f.path),
+                    true,
+                ),
+            },
+            Ok(DriftPolicy::Adopt) => match adopt_file(state, &f).await {
+                Ok(()) => (
+                    format!(
+                        "Adopted the modified file {} into object {}",
+                        f.path, f.object
+                    ),
+                    false,
+                ),
+                Err(e) => (
+                    format!("Unable to adopt the modified file {}: {e:?}", f.path),
+                    true,
+                ),
+            },
+            Err(e) => (
+                format!("Unable to apply drift policy to {}: {e:?}", f.path),
+                true,
+            ),
+        };
+        info!("{message}");
+        // Retry failed remediations on every scan, but only report the first failure
+        if failed
+            && !state
+                .modified_files
+                .lock()
+                .unwrap()
+                .note_remediation_failure(f.id)
+        {
+            continue;
+        }
+        msg::emit(state, f.host, "Drift remediation".to_string(), message).await?;
+    }
     Ok(())
 }
 
@@ -357,113 +771,19 @@ pub async fn resolve(state: &State, client: &WebClient, act: IModifiedFilesResol
 
     match act.action {
         crate::action_types::IModifiedFilesResolveAction::Redeploy => {
-            let Some(host) = state.host_clients.lock().unwrap().get(&f.host).cloned() else {
-                bail!("Host is not up");
-            };
-
-            let script = format!(
-                "
-with open({}, 'w', encoding='utf-8') as f:
-  f.write({})
-",
-                serde_json::to_string(&f.path)?,
-                serde_json::to_string(&f.deployed)?
-            );
-
-            let mut jh = host
-                .start_job(&HostClientMessage::RunScript(RunScriptMessage {
-                    id: host.next_job_id(),
-                    name: "revert.py".to_string(),
-                    interperter: "/usr/bin/python3".to_string(),
-                    content: script.to_string(),
-                    args: Vec::new(),
-                    input_json: None,
-                    stdin_type: Some(RunScriptStdinType::None),
-                    stdout_type: Some(RunScriptOutType::None),
-                    stderr_type: Some(RunScriptOutType::Text),
-                }))
-                .await?;
-
-            match jh.next_message().await? {
-                Some(ClientHostMessage::Success(SuccessMessage { code, .. })) => {
-                    jh.done();
-                    if let Some(code) = code
-                        && code != 0
-                    {
-                        bail!("Resolve job failed with code {}", code);
-                    }
-                }
-                Some(ClientHostMessage::Failure(FailureMessage { .. })) => {
-                    jh.done();
-                    bail!("Failure in resolve job")
-                }
-                Some(ClientHostMessage::Data(m)) => {
-                    info!("Unexpected data in resolve {m:?}");
-                }
-                Some(msg) => {
-                    bail!("Got unknown message {} in resolve", msg.tag());
-                }
-                None => {
-                    bail!("Host dissapeared")
-                }
-            }
-
-            for (f, p) in &mut state.modified_files.lock().unwrap().modified_files {
-                if f.id == act.id {
-                    p.dead = true;
-                    p.updated = true;
-                }
-            }
-            broadcast_changes(state).await?;
+            redeploy_file(state, &f).await?;
         }
         crate::action_types::IModifiedFilesResolveAction::UpdateCurrent => {
-            let r = query!(
-                "SELECT `id`, `version`, `type`, `name`, `content`, `category`, `comment`,
-                strftime('%s', `time`) AS `time`, `author` FROM `objects`
-                WHERE `id`=? AND `newest`",
-                f.object
-            )
-            .fetch_one(&state.db)
-            .await?;
-
-            let mut obj = IObject2::<ValueMap> {
-                id: f.object,
-                r#type: r.r#type.try_into()?,
-                name: r.name,
-                category: r.category.unwrap_or_default(),
-                content: serde_json::from_str(&r.content)?,
-                version: Some(r.version),
-                comment: r.comment,
-                author: r.author,
-                time: Some(r.time.parse()?),
-            };
-
-            let key = match f.r#type {
-                FILE_ID => "data",
-                SYSTEMD_SERVICE_ID => "unit",
-                CRON_ID => "script",
-                _ => bail!("Unknown object type"),
-            };
-            obj.content.insert(
-                key.to_string(),
-                serde_json::Value::String(act.new_current.context("Missing new current")?),
-            );
-
-            let IV { id, version } = change_object(
+            update_current(
                 state,
-                f.object,
-                Some(&obj),
+                &f,
+                &[(
+                    content_key(f.r#type)?,
+                    act.new_current.context("Missing new current")?,
+                )],
                 client.get_auth().user.as_deref().context("Missing user")?,
             )
             .await?;
-            obj.version = Some(version);
-            webclient::broadcast(
-                state,
-                IServerAction::ObjectChanged(IObjectChanged {
-                    id,
-                    object: vec![obj],
-                }),
-            )?;
         }
     }
 
@@ -521,16 +841,31 @@ async fn run_host_scan_job(
 
 async fn run_host_scan_job_inner(host: Arc<HostClient>, paths: Vec<String>) -> Result<String> {
     let script = "
-import sys, base64, json
+import sys, json, os, hashlib, pwd, grp
 ans = []
 for path in sys.argv[1:]:
-    data = None
+    data = hash = mode = owner = group = None
     try:
         with open(path, 'rb') as f:
-            data = f.read().decode('utf-8')
+            raw = f.read()
+        hash = hashlib.sha256(raw).hexdigest()
+        try:
+            data = raw.decode('utf-8')
+        except UnicodeDecodeError:
+            pass
+        st = os.stat(path)
+        mode = '%o' % (st.st_mode & 0o7777)
+        try:
+            owner = pwd.getpwuid(st.st_uid).pw_name
+        except KeyError:
+            owner = str(st.st_uid)
+        try:
+            group = grp.getgrgid(st.st_gid).gr_name
+        except KeyError:
+            group = str(st.st_gid)
     except OSError:
         pass
-    ans.append({'path': path, 'data': data})
+    ans.append({'path': path, 'data': data, 'hash': hash, 'mode': mode, 'owner': owner, 'group': group})
 sys.stdout.write(json.dumps(ans, indent=2))
 sys.stdout.flush()";
     let mut jh = host
@@ -580,14 +915,18 @@ sys.stdout.flush()";
 }
 
 pub async fn modified_files_scan(state: Arc<State>, run_token: RunToken) -> Result<()> {
-    if cancelable(&run_token, tokio::time::sleep(Duration::from_secs(2 * 60)))
-        .await
-        .is_err()
+    let config = &state.config.modified_files_scan;
+    if cancelable(
+        &run_token,
+        tokio::time::sleep(Duration::from_secs(config.initial_delay_seconds)),
+    )
+    .await
+    .is_err()
     {
         return Ok(());
     }
     loop {
-        match cancelable(&run_token, scan(&state)).await {
+        match cancelable(&run_token, scan(&state, None)).await {
             Ok(Ok(())) => (),
             Ok(Err(e)) => {
                 error!("Error in modified_files.scan {e:?}");
@@ -596,7 +935,7 @@ pub async fn modified_files_scan(state: Arc<State>, run_token: RunToken) -> Resu
         }
         if cancelable(
             &run_token,
-            tokio::time::sleep(Duration::from_secs(60 * 60 * 12)),
+            tokio::time::sleep(Duration::from_secs(config.interval_seconds)),
         )
         .await
         .is_err()
@@ -606,3 +945,172 @@ pub async fn modified_files_scan(state: Arc<State>, run_token: RunToken) -> Resu
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modified_file(drift: Vec<IModifiedFileDrift>) -> ModifiedFile {
+        ModifiedFile {
+            id: 1,
+            r#type: FILE_ID,
+            host: 2,
+            object: 3,
+            deployed: "old\n".into(),
+            actual: "new\n".into(),
+            current: Some("old\n".into()),
+            path: "/etc/motd".into(),
+            drift,
+        }
+    }
+
+    fn content_drift(actual: &str) -> IModifiedFileDrift {
+        IModifiedFileDrift {
+            attribute: ModifiedFileAttribute::Content,
+            deployed: sha256_hex(b"old\n"),
+            actual: actual.into(),
+        }
+    }
+
+    #[test]
+    fn test_remediation_failure_reported_once() {
+        let mut m = ModifiedFiles::default();
+        m.modified_files.push((
+            modified_file(Vec::new()),
+            Prop {
+                dead: false,
+                updated: false,
+                remediation_failed: false,
+            },
+        ));
+        assert!(m.note_remediation_failure(1));
+        assert!(!m.note_remediation_failure(1));
+        assert!(!m.note_remediation_failure(2));
+    }
+
+    #[test]
+    fn test_adopt_updates() {
+        let f = modified_file(vec![
+            content_drift(&sha256_hex(b"new\n")),
+            IModifiedFileDrift {
+                attribute: ModifiedFileAttribute::Mode,
+                deployed: "644".into(),
+                actual: "600".into(),
+            },
+        ]);
+        assert_eq!(
+            adopt_updates(&f).unwrap(),
+            vec![("data", "new\n".to_string()), ("mode", "600".to_string())]
+        );
+
+        // Binary content is reported by hash only
+        let f = modified_file(vec![content_drift("0000")]);
+        assert!(adopt_updates(&f).is_err());
+
+        let mut f = modified_file(vec![content_drift(&sha256_hex(b"new\n"))]);
+        f.current = Some("changed\n".into());
+        assert!(adopt_updates(&f).is_err());
+    }
+
+    #[test]
+    fn test_adopt_deployments() {
+        let f = modified_file(Vec::new());
+        let row = |host: i64, name: &str, object: i64| {
+            (
+                host,
+                name.to_string(),
+                format!(r#"{{"object":{object},"content":{{"data":"old\n","path":"/etc/motd"}}}}"#),
+            )
+        };
+        let updates = [("data", "new\n".to_string())];
+
+        let ans = adopt_deployments(&f, [row(2, "a", 3), row(2, "b", 4)], &updates).unwrap();
+        assert_eq!(ans.len(), 1);
+        assert_eq!(ans[0].0, "a");
+        let content: serde_json::Value = serde_json::from_str(&ans[0].1).unwrap();
+        assert_eq!(content["content"]["data"], "new\n");
+        assert_eq!(content["content"]["path"], "/etc/motd");
+
+        // The object is shared with another host
+        assert!(adopt_deployments(&f, [row(2, "a", 3), row(5, "a", 3)], &updates).is_err());
+        // Other objects on other hosts do not matter
+        assert!(adopt_deployments(&f, [row(2, "a", 3), row(5, "a", 4)], &updates).is_ok());
+    }
+
+    fn motd_obj(mode: Option<&str>, owner: Option<&str>) -> Obj {
+        Obj {
+            path: "/etc/motd".into(),
+            r#type: FILE_ID,
+            data: "hello\n".into(),
+            object: 3,
+            attributes: Attributes {
+                mode: mode.map(|v| v.to_string()),
+                owner: owner.map(|v| v.to_string()),
+                group: None,
+            },
+        }
+    }
+
+    fn scanned(raw: &[u8], mode: &str, owner: &str) -> FileContent<'static> {
+        FileContent {
+            path: "/etc/motd".into(),
+            data: std::str::from_utf8(raw).ok().map(|v| v.to_string().into()),
+            hash: Some(sha256_hex(raw).into()),
+            mode: Some(mode.to_string().into()),
+            owner: Some(owner.to_string().into()),
+            group: Some("root".into()),
+        }
+    }
+
+    #[test]
+    fn test_normalize_mode() {
+        assert_eq!(normalize_mode(&"0644".into()).as_deref(), Some("644"));
+        assert_eq!(normalize_mode(&644.into()).as_deref(), Some("644"));
+        assert_eq!(normalize_mode(&" 4755 ".into()).as_deref(), Some("4755"));
+        assert_eq!(normalize_mode(&"rw-r--r--".into()), None);
+        assert_eq!(normalize_mode(&serde_json::Value::Null), None);
+    }
+
+    #[test]
+    fn test_find_drift() {
+        let attributes = |drift: Vec<IModifiedFileDrift>| {
+            drift.into_iter().map(|d| d.attribute).collect::<Vec<_>>()
+        };
+        let obj = motd_obj(Some("644"), Some("root"));
+        assert!(find_drift(&obj, &scanned(b"hello\n", "644", "root")).is_empty());
+
+        let drift = find_drift(&obj, &scanned(b"hello\n", "600", "www-data"));
+        assert_eq!(
+            attributes(drift.clone()),
+            [ModifiedFileAttribute::Mode, ModifiedFileAttribute::Owner]
+        );
+        assert_eq!(drift[0].deployed, "644");
+        assert_eq!(drift[0].actual, "600");
+        assert_eq!(drift[1].actual, "www-data");
+
+        // Attributes not given when deploying are not compared
+        let obj = motd_obj(None, None);
+        assert!(find_drift(&obj, &scanned(b"hello\n", "600", "www-data")).is_empty());
+
+        // Content that is not utf-8 is detected by its hash
+        let content = scanned(b"hello\xff\n", "644", "root");
+        assert!(content.data.is_none());
+        let drift = find_drift(&obj, &content);
+        assert_eq!(attributes(drift.clone()), [ModifiedFileAttribute::Content]);
+        assert_eq!(drift[0].deployed, sha256_hex(b"hello\n"));
+        assert_eq!(drift[0].actual, sha256_hex(b"hello\xff\n"));
+
+        // A missing file has no hash, and is compared as empty
+        let missing = FileContent {
+            path: "/etc/motd".into(),
+            data: None,
+            hash: None,
+            mode: None,
+            owner: None,
+            group: None,
+        };
+        let drift = find_drift(&obj, &missing);
+        assert_eq!(attributes(drift.clone()), [ModifiedFileAttribute::Content]);
+        assert_eq!(drift[0].actual, sha256_hex(b""));
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local};
+use log::{error, info, warn};
+use tokio_tasks::{RunToken, TaskBuilder, cancelable};
+
+use crate::{
+    action_types::DeploymentObjectStatus, config::ScheduledDeployment, deployment, msg,
+    state::State,
+};
+
+/// When a scheduled deployment should run
+enum Schedule {
+    Cron(Box<cron::Schedule>),
+    At(DateTime<Local>),
+}
+
+/// Parse the cron expression or time of a scheduled deployment
+fn parse_schedule(scheduled: &ScheduledDeployment) -> Result<Schedule> {
+    if let Some(expr) = &scheduled.cron {
+        let schedule = cron::Schedule::from_str(expr)
+            .with_context(|| format!("Invalid cron expression for {}", scheduled.name))?;
+        return Ok(Schedule::Cron(Box::new(schedule)));
+    }
+    if let Some(at) = &scheduled.at {
+        let at = DateTime::parse_from_rfc3339(at)
+            .with_context(|| format!("Invalid time for {}", scheduled.name))?
+            .with_timezone(&Local);
+        return Ok(Schedule::At(at));
+    }
+    bail!(
+        "Scheduled deployment {} has neither cron nor at",
+        scheduled.name
+    )
+}
+
+/// Find the first time after `after` the scheduled deployment should run
+fn next_time(schedule: &Schedule, after: DateTime<Local>) -> Option<DateTime<Local>> {
+    match schedule {
+        Schedule::Cron(schedule) => schedule.after(&after).next(),
+        Schedule::At(at) => (*at > after).then_some(*at),
+    }
+}
+
+async fn run_scheduled(
+    rt: &RunToken,
+    state: &State,
+    scheduled: &ScheduledDeployment,
+) -> Result<()> {
+    info!("Starting scheduled deployment {}", scheduled.name);
+    let Some(objects) = deployment::deploy_unattended(
+        rt,
+        state,
+        format!("schedule:{}", scheduled.name),
+        &scheduled.hosts,
+        &scheduled.objects,
+        scheduled.redeploy,
+    )
+    .await?
+    else {
+        warn!(
+            "Skipping scheduled deployment {}, another deployment is in progress",
+            scheduled.name
+        );
+        return Ok(());
+    };
+
+    // Post the outcome for each host deployed to
+    let mut hosts: BTreeMap<i64, (String, usize, usize)> = BTreeMap::new();
+    for o in objects {
+        let (_, success, failure) = hosts.entry(o.host).or_insert((o.host_name, 0, 0));
+        match o.status {
+            DeploymentObjectStatus::Success => *success += 1,
+            DeploymentObjectStatus::Failure => *failure += 1,
+            _ => (),
+        }
+    }
+    for (host, (host_name, success, failure)) in hosts {
+        let message = if failure == 0 {
+            format!(
+                "Scheduled deployment {} deployed {} objects to {}",
+                scheduled.name, success, host_name
+            )
+        } else {
+            format!(
+                "Scheduled deployment {} failed to deploy {} of {} objects to {}",
+                scheduled.name,
+                failure,
+                success + failure,
+                host_name
+            )
+        };
+        msg::emit(state, host, "Scheduled deployment".to_string(), message).await?;
+    }
+    info!("Scheduled deployment {} done", scheduled.name);
+    Ok(())
+}
+
+pub async fn deployment_scheduler(state: Arc<State>, run_token: RunToken) -> Result<()> {
+    // Validate the schedules up front, a bad entry is skipped rather than stopping the others
+    let mut schedules = Vec::new();
+    for (i, scheduled) in state.config.scheduled_deployments.iter().enumerate() {
+        match parse_schedule(scheduled) {
+            Ok(schedule) => schedules.push((i, schedule)),
+            Err(e) => error!("Skipping scheduled deployment: {e:?}"),
+        }
+    }
+    let mut next: Vec<_> = schedules
+        .iter()
+        .map(|(_, schedule)| next_time(schedule, Local::now()))
+        .collect();
+    while let Some((j, time)) = next
+        .iter()
+        .enumerate()
+        .filter_map(|(j, t)| t.map(|t| (j, t)))
+        .min_by_key(|(_, t)| *t)
+    {
+        let wait = (time - Local::now()).to_std().unwrap_or_default();
+        if cancelable(&run_token, tokio::time::sleep(wait))
+            .await
+            .is_err()
+        {
+            return Ok(());
+        }
+        let (i, schedule) = &schedules[j];
+        let i = *i;
+        let name = &state.config.scheduled_deployments[i].name;
+
+        // Run the deployment under its own run token, so stopping it from
+        // the ui only stops this run and not the scheduler
+        let s = state.clone();
+        let task = TaskBuilder::new(format!("scheduled_deployment_{name}"))
+            .shutdown_order(-1)
+            .create(move |rt| async move {
+                run_scheduled(&rt, &s, &s.config.scheduled_deployments[i]).await
+            });
+        match cancelable(&run_token, task.clone().wait()).await {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => error!("Error in scheduled deployment {name}: {e:?}"),
+            Err(_) => {
+                task.cancel().await;
+                return Ok(());
+            }
+        }
+        next[j] = next_time(schedule, Local::now());
+    }
+    // Nothing is left to schedule, stay alive until we are asked to stop
+    run_token.cancelled().await;
+    Ok(())
+}
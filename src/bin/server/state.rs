@@ -1,7 +1,9 @@
+use crate::blob_storage::BlobStorage;
 use crate::cmpref::CmpRef;
 use crate::config::Config;
 use crate::deployment::Deployment;
 use crate::docker::Docker;
+use crate::docker_storage::StorageUsage;
 use crate::docker_web;
 use crate::hostclient::HostClient;
 use crate::modified_files::ModifiedFiles;
@@ -29,11 +31,16 @@ pub struct State {
     pub config: Config,
     pub next_object_id: AtomicI64,
     pub modified_files: Mutex<ModifiedFiles>,
+    /// Held while scanning for modified files, so scans requested meanwhile wait their turn
+    pub modified_files_scan: tokio::sync::Mutex<()>,
     pub deployment: Mutex<Deployment>,
     pub docker: Docker,
+    /// The registry storage used by each project
+    pub docker_storage: Mutex<StorageUsage>,
     pub host_clients: Mutex<HashMap<i64, Arc<HostClient>>>,
     pub web_clients: Mutex<HashSet<CmpRef<Arc<WebClient>>>>,
     pub docker_uploads: Mutex<HashMap<Uuid, Arc<docker_web::Upload>>>,
+    pub blob_storage: Box<dyn BlobStorage>,
     pub read_only: bool,
     /// IP-address -> rate-limit state, used to enforce exponential backoff on
     /// failed login attempts without locking accounts (which would allow DoS).
@@ -18,6 +18,8 @@ use sadmin2::client_message::{
 use serde::Deserialize;
 
 use crate::{
+    action_types::AuditOutcome,
+    audit::{self, AuditRecord},
     get_auth::get_auth,
     hostclient::HostClient,
     state::State,
@@ -182,7 +184,18 @@ pub async fn handler(
     }): Query<TerminalQuery>,
 ) -> Result<Response, WebError> {
     let auth = get_auth(&state, Some(&remote), Some(&session)).await?;
+    let host = audit::host_name(&state, server).await?;
+    let mut record = AuditRecord {
+        user: auth.user.as_deref(),
+        remote: &remote,
+        action: "Terminal",
+        host: Some(&host),
+        outcome: AuditOutcome::Success,
+        details: None,
+    };
     if !auth.admin {
+        record.outcome = AuditOutcome::Denied;
+        audit::record(&state.db, record).await?;
         return Err(WebError::forbidden());
     }
     let Some(host_client) = state
@@ -192,8 +205,12 @@ pub async fn handler(
         .get(&server)
         .map(Arc::downgrade)
     else {
+        record.outcome = AuditOutcome::Failure;
+        record.details = Some("Host not connected".to_string());
+        audit::record(&state.db, record).await?;
         return Err(WebError::not_found());
     };
+    audit::record(&state.db, record).await?;
     Ok(ws.on_upgrade(move |socket| async move {
         if let Err(e) = inner(socket, host_client, cols, rows).await {
             error!("Error in handle_terminal_inner: {e}");
@@ -20,21 +20,26 @@ use tokio_tungstenite::tungstenite;
 
 use crate::{
     action_types::{
-        DockerImageTag, DockerImageTagRow, IAlert, IAuthStatus, IDockerDeploymentsChanged,
-        IDockerDeploymentsChangedRemoved, IDockerImageTagsChargedImageTagPin,
-        IDockerListImageByHashRes, IDockerListImageTagHistoryRes, IDockerListImageTagsCharged,
-        IDockerListImageTagsRes, IDockerListImageTagsResTag, IGenerateKey, IGenerateKeyRes,
-        IGetObjectHistoryRes, IGetObjectHistoryResHistory, IGetObjectId, IGetObjectIdRes, ILogin,
+        AuditOutcome, DockerImageTag, DockerImageTagRow, IAlert, IAuthStatus, IDeploymentPlanRes,
+        IDockerDeploymentsChanged, IDockerDeploymentsChangedRemoved,
+        IDockerImageTagsChargedImageTagPin, IDockerListImageByHashRes,
+        IDockerListImageTagHistoryRes, IDockerListImageTagsCharged, IDockerListImageTagsRes,
+        IDockerListImageTagsResTag, IDockerStorageUsageRes, IGenerateKey, IGenerateKeyRes,
+        IGetDeploymentHistoryRes, IGetObjectHistoryRes, IGetObjectHistoryResHistory, IGetObjectId,
+        IGetObjectIdRes, IListAuditLogRes, IListDeploymentHistoryRes, ILogin,
         IMessageTextRepAction, IObject2, IObjectChanged, IObjectDigest, ISearchRes,
         ISearchResObject, ISetInitialState, ISetMessagesDismissed, ISetPageAction, ISource,
         ObjectRow, ObjectType,
     },
+    audit::{self, AuditRecord},
     cmpref::CmpRef,
     crt, crypt,
     db::{self, IV},
     deployment,
-    docker::{deploy_service, list_deployment_history, list_deployments, redploy_service},
-    docker_web,
+    docker::{
+        deploy_service, list_deployment_history, list_deployments, prune_preview, redploy_service,
+    },
+    docker_storage, docker_web,
     get_auth::get_auth,
     hostclient::{HostClient, JobHandle},
     modified_files, msg, setup,
@@ -173,6 +178,31 @@ impl WebClient {
         *self.auth.lock().unwrap() = auth;
     }
 
+    /// Record an administrative action performed by this client in the audit log,
+    /// returning the id of the entry
+    async fn audit(
+        &self,
+        state: &State,
+        action: &str,
+        host: Option<&str>,
+        outcome: AuditOutcome,
+        details: Option<String>,
+    ) -> Result<i64> {
+        let user = self.get_auth().user;
+        audit::record(
+            &state.db,
+            AuditRecord {
+                user: user.as_deref(),
+                remote: &self.remote,
+                action,
+                host,
+                outcome,
+                details,
+            },
+        )
+        .await
+    }
+
     async fn close(&self, code: u16) -> Result<()> {
         warn!("Closing connection {} with error code {code}", self.remote);
         if let Ok(Ok(mut sink)) = cancelable(
@@ -313,6 +343,14 @@ impl WebClient {
             })
         };
         if let Some(secs) = rate_limit_secs {
+            audit_login(
+                state,
+                &act.user,
+                &self.remote,
+                AuditOutcome::Denied,
+                "Rate limited",
+            )
+            .await?;
             self.send_message(
                 rt,
                 IServerAction::AuthStatus(IAuthStatus {
@@ -365,6 +403,14 @@ impl WebClient {
             .as_secs() as i64;
 
         if !found {
+            audit_login(
+                state,
+                &act.user,
+                &self.remote,
+                AuditOutcome::Failure,
+                "Invalid user name",
+            )
+            .await?;
             self.send_message(
                 rt,
                 IServerAction::AuthStatus(IAuthStatus {
@@ -385,6 +431,14 @@ impl WebClient {
             let otp_was_submitted = act.otp.as_deref().is_some_and(|s| !s.is_empty());
             if !pwd || (!otp && otp_was_submitted) {
                 record_failed_login_attempt(&state.login_attempts, &self.remote);
+                audit_login(
+                    state,
+                    &act.user,
+                    &self.remote,
+                    AuditOutcome::Failure,
+                    "Invalid password or one time password",
+                )
+                .await?;
             }
             // Hard limit: 5 wrong OTP submissions on the same session resets the
             // pwd bit, forcing the user to re-enter their password.
@@ -492,6 +546,7 @@ impl WebClient {
                 bail!("Internal auth error");
             }
             self.set_auth(auth.clone());
+            audit_login(state, &act.user, &self.remote, AuditOutcome::Success, "").await?;
             self.send_message(rt, IServerAction::AuthStatus(auth))
                 .await?;
         }
@@ -993,12 +1048,31 @@ os.execv(sys.argv[1], sys.argv[1:])
     ) -> Result<()> {
         let ct = RunToken::new();
         let act_id = act.id;
+        let host = act.host.clone();
+        let command = format!("{} {:?}", act.command, act.args);
+        let audit_id = self
+            .audit(
+                state,
+                "RunCommand",
+                Some(&host),
+                AuditOutcome::Started,
+                Some(command.clone()),
+            )
+            .await?;
         self.command_tokens
             .lock()
             .unwrap()
             .insert(act_id, ct.clone());
         let r = self.handle_run_command_inner(state, rt, &ct, act).await;
         self.command_tokens.lock().unwrap().remove(&act_id);
+        let (outcome, details) = match &r {
+            Ok(code) => (
+                AuditOutcome::Success,
+                format!("{command} exited with {code}"),
+            ),
+            Err(e) => (AuditOutcome::Failure, format!("{command} failed: {e:#}")),
+        };
+        audit::finish(&state.db, audit_id, outcome, Some(details)).await?;
         let status = match r {
             Ok(code) => code,
             Err(e) => {
@@ -1125,8 +1199,17 @@ os.execv(sys.argv[1], sys.argv[1:])
             }
             IClientAction::Login(act) => {
                 set_location!(rt);
+                let user = act.user.clone();
                 if let Err(e) = self.handle_login_inner(&rt, state, act).await {
                     error!("Error in handle_login: {e:?}");
+                    audit_login(
+                        state,
+                        &user,
+                        &self.remote,
+                        AuditOutcome::Failure,
+                        "Internal error",
+                    )
+                    .await?;
                     set_location!(rt);
                     self.send_message(
                         &rt,
@@ -1298,7 +1381,16 @@ os.execv(sys.argv[1], sys.argv[1:])
                 )?;
             }
             IClientAction::ResetServerState(act) => {
+                let host = audit::host_name(state, act.host).await?;
                 if !self.get_auth().admin {
+                    self.audit(
+                        state,
+                        "ResetServerState",
+                        Some(&host),
+                        AuditOutcome::Denied,
+                        None,
+                    )
+                    .await?;
                     self.close(403).await?;
                     return Ok(());
                 };
@@ -1307,9 +1399,13 @@ os.execv(sys.argv[1], sys.argv[1:])
                     return Ok(());
                 }
                 set_location!(rt);
-                query!("DELETE FROM `deployments` WHERE `host`=?", act.host)
+                let r = query!("DELETE FROM `deployments` WHERE `host`=?", act.host)
                     .execute(&state.db)
+                    .await;
+                let (outcome, details) = audit::outcome(&r);
+                self.audit(state, "ResetServerState", Some(&host), outcome, details)
                     .await?;
+                r?;
             }
             IClientAction::Search(act) => {
                 if !self.get_auth().admin {
@@ -1617,7 +1713,16 @@ os.execv(sys.argv[1], sys.argv[1:])
                 .context("In send message")?;
             }
             IClientAction::DockerImageSetPin(act) => {
+                let details = format!("image {} pin {}", act.id, act.pin);
                 if !self.get_auth().docker_push {
+                    self.audit(
+                        state,
+                        "DockerImageSetPin",
+                        None,
+                        AuditOutcome::Denied,
+                        Some(details),
+                    )
+                    .await?;
                     self.close(403).await?;
                     return Ok(());
                 };
@@ -1641,6 +1746,14 @@ os.execv(sys.argv[1], sys.argv[1:])
                 )
                 .fetch_all(&state.db)
                 .await?;
+                self.audit(
+                    state,
+                    "DockerImageSetPin",
+                    None,
+                    AuditOutcome::Success,
+                    Some(details),
+                )
+                .await?;
 
                 let mut changed = Vec::new();
                 for row in rows {
@@ -1667,7 +1780,16 @@ os.execv(sys.argv[1], sys.argv[1:])
                 )?;
             }
             IClientAction::DockerImageTagSetPin(act) => {
+                let details = format!("{}:{} pin {}", act.image, act.tag, act.pin);
                 if !self.get_auth().docker_push {
+                    self.audit(
+                        state,
+                        "DockerImageTagSetPin",
+                        None,
+                        AuditOutcome::Denied,
+                        Some(details.clone()),
+                    )
+                    .await?;
                     self.close(403).await?;
                     return Ok(());
                 };
@@ -1693,6 +1815,14 @@ os.execv(sys.argv[1], sys.argv[1:])
                     .execute(&state.db)
                     .await?;
                 }
+                self.audit(
+                    state,
+                    "DockerImageTagSetPin",
+                    None,
+                    AuditOutcome::Success,
+                    Some(details),
+                )
+                .await?;
                 broadcast(
                     state,
                     IServerAction::DockerListImageTagsChanged(IDockerListImageTagsCharged {
@@ -1776,7 +1906,9 @@ os.execv(sys.argv[1], sys.argv[1:])
                 )?;
             }
             IClientAction::ServiceDeployStart(act) => {
-                if !self.get_auth().docker_push {
+                // The project is checked against docker_deploy_projects once it is known
+                if !self.get_auth().docker_push && self.get_auth().docker_deploy_projects.is_empty()
+                {
                     self.close(403).await?;
                     return Ok(());
                 };
@@ -1788,7 +1920,9 @@ os.execv(sys.argv[1], sys.argv[1:])
                 deploy_service(state, self, act).await?;
             }
             IClientAction::ServiceRedeployStart(act) => {
-                if !self.get_auth().docker_push {
+                // The project is checked against docker_deploy_projects once it is known
+                if !self.get_auth().docker_push && self.get_auth().docker_deploy_projects.is_empty()
+                {
                     self.close(403).await?;
                     return Ok(());
                 };
@@ -1815,7 +1949,34 @@ os.execv(sys.argv[1], sys.argv[1:])
                 set_location!(rt);
                 list_deployment_history(&rt, state, self, act).await?;
             }
-            IClientAction::ModifiedFilesScan(_) => {
+            IClientAction::DockerPrunePreview(act) => {
+                let auth = self.get_auth();
+                if !auth.admin && !auth.can_docker_pull_any() {
+                    self.close(403).await?;
+                    return Ok(());
+                };
+                set_location!(rt);
+                prune_preview(&rt, state, self, act).await?;
+            }
+            IClientAction::DockerStorageUsage(act) => {
+                let auth = self.get_auth();
+                if !auth.can_docker_pull_any() {
+                    self.close(403).await?;
+                    return Ok(());
+                };
+                set_location!(rt);
+                let mut projects = docker_storage::usage(state);
+                projects.retain(|p| auth.can_docker_pull(&p.project));
+                self.send_message(
+                    &rt,
+                    IServerAction::DockerStorageUsageRes(IDockerStorageUsageRes {
+                        r#ref: act.r#ref,
+                        projects,
+                    }),
+                )
+                .await?;
+            }
+            IClientAction::ModifiedFilesScan(act) => {
                 if !self.get_auth().admin {
                     self.close(403).await?;
                     return Ok(());
@@ -1825,7 +1986,7 @@ os.execv(sys.argv[1], sys.argv[1:])
                     return Ok(());
                 }
                 set_location!(rt);
-                modified_files::scan(state).await?;
+                modified_files::scan(state, act.host).await?;
             }
             IClientAction::ModifiedFilesList(act) => {
                 if !self.get_auth().admin {
@@ -1863,6 +2024,30 @@ os.execv(sys.argv[1], sys.argv[1:])
                     alert_error(&rt, state, e, "Deployment::deployObject", Some(self)).await?;
                 }
             }
+            IClientAction::DeploymentPlan(act) => {
+                if !self.get_auth().admin {
+                    self.close(403).await?;
+                    return Ok(());
+                };
+                set_location!(rt);
+                let (objects, error) = match deployment::plan(state, act.id, act.redeploy).await {
+                    Ok(v) => (v, None),
+                    Err(e) => {
+                        error!("Error in deployment plan: {e:?}");
+                        (Vec::new(), Some(format!("{e:?}")))
+                    }
+                };
+                set_location!(rt);
+                self.send_message(
+                    &rt,
+                    IServerAction::DeploymentPlanRes(IDeploymentPlanRes {
+                        r#ref: act.r#ref,
+                        objects,
+                        error,
+                    }),
+                )
+                .await?;
+            }
             IClientAction::CancelDeployment(_) => {
                 if !self.get_auth().admin {
                     self.close(403).await?;
@@ -1875,6 +2060,63 @@ os.execv(sys.argv[1], sys.argv[1:])
                 set_location!(rt);
                 deployment::cancel(state).await?;
             }
+            IClientAction::ListDeploymentHistory(act) => {
+                if !self.get_auth().admin {
+                    self.close(403).await?;
+                    return Ok(());
+                };
+                set_location!(rt);
+                let deployments = deployment::list_history(&state.db, act.host, act.limit).await?;
+                set_location!(rt);
+                self.send_message(
+                    &rt,
+                    IServerAction::ListDeploymentHistoryRes(IListDeploymentHistoryRes {
+                        r#ref: act.r#ref,
+                        deployments,
+                    }),
+                )
+                .await?;
+            }
+            IClientAction::ListAuditLog(act) => {
+                if !self.get_auth().admin {
+                    self.close(403).await?;
+                    return Ok(());
+                };
+                set_location!(rt);
+                let entries = audit::list(&state.db, act.user, act.since, act.limit).await?;
+                set_location!(rt);
+                self.send_message(
+                    &rt,
+                    IServerAction::ListAuditLogRes(IListAuditLogRes {
+                        r#ref: act.r#ref,
+                        entries,
+                    }),
+                )
+                .await?;
+            }
+            IClientAction::GetDeploymentHistory(act) => {
+                if !self.get_auth().admin {
+                    self.close(403).await?;
+                    return Ok(());
+                };
+                set_location!(rt);
+                let (deployment, objects, log) =
+                    match deployment::get_history(&state.db, act.id).await? {
+                        Some((deployment, objects, log)) => (Some(deployment), objects, log),
+                        None => (None, Vec::new(), String::new()),
+                    };
+                set_location!(rt);
+                self.send_message(
+                    &rt,
+                    IServerAction::GetDeploymentHistoryRes(IGetDeploymentHistoryRes {
+                        r#ref: act.r#ref,
+                        deployment,
+                        objects,
+                        log,
+                    }),
+                )
+                .await?;
+            }
             IClientAction::StartDeployment(_) => {
                 if !self.get_auth().admin {
                     self.close(403).await?;
@@ -1885,7 +2127,7 @@ os.execv(sys.argv[1], sys.argv[1:])
                     return Ok(());
                 }
                 set_location!(rt);
-                if let Err(e) = deployment::start(&rt, state).await {
+                if let Err(e) = deployment::start(&rt, state, self.get_auth().user).await {
                     alert_error(&rt, state, e, "Deployment::start", Some(self)).await?;
                 }
             }
@@ -1899,7 +2141,7 @@ os.execv(sys.argv[1], sys.argv[1:])
                     return Ok(());
                 }
                 set_location!(rt);
-                if let Err(e) = deployment::mark_deployed(&rt, state).await {
+                if let Err(e) = deployment::mark_deployed(&rt, state, self.get_auth().user).await {
                     alert_error(&rt, state, e, "Deployment::mark_deployed", Some(self)).await?;
                 }
             }
@@ -1939,6 +2181,14 @@ os.execv(sys.argv[1], sys.argv[1:])
             }
             IClientAction::RunCommand(act) => {
                 if !self.get_auth().admin {
+                    self.audit(
+                        state,
+                        "RunCommand",
+                        Some(&act.host),
+                        AuditOutcome::Denied,
+                        Some(act.command.clone()),
+                    )
+                    .await?;
                     self.close(403).await?;
                     return Ok(());
                 };
@@ -1965,6 +2215,14 @@ os.execv(sys.argv[1], sys.argv[1:])
             }
             IClientAction::GetSecret(act) => {
                 if !self.get_auth().admin {
+                    self.audit(
+                        state,
+                        "GetSecret",
+                        act.host.as_deref(),
+                        AuditOutcome::Denied,
+                        Some(act.name.clone()),
+                    )
+                    .await?;
                     self.close(403).await?;
                     return Ok(());
                 };
@@ -1991,6 +2249,19 @@ os.execv(sys.argv[1], sys.argv[1:])
                 };
 
                 let value = vars.get(&*act.name).map(|v| v.to_string());
+                let outcome = if value.is_some() {
+                    AuditOutcome::Success
+                } else {
+                    AuditOutcome::Failure
+                };
+                self.audit(
+                    state,
+                    "GetSecret",
+                    act.host.as_deref(),
+                    outcome,
+                    Some(act.name.clone()),
+                )
+                .await?;
                 self.send_message(
                     &rt,
                     IServerAction::GetSecretRes(IGetSecretRes {
@@ -2003,6 +2274,14 @@ os.execv(sys.argv[1], sys.argv[1:])
             }
             IClientAction::SocketConnect(act) => {
                 if !self.get_auth().admin {
+                    self.audit(
+                        state,
+                        "SocketConnect",
+                        Some(&act.host),
+                        AuditOutcome::Denied,
+                        Some(act.dst.clone()),
+                    )
+                    .await?;
                     self.close(403).await?;
                     return Ok(());
                 };
@@ -2011,7 +2290,24 @@ os.execv(sys.argv[1], sys.argv[1:])
                     return Ok(());
                 }
                 let msg_id = act.msg_id;
+                let host = act.host.clone();
+                let dst = act.dst.clone();
+                let audit_id = self
+                    .audit(
+                        state,
+                        "SocketConnect",
+                        Some(&host),
+                        AuditOutcome::Started,
+                        Some(dst.clone()),
+                    )
+                    .await?;
                 let r = self.handle_socket_connect(&rt, state, act).await;
+                let (outcome, error) = audit::outcome(&r);
+                let details = match error {
+                    Some(e) => format!("{dst}: {e}"),
+                    None => dst,
+                };
+                audit::finish(&state.db, audit_id, outcome, Some(details)).await?;
                 self.send_response(&rt, msg_id, r).await?;
             }
             IClientAction::SocketClose(act) => {
@@ -2042,6 +2338,14 @@ os.execv(sys.argv[1], sys.argv[1:])
             }
             IClientAction::CommandSpawn(act) => {
                 if !self.get_auth().admin {
+                    self.audit(
+                        state,
+                        "CommandSpawn",
+                        Some(&act.host),
+                        AuditOutcome::Denied,
+                        Some(act.program.clone()),
+                    )
+                    .await?;
                     self.close(403).await?;
                     return Ok(());
                 };
@@ -2050,7 +2354,24 @@ os.execv(sys.argv[1], sys.argv[1:])
                     return Ok(());
                 }
                 let msg_id = act.msg_id;
+                let host = act.host.clone();
+                let command = format!("{} {:?}", act.program, act.args);
+                let audit_id = self
+                    .audit(
+                        state,
+                        "CommandSpawn",
+                        Some(&host),
+                        AuditOutcome::Started,
+                        Some(command.clone()),
+                    )
+                    .await?;
                 let r = self.handle_command_spawn(&rt, state, act).await;
+                let (outcome, error) = audit::outcome(&r);
+                let details = match error {
+                    Some(e) => format!("{command}: {e}"),
+                    None => command,
+                };
+                audit::finish(&state.db, audit_id, outcome, Some(details)).await?;
                 self.send_response(&rt, msg_id, r).await?;
             }
             IClientAction::CommandSignal(act) => {
@@ -2141,6 +2462,29 @@ os.execv(sys.argv[1], sys.argv[1:])
     }
 }
 
+/// Record a login attempt in the audit log
+async fn audit_login(
+    state: &State,
+    user: &str,
+    remote: &str,
+    outcome: AuditOutcome,
+    details: &str,
+) -> Result<()> {
+    audit::record(
+        &state.db,
+        AuditRecord {
+            user: Some(user),
+            remote,
+            action: "Login",
+            host: None,
+            outcome,
+            details: (!details.is_empty()).then(|| details.to_string()),
+        },
+    )
+    .await?;
+    Ok(())
+}
+
 /// Record a failed login attempt from `ip`, applying exponential backoff.
 /// The delay starts at 1 s and doubles on each subsequent failure up to 300 s.
 fn record_failed_login_attempt(login_attempts: &Mutex<HashMap<String, LoginAttempts>>, ip: &str) {
@@ -2281,11 +2625,21 @@ async fn status_handler(
 
 async fn metrics_handler(WState(state): WState<Arc<State>>) -> Result<Response, WebError> {
     let v = msg::get_count(&state).await?;
-    Ok((
-        [("Content-Type", "text/plain; version=0.0.4")],
-        format!("simpleadmin_messages {v}\n"),
-    )
-        .into_response())
+    let mut res = format!("simpleadmin_messages {v}\n");
+    for p in docker_storage::usage(&state) {
+        res.push_str(&format!(
+            "simpleadmin_docker_storage_bytes{{project=\"{}\",kind=\"unique\"}} {}\n\
+            simpleadmin_docker_storage_bytes{{project=\"{}\",kind=\"shared\"}} {}\n",
+            p.project, p.unique_bytes, p.project, p.shared_bytes
+        ));
+        if let Some(quota) = p.quota_bytes {
+            res.push_str(&format!(
+                "simpleadmin_docker_storage_quota_bytes{{project=\"{}\"}} {quota}\n",
+                p.project
+            ));
+        }
+    }
+    Ok(([("Content-Type", "text/plain; version=0.0.4")], res).into_response())
 }
 
 #[derive(Serialize)]
@@ -271,6 +271,11 @@ pub enum ClientHostMessage {
         code: i32,
         signal: Option<i32>,
     },
+    /// A service was restarted too often and has been given up on
+    ServiceFailed {
+        name: String,
+        message: String,
+    },
 }
 
 impl ClientHostMessage {
@@ -285,6 +290,7 @@ impl ClientHostMessage {
             ClientHostMessage::CommandStdout { .. } => None,
             ClientHostMessage::CommandStderr { .. } => None,
             ClientHostMessage::CommandFinished { .. } => None,
+            ClientHostMessage::ServiceFailed { .. } => None,
         }
     }
 
@@ -300,6 +306,7 @@ impl ClientHostMessage {
             ClientHostMessage::CommandStdout { .. } => "command_stdout",
             ClientHostMessage::CommandStderr { .. } => "command_stderr",
             ClientHostMessage::CommandFinished { .. } => "command_finished",
+            ClientHostMessage::ServiceFailed { .. } => "service_failed",
         }
     }
 }
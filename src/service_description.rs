@@ -284,6 +284,119 @@ pub enum ServiceMetrics {
     },
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct IoLimit {
+    /// The block device to limit, for instance /dev/sda
+    pub device: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_bps: Option<Size>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_bps: Option<Size>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_iops: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_iops: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum HealthProbe {
+    /// Run a command in the container of the service, or as the service user
+    /// for services not running in a container. The probe passes if it exits with 0
+    Exec { command: Vec<String> },
+    /// GET a path on a local port. The probe passes on a 2xx response
+    Http {
+        port: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+    },
+    /// Connect to a local port
+    Tcp { port: u16 },
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HealthCheck {
+    pub probe: HealthProbe,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<Duration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<Duration>,
+    /// Number of consecutive failed probes before the service is considered unhealthy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_threshold: Option<u32>,
+}
+
+impl HealthCheck {
+    pub fn get_interval(&self) -> Duration {
+        self.interval.unwrap_or(Duration::S(10.0))
+    }
+
+    pub fn get_timeout(&self) -> Duration {
+        self.timeout.unwrap_or(Duration::S(5.0))
+    }
+
+    pub fn get_failure_threshold(&self) -> u32 {
+        self.failure_threshold.unwrap_or(3)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Restart the service whenever it exits
+    #[default]
+    Always,
+    /// Restart the service only when it exits with a non zero code, is killed or fails to start
+    OnFailure,
+    /// Leave the service stopped when it exits
+    Never,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceRestart {
+    #[serde(default)]
+    pub policy: RestartPolicy,
+    /// Delay before the first restart, doubled for every consecutive failure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backoff_base: Option<Duration>,
+    /// Upper bound for the restart delay
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backoff_max: Option<Duration>,
+    /// Give up and mark the service as failed if it is restarted more than this
+    /// many times within `restart_window`. When not set the service is restarted
+    /// forever, waiting at most `backoff_max` between restarts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_restarts: Option<u32>,
+    /// The window in which restarts are counted for `max_restarts`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart_window: Option<Duration>,
+}
+
+impl ServiceRestart {
+    pub fn get_backoff_base(&self) -> Duration {
+        self.backoff_base.unwrap_or(Duration::S(1.0))
+    }
+
+    pub fn get_backoff_max(&self) -> Duration {
+        self.backoff_max.unwrap_or(Duration::M(5.0))
+    }
+
+    pub fn get_restart_window(&self) -> Duration {
+        self.restart_window.unwrap_or(Duration::M(10.0))
+    }
+
+    /// The delay before restarting after `failures` consecutive failures
+    pub fn backoff(&self, failures: u32) -> std::time::Duration {
+        let base: std::time::Duration = self.get_backoff_base().into();
+        let max: std::time::Duration = self.get_backoff_max().into();
+        base.saturating_mul(1 << failures.saturating_sub(1).min(31))
+            .min(max)
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 #[serde(untagged)]
 pub enum Subcert {
@@ -316,6 +429,22 @@ pub struct ServiceDescription {
     pub post_start: Vec<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub max_memory: Option<Size>,
+    /// Throttle the service when its memory use goes above this (memory.high)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_soft_limit: Option<Size>,
+    /// The number of cpus the service may use, for instance 0.5 or 2
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_quota: Option<f64>,
+    /// Relative cpu weight between 1 and 10000, the default is 100
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_weight: Option<u64>,
+    /// Relative io weight between 1 and 10000, the default is 100
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub io_weight: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub io_limits: Vec<IoLimit>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pids_max: Option<u64>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub extract_files: Vec<ExtractFile>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -342,12 +471,20 @@ pub struct ServiceDescription {
     pub pod_env: HashMap<String, Option<String>>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub overlap_stop_signal: Option<Signal>,
+    /// When overlapping, keep the old instance running until the new one has been
+    /// running and passing its health checks for this long
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overlap_soak: Option<Duration>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub stop_signal: Option<Signal>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub start_magic: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub metrics: Option<ServiceMetrics>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart: Option<ServiceRestart>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheck>,
 }
 
 impl ServiceDescription {
@@ -358,11 +495,17 @@ impl ServiceDescription {
     pub fn get_stop_signal(&self) -> Signal {
         self.stop_signal.unwrap_or(Signal::Term)
     }
+
+    pub fn get_restart(&self) -> ServiceRestart {
+        self.restart.clone().unwrap_or_default()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::service_description::{ServiceDescription, Subcert};
+    use crate::service_description::{
+        HealthCheck, HealthProbe, RestartPolicy, ServiceDescription, Subcert,
+    };
 
     #[test]
     fn service_description() {
@@ -405,5 +548,72 @@ pod_env:
         assert_eq!(sd.pod_env["a"], None);
         assert_eq!(sd.pod_env["b"].as_deref(), Some("null"));
         assert_eq!(sd.pod_env["c"].as_deref(), Some("none"));
+
+        let sd: ServiceDescription = serde_yaml::from_str(
+            "
+name: Hat
+service_type: plain
+restart:
+  policy: on-failure
+  backoff_base: 2s
+  backoff_max: 1m
+        ",
+        )
+        .unwrap();
+        let restart = sd.get_restart();
+        assert_eq!(restart.policy, RestartPolicy::OnFailure);
+        assert_eq!(restart.backoff(1), std::time::Duration::from_secs(2));
+        assert_eq!(restart.backoff(3), std::time::Duration::from_secs(8));
+        assert_eq!(restart.backoff(20), std::time::Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_health_probe() {
+        let check: HealthCheck = serde_yaml::from_str(
+            "
+probe:
+  type: exec
+  command: [pg_isready, -q]
+interval: 30s
+failure_threshold: 5
+        ",
+        )
+        .unwrap();
+        assert!(
+            matches!(&check.probe, HealthProbe::Exec { command } if command == &["pg_isready", "-q"])
+        );
+        assert_eq!(
+            std::time::Duration::from(check.get_interval()),
+            std::time::Duration::from_secs(30)
+        );
+        assert_eq!(
+            std::time::Duration::from(check.get_timeout()),
+            std::time::Duration::from_secs(5)
+        );
+        assert_eq!(check.get_failure_threshold(), 5);
+
+        let check: HealthCheck =
+            serde_yaml::from_str("probe: {type: http, port: 8080, path: /health}").unwrap();
+        assert!(matches!(
+            &check.probe,
+            HealthProbe::Http { port: 8080, path: Some(p) } if p == "/health"
+        ));
+        assert_eq!(check.get_failure_threshold(), 3);
+        let check: HealthCheck = serde_yaml::from_str("probe: {type: http, port: 8080}").unwrap();
+        assert!(matches!(
+            check.probe,
+            HealthProbe::Http {
+                port: 8080,
+                path: None
+            }
+        ));
+        let check: HealthCheck = serde_yaml::from_str("probe: {type: tcp, port: 22}").unwrap();
+        assert!(matches!(check.probe, HealthProbe::Tcp { port: 22 }));
+
+        assert!(serde_yaml::from_str::<HealthCheck>("probe: {type: udp, port: 53}").is_err());
+        assert!(serde_yaml::from_str::<HealthCheck>("probe: {type: tcp}").is_err());
+        assert!(
+            serde_yaml::from_str::<HealthCheck>("probe: {type: tcp, port: 22, path: /}").is_err()
+        );
     }
 }
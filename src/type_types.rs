@@ -270,6 +270,18 @@ pub enum KindType {
     Monitor, // Deprecated
 }
 
+// What to do when a deployed file is found to be modified on the host
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, TS)]
+#[serde(rename_all = "camelCase")]
+pub enum DriftPolicy {
+    // Only report the modified file
+    Alert,
+    // Write the deployed content back to the file
+    Redeploy,
+    // Make the content of the file the current content of the object
+    Adopt,
+}
+
 #[derive(Serialize, Deserialize, Debug, Default, Clone, TS)]
 #[serde(rename_all = "camelCase")]
 pub struct IType {
@@ -312,6 +324,9 @@ pub struct IType {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     #[ts(optional)]
     pub name_variable: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub drift_policy: Option<DriftPolicy>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, TS)]
@@ -519,6 +534,7 @@ pub fn export_ts(config: &Config) -> Vec<String> {
         ITypeContentTypeProp::export_to_string(config).unwrap(),
         ITypeProp::export_to_string(config).unwrap(),
         KindType::export_to_string(config).unwrap(),
+        DriftPolicy::export_to_string(config).unwrap(),
         IType::export_to_string(config).unwrap(),
         IVariable::export_to_string(config).unwrap(),
     ]